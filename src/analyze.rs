@@ -0,0 +1,548 @@
+use std::fmt;
+
+use super::estimate::{self, Estimate};
+use super::gcode::Vec4;
+use super::profile::Profile;
+use super::toolpath::{Command, Heater};
+
+// Moves outside the build volume listed in the report, the others are only counted
+const MAX_LISTED: usize = 5;
+// Feedrate firmwares start with, in mm/min
+const DEFAULT_FEEDRATE: f64 = 1500.0;
+// Layer heights closer than this are the same layer, in millimeters
+const LAYER_PRECISION: f64 = 1e-4;
+
+// A letter and its number, like `X12.5`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Word {
+    pub letter: char,
+    pub value: f64,
+}
+
+// Words of a line, comments removed. Parenthesized and `;` comments are
+// supported, as are words without spaces in between like `G1X10Y5`. The line
+// number and checksum hosts add, as in `N1 G1 X10*33`, are dropped.
+pub fn parse_line(line: &str) -> Vec<Word> {
+    let mut code = String::with_capacity(line.len());
+    let mut depth = 0;
+
+    for c in line.chars() {
+        match c {
+            ';' | '*' if depth == 0 => break,
+            '(' => depth += 1,
+            ')' if depth > 0 => depth -= 1,
+            c if depth == 0 => code.push(c),
+            _ => (),
+        }
+    }
+
+    let mut words = vec![];
+    let mut chars = code.chars().filter(|c| !c.is_whitespace()).peekable();
+
+    while let Some(c) = chars.next() {
+        if !c.is_ascii_alphabetic() {
+            continue;
+        }
+
+        let mut number = String::new();
+
+        while let Some(&next) = chars.peek() {
+            if next.is_ascii_digit() || next == '.' || next == '-' || next == '+' {
+                number.push(next);
+                chars.next();
+            } else {
+                break;
+            }
+        }
+
+        // Letters without a number, like the axes of `G28 X Y`
+        words.push(Word {
+            letter: c.to_ascii_uppercase(),
+            value: number.parse().unwrap_or(f64::NAN),
+        });
+    }
+
+    if words.first().map(|word| word.letter) == Some('N') {
+        words.remove(0);
+    }
+
+    words
+}
+
+pub fn value(words: &[Word], letter: char) -> Option<f64> {
+    words
+        .iter()
+        .find(|word| word.letter == letter)
+        .map(|word| word.value)
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Bounds {
+    pub min: [f64; 3],
+    pub max: [f64; 3],
+}
+
+impl Bounds {
+    fn empty() -> Self {
+        Bounds {
+            min: [f64::INFINITY; 3],
+            max: [f64::NEG_INFINITY; 3],
+        }
+    }
+
+    fn add(&mut self, p: [f64; 3]) {
+        for (axis, value) in p.iter().enumerate() {
+            self.min[axis] = self.min[axis].min(*value);
+            self.max[axis] = self.max[axis].max(*value);
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.min[0] > self.max[0]
+    }
+}
+
+impl fmt::Display for Bounds {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_empty() {
+            return write!(f, "none");
+        }
+
+        write!(
+            f,
+            "X {:.3}..{:.3} Y {:.3}..{:.3} Z {:.3}..{:.3}",
+            self.min[0], self.max[0], self.min[1], self.max[1], self.min[2], self.max[2]
+        )
+    }
+}
+
+// A move ending outside the build volume
+#[derive(Debug, Clone)]
+pub struct Violation {
+    pub line: usize,
+    pub text: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Analysis {
+    pub lines: usize,
+    pub moves: usize,
+    // All the moves, and the extruding ones only
+    pub bounds: Bounds,
+    pub extruded_bounds: Bounds,
+    // `;LAYER:` markers, or without them extrusions starting above the
+    // previous layer
+    pub layers: usize,
+    // Filament pushed, not counting what undoes retractions, and pulled back
+    // by retractions, in millimeters
+    pub extruded: f64,
+    pub retracted: f64,
+    pub retractions: usize,
+    // Length of the extruding and non extruding moves, in millimeters
+    pub extruding_distance: f64,
+    pub travel_distance: f64,
+    // Time spent in G4 dwells, in seconds
+    pub dwell: f64,
+    pub estimate: Estimate,
+    pub build_volume: [f64; 3],
+    pub outside: Vec<Violation>,
+    pub outside_count: usize,
+}
+
+// Parser state carried from one line to the next
+struct Machine {
+    // Logical position, as written in the G-code
+    pos: [f64; 4],
+    // Added to logical positions to keep simulated ones continuous across G92
+    shift: [f64; 4],
+    relative: bool,
+    relative_e: bool,
+    // Millimeters per unit, 25.4 after G20
+    unit: f64,
+}
+
+impl Machine {
+    fn simulated(&self) -> Vec4 {
+        Vec4 {
+            x: self.pos[0] + self.shift[0],
+            y: self.pos[1] + self.shift[1],
+            z: self.pos[2] + self.shift[2],
+            e: self.pos[3] + self.shift[3],
+        }
+    }
+
+    // Target of a move, each axis absolute or relative as the modes say
+    fn target(&self, words: &[Word]) -> [f64; 4] {
+        let mut target = self.pos;
+
+        for (axis, letter) in ['X', 'Y', 'Z', 'E'].iter().enumerate() {
+            if let Some(v) = value(words, *letter).filter(|v| v.is_finite()) {
+                let relative = if axis == 3 {
+                    self.relative || self.relative_e
+                } else {
+                    self.relative
+                };
+
+                target[axis] = if relative {
+                    target[axis] + v * self.unit
+                } else {
+                    v * self.unit
+                };
+            }
+        }
+
+        target
+    }
+}
+
+fn arc_length(from: [f64; 4], to: [f64; 4], center: (f64, f64), clockwise: bool) -> f64 {
+    let (cx, cy) = (from[0] + center.0, from[1] + center.1);
+    let radius = (from[0] - cx).hypot(from[1] - cy);
+    let start = (from[1] - cy).atan2(from[0] - cx);
+    let end = (to[1] - cy).atan2(to[0] - cx);
+    let mut sweep = if clockwise { start - end } else { end - start };
+
+    if sweep <= 0.0 {
+        sweep += 2.0 * std::f64::consts::PI;
+    }
+
+    (radius * sweep).hypot(to[2] - from[2])
+}
+
+// Parse G-code text, replaying it on the machine of the profile
+pub fn analyze(text: &str, profile: &Profile) -> Analysis {
+    let mut machine = Machine {
+        pos: [0.0; 4],
+        shift: [0.0; 4],
+        relative: false,
+        relative_e: false,
+        unit: 1.0,
+    };
+    let mut commands = vec![];
+    let mut markers = 0;
+    // Highest extrusion so far, and filament retracted but not pushed back yet
+    let mut layer_z = f64::NEG_INFINITY;
+    let mut pending = 0.0;
+    let mut analysis = Analysis {
+        lines: 0,
+        moves: 0,
+        bounds: Bounds::empty(),
+        extruded_bounds: Bounds::empty(),
+        layers: 0,
+        extruded: 0.0,
+        retracted: 0.0,
+        retractions: 0,
+        extruding_distance: 0.0,
+        travel_distance: 0.0,
+        dwell: 0.0,
+        estimate: Estimate::default(),
+        build_volume: profile.build_volume,
+        outside: vec![],
+        outside_count: 0,
+    };
+
+    for (number, line) in text.lines().enumerate() {
+        analysis.lines += 1;
+
+        if line.trim_start().starts_with(";LAYER:") {
+            markers += 1;
+        }
+
+        let words = parse_line(line);
+        let command = match words.first() {
+            // Subcodes like G29.1 are not understood
+            Some(word) if "GM".contains(word.letter) && word.value.fract() == 0.0 => {
+                (word.letter, word.value as u32)
+            }
+            _ => continue,
+        };
+        let words = &words[1..];
+
+        if let Some(f) = value(words, 'F').filter(|f| *f > 0.0) {
+            commands.push(Command::Feedrate(f * machine.unit));
+        }
+
+        match command {
+            ('G', g @ 0..=3) => {
+                let from = machine.pos;
+                let to = machine.target(words);
+                let arc = if g >= 2 {
+                    let i = value(words, 'I').unwrap_or(0.0) * machine.unit;
+                    let j = value(words, 'J').unwrap_or(0.0) * machine.unit;
+
+                    Some(((i, j), g == 2))
+                } else {
+                    None
+                };
+                let distance = match arc {
+                    Some((center, clockwise)) => arc_length(from, to, center, clockwise),
+                    None => ((to[0] - from[0]).powi(2)
+                        + (to[1] - from[1]).powi(2)
+                        + (to[2] - from[2]).powi(2))
+                    .sqrt(),
+                };
+                let e = to[3] - from[3];
+
+                machine.pos = to;
+
+                if e > 0.0 {
+                    let unretract = e.min(pending);
+
+                    pending -= unretract;
+                    analysis.extruded += e - unretract;
+                } else if e < 0.0 {
+                    pending -= e;
+                    analysis.retracted -= e;
+                    analysis.retractions += 1;
+                }
+
+                if distance == 0.0 {
+                    if e != 0.0 {
+                        commands.push(Command::Move(machine.simulated()));
+                    }
+
+                    continue;
+                }
+
+                analysis.moves += 1;
+
+                let end = [to[0], to[1], to[2]];
+
+                analysis.bounds.add(end);
+
+                if e > 0.0 {
+                    analysis.extruding_distance += distance;
+                    analysis.extruded_bounds.add(end);
+
+                    // Spirals climb while extruding, only Z changes in
+                    // between extrusions start layers
+                    if from[2] > layer_z + LAYER_PRECISION {
+                        analysis.layers += 1;
+                    }
+
+                    layer_z = layer_z.max(to[2]);
+                } else {
+                    analysis.travel_distance += distance;
+                }
+
+                let outside = (0..3).any(|axis| {
+                    end[axis] < -LAYER_PRECISION
+                        || end[axis] > profile.build_volume[axis] + LAYER_PRECISION
+                });
+
+                if outside {
+                    analysis.outside_count += 1;
+
+                    if analysis.outside.len() < MAX_LISTED {
+                        analysis.outside.push(Violation {
+                            line: number + 1,
+                            text: line.trim().to_string(),
+                        });
+                    }
+                }
+
+                commands.push(match arc {
+                    Some((center, clockwise)) => Command::Arc {
+                        to: machine.simulated(),
+                        center,
+                        clockwise,
+                    },
+                    None => Command::Move(machine.simulated()),
+                });
+            }
+            ('G', 4) => {
+                analysis.dwell += value(words, 'P').map(|p| p / 1000.0).unwrap_or(0.0)
+                    + value(words, 'S').unwrap_or(0.0);
+            }
+            ('G', 20) => machine.unit = 25.4,
+            ('G', 21) => machine.unit = 1.0,
+            ('G', 28) => {
+                let all = !words.iter().any(|w| "XYZ".contains(w.letter));
+
+                for (axis, letter) in ['X', 'Y', 'Z'].iter().enumerate() {
+                    if all || value(words, *letter).is_some() {
+                        machine.pos[axis] = 0.0;
+                        machine.shift[axis] = 0.0;
+                    }
+                }
+
+                commands.push(Command::Move(machine.simulated()));
+            }
+            ('G', 90) => machine.relative = false,
+            ('G', 91) => machine.relative = true,
+            ('G', 92) => {
+                let all = words.is_empty();
+
+                for (axis, letter) in ['X', 'Y', 'Z', 'E'].iter().enumerate() {
+                    let set = match value(words, *letter) {
+                        Some(v) if v.is_finite() => Some(v * machine.unit),
+                        _ if all => Some(0.0),
+                        _ => None,
+                    };
+
+                    if let Some(v) = set {
+                        machine.shift[axis] += machine.pos[axis] - v;
+                        machine.pos[axis] = v;
+                    }
+                }
+            }
+            ('M', 82) => machine.relative_e = false,
+            ('M', 83) => machine.relative_e = true,
+            ('M', m @ (109 | 190)) => commands.push(Command::Temperature {
+                heater: if m == 109 {
+                    Heater::Nozzle
+                } else {
+                    Heater::Bed
+                },
+                celsius: value(words, 'S')
+                    .or_else(|| value(words, 'R'))
+                    .unwrap_or(0.0),
+                wait: true,
+            }),
+            _ => (),
+        }
+    }
+
+    let origin = Vec4 {
+        x: 0.0,
+        y: 0.0,
+        z: 0.0,
+        e: 0.0,
+    };
+
+    if markers > 0 {
+        analysis.layers = markers;
+    }
+
+    analysis.estimate = estimate::simulate(&commands, &origin, DEFAULT_FEEDRATE, profile);
+    analysis.estimate.time += analysis.dwell;
+    analysis
+}
+
+impl fmt::Display for Analysis {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Lines: {} ({} moves)", self.lines, self.moves)?;
+        writeln!(f, "Bounding box: {}", self.bounds)?;
+        writeln!(f, "Printed bounding box: {}", self.extruded_bounds)?;
+        writeln!(f, "Layers: {}", self.layers)?;
+        writeln!(
+            f,
+            "Filament: {:.3}mm extruded, {} retractions ({:.3}mm)",
+            self.extruded, self.retractions, self.retracted
+        )?;
+        writeln!(
+            f,
+            "Filament volume: {:.2}cm3, weight: {:.2}g",
+            self.estimate.filament_volume / 1000.0,
+            self.estimate.filament_weight
+        )?;
+        writeln!(f, "Extruding moves: {:.3}mm", self.extruding_distance)?;
+        writeln!(f, "Travel moves: {:.3}mm", self.travel_distance)?;
+        writeln!(
+            f,
+            "Estimated time: {}",
+            estimate::duration(self.estimate.time)
+        )?;
+
+        let [width, depth, height] = self.build_volume;
+
+        if self.outside_count == 0 {
+            return writeln!(
+                f,
+                "All moves fit the {}x{}x{} build volume",
+                width, depth, height
+            );
+        }
+
+        writeln!(
+            f,
+            "{} moves outside the {}x{}x{} build volume:",
+            self.outside_count, width, depth, height
+        )?;
+
+        for violation in self.outside.iter() {
+            writeln!(f, "  line {}: {}", violation.line, violation.text)?;
+        }
+
+        if self.outside_count > self.outside.len() {
+            writeln!(f, "  ...")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::profile::Profile;
+    use super::{analyze, parse_line};
+
+    const CORNERS: [(f64, f64); 4] = [(10.0, 0.0), (10.0, 10.0), (0.0, 10.0), (0.0, 0.0)];
+
+    // Two flat layers 10mm wide with a retraction in between, then a spiral
+    // climbing 0.2mm per turn for three turns, each side pushing 0.5mm
+    fn spiral(markers: bool) -> String {
+        let mut lines = vec!["G90".to_string(), "M82".to_string(), "G92 E0".to_string()];
+        let mut e = 0.0;
+
+        for (layer, z) in [0.2, 0.4].iter().enumerate() {
+            if markers {
+                lines.push(format!(";LAYER:{}", layer + 1));
+            }
+
+            lines.push(format!("G0 X0 Y0 Z{}", z));
+
+            if layer > 0 {
+                lines.push(format!("G1 E{}", e));
+            }
+
+            for (x, y) in CORNERS.iter() {
+                e += 0.5;
+                lines.push(format!("G1 X{} Y{} E{}", x, y, e));
+            }
+
+            lines.push(format!("G1 E{}", e - 1.0));
+        }
+
+        lines.push(format!("G1 E{}", e));
+
+        for turn in 0..3 {
+            if markers {
+                lines.push(format!(";LAYER:{}", turn + 3));
+            }
+
+            for (side, (x, y)) in CORNERS.iter().enumerate() {
+                let z = 0.4 + 0.2 * (turn as f64 + (side + 1) as f64 / 4.0);
+
+                e += 0.5;
+                lines.push(format!("G1 X{} Y{} Z{} E{}", x, y, z, e));
+            }
+        }
+
+        lines.join("\n")
+    }
+
+    #[test]
+    fn spiral_vase() {
+        let profile = Profile::default();
+        let analysis = analyze(&spiral(false), &profile);
+
+        // Without markers, the spiral goes on from the last flat layer
+        assert_eq!(analysis.layers, 2);
+        assert!((analysis.extruded - 10.0).abs() < 1e-9);
+        assert!((analysis.retracted - 2.0).abs() < 1e-9);
+        assert_eq!(analysis.retractions, 2);
+
+        assert_eq!(analyze(&spiral(true), &profile).layers, 5);
+    }
+
+    #[test]
+    fn line_numbers_and_checksums() {
+        let words: Vec<(char, f64)> = parse_line("N12 G1 X10 Y5.5 E1*33")
+            .iter()
+            .map(|word| (word.letter, word.value))
+            .collect();
+
+        assert_eq!(words, vec![('G', 1.0), ('X', 10.0), ('Y', 5.5), ('E', 1.0)]);
+    }
+}
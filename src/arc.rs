@@ -0,0 +1,223 @@
+use std::f64::consts::PI;
+
+use super::gcode::Vec4;
+use super::math::equal_float;
+use super::toolpath::Command;
+
+// Fewer segments than this are not worth an arc
+const MIN_SEGMENTS: usize = 3;
+// Flatter curves are left as straight lines
+const MAX_RADIUS: f64 = 1000.0;
+
+fn turn(a: &Vec4, b: &Vec4, c: &Vec4) -> f64 {
+    (b.x - a.x) * (c.y - b.y) - (b.y - a.y) * (c.x - b.x)
+}
+
+fn distance(a: (f64, f64), b: (f64, f64)) -> f64 {
+    ((a.0 - b.0).powi(2) + (a.1 - b.1).powi(2)).sqrt()
+}
+
+// Center and radius of the circle going through three points
+fn circle(a: &Vec4, b: &Vec4, c: &Vec4) -> Option<((f64, f64), f64)> {
+    let d = 2.0 * (a.x * (b.y - c.y) + b.x * (c.y - a.y) + c.x * (a.y - b.y));
+
+    if d.abs() < 1e-9 {
+        return None;
+    }
+
+    let (a2, b2, c2) = (
+        a.x * a.x + a.y * a.y,
+        b.x * b.x + b.y * b.y,
+        c.x * c.x + c.y * c.y,
+    );
+    let center = (
+        (a2 * (b.y - c.y) + b2 * (c.y - a.y) + c2 * (a.y - b.y)) / d,
+        (a2 * (c.x - b.x) + b2 * (a.x - c.x) + c2 * (b.x - a.x)) / d,
+    );
+
+    Some((center, distance(center, (a.x, a.y))))
+}
+
+// Circle the points and the segments joining them all stay within
+// `tolerance` of, along with its direction
+fn fit_arc(points: &[Vec4], tolerance: f64) -> Option<((f64, f64), bool)> {
+    let first = points.first()?;
+    let last = points.last()?;
+    let (center, radius) = circle(first, &points[points.len() / 2], last)?;
+
+    if radius > MAX_RADIUS {
+        return None;
+    }
+
+    let clockwise = turn(&points[0], &points[1], &points[2]) < 0.0;
+
+    // Every corner has to bend the same way
+    for w in points.windows(3) {
+        let t = turn(&w[0], &w[1], &w[2]);
+
+        if t.abs() < 1e-12 || (t < 0.0) != clockwise {
+            return None;
+        }
+    }
+
+    let mut sweep = 0.0;
+
+    for w in points.windows(2) {
+        let (a, b) = ((w[0].x, w[0].y), (w[1].x, w[1].y));
+        let middle = ((a.0 + b.0) / 2.0, (a.1 + b.1) / 2.0);
+
+        if (distance(b, center) - radius).abs() > tolerance
+            || (distance(middle, center) - radius).abs() > tolerance
+        {
+            return None;
+        }
+
+        sweep += (distance(a, b) / (2.0 * radius)).min(1.0).asin() * 2.0;
+    }
+
+    // A full turn ends where it starts, which firmwares cannot tell from a no-op
+    if sweep > PI * 1.9 {
+        return None;
+    }
+
+    Some((center, clockwise))
+}
+
+// Replace a run of extruding moves by arcs where possible. `run` starts with
+// the position the first move leaves from.
+fn flush(run: &mut Vec<Vec4>, output: &mut Vec<Command>, tolerance: f64) {
+    let mut start = 0;
+
+    while start + 1 < run.len() {
+        let mut best = None;
+        let mut end = start + MIN_SEGMENTS;
+
+        // Grow the arc as long as it still fits
+        while end < run.len() {
+            match fit_arc(&run[start..=end], tolerance) {
+                Some(arc) => best = Some((end, arc)),
+                None => break,
+            }
+
+            end += 1;
+        }
+
+        match best {
+            Some((end, (center, clockwise))) => {
+                output.push(Command::Arc {
+                    to: run[end].clone(),
+                    center: (center.0 - run[start].x, center.1 - run[start].y),
+                    clockwise,
+                });
+                start = end;
+            }
+            None => {
+                output.push(Command::Move(run[start + 1].clone()));
+                start += 1;
+            }
+        }
+    }
+
+    run.clear();
+}
+
+// Arc fitting pass: runs of flat extruding segments lying within `tolerance`
+// millimeters of a circle become a single G2/G3 move extruding the same amount
+pub fn fit(commands: Vec<Command>, tolerance: f64) -> Vec<Command> {
+    let mut output = Vec::with_capacity(commands.len());
+    let mut run: Vec<Vec4> = vec![];
+    let mut pos = Vec4 {
+        x: 0.0,
+        y: 0.0,
+        z: 0.0,
+        e: 0.0,
+    };
+
+    for command in commands {
+        let extruding =
+            matches!(&command, Command::Move(to) if to.e > pos.e && equal_float(to.z, pos.z));
+
+        if extruding && run.is_empty() {
+            run.push(pos.clone());
+        }
+
+        command.apply(&mut pos);
+
+        if extruding {
+            run.push(pos.clone());
+        } else {
+            flush(&mut run, &mut output, tolerance);
+            output.push(command);
+        }
+    }
+
+    flush(&mut run, &mut output, tolerance);
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::gcode::Vec4;
+    use super::super::toolpath::Command;
+    use super::fit;
+
+    // Moves along a quarter of the circle of radius 10 around (50, 50)
+    fn quarter(clockwise: bool) -> Vec<Command> {
+        (0..=10)
+            .map(|i| {
+                let angle = std::f64::consts::FRAC_PI_2 * i as f64 / 10.0;
+                let angle = if clockwise { -angle } else { angle };
+
+                Command::Move(Vec4 {
+                    x: 50.0 + 10.0 * angle.cos(),
+                    y: 50.0 + 10.0 * angle.sin(),
+                    z: 0.2,
+                    e: i as f64 * 0.1,
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn circle() {
+        for clockwise in [false, true] {
+            let commands = fit(quarter(clockwise), 0.05);
+
+            // The travel to the start, then the arc
+            assert_eq!(commands.len(), 2);
+
+            match &commands[1] {
+                Command::Arc {
+                    to,
+                    center,
+                    clockwise: direction,
+                } => {
+                    assert_eq!(*direction, clockwise);
+                    assert!((center.0 + 10.0).abs() < 1e-9 && center.1.abs() < 1e-9);
+                    assert!((to.x - 50.0).abs() < 1e-9);
+                    assert!((to.y - if clockwise { 40.0 } else { 60.0 }).abs() < 1e-9);
+                    assert!((to.e - 1.0).abs() < 1e-9);
+                }
+                command => panic!("expected an arc, found {:?}", command),
+            }
+        }
+    }
+
+    #[test]
+    fn straight_line() {
+        let commands: Vec<Command> = (0..10)
+            .map(|i| {
+                Command::Move(Vec4 {
+                    x: i as f64,
+                    y: 0.0,
+                    z: 0.2,
+                    e: i as f64 * 0.1,
+                })
+            })
+            .collect();
+
+        assert!(fit(commands, 0.01)
+            .iter()
+            .all(|command| matches!(command, Command::Move(_))));
+    }
+}
@@ -3,11 +3,34 @@ pub enum Axis {
     X, Y, Z,
 }
 
+// Point put over the origin, the middle of the bed
+#[derive(Debug, Clone)]
+pub enum Centering {
+    BoundingBox,
+    Mass,
+    Bed,
+    // Middle of the bounding box over the middle of the bed along X or Y only
+    Axis(Axis),
+}
+
 #[derive(Debug, Clone)]
 pub enum Transform {
-    Center,
+    Center(Centering),
+    DropToBed,
+    // Centered on the bed, then dropped on it
+    PlaceOnBed,
+    // Largest flat side down
+    LayFlat,
+    // Rotation needing the least supports
+    AutoOrient,
     Rotate(Axis, f64),
+    // Axis X, Y and Z, then angle
+    RotateAxis(f64, f64, f64, f64),
     Move(f64, f64, f64),
     Scale(f64, f64, f64),
+    // Scaled alike on every axis, to this size along the axis
+    ScaleTo(Axis, f64),
+    // Through the plane at 0 across the axis
+    Mirror(Axis),
     Homothety(f64),
 }
\ No newline at end of file
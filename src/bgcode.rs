@@ -0,0 +1,784 @@
+use std::io;
+
+use super::checksum::crc32;
+use super::estimate::{self, Estimate};
+use super::profile::Profile;
+use super::thumbnail::Thumbnail;
+
+// Binary G-code container, as read by recent Prusa firmwares: a file header
+// followed by metadata, thumbnail and G-code blocks, each checked by a CRC32.
+// G-code blocks are MeatPack encoded then heatshrink compressed.
+
+const MAGIC: &[u8; 4] = b"GCDE";
+const VERSION: u32 = 1;
+const CHECKSUM_CRC32: u16 = 1;
+
+// Block types
+const FILE_METADATA: u16 = 0;
+const GCODE: u16 = 1;
+const SLICER_METADATA: u16 = 2;
+const PRINTER_METADATA: u16 = 3;
+const PRINT_METADATA: u16 = 4;
+const THUMBNAIL: u16 = 5;
+
+// Compressions, only heatshrink is supported besides none
+const NO_COMPRESSION: u16 = 0;
+const HEATSHRINK_11_4: u16 = 2;
+const HEATSHRINK_12_4: u16 = 3;
+
+// Encodings of metadata and G-code blocks
+const INI: u16 = 0;
+const PLAIN: u16 = 0;
+const MEATPACK: u16 = 1;
+const MEATPACK_COMMENTS: u16 = 2;
+
+const PNG: u16 = 0;
+
+// Uncompressed size G-code blocks are cut at
+const MAX_GCODE_BLOCK: usize = 65535;
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+// Writes values most significant bit first, as heatshrink reads them
+struct BitWriter {
+    bytes: Vec<u8>,
+    current: u8,
+    used: u8,
+}
+
+impl BitWriter {
+    fn push(&mut self, value: usize, bits: u8) {
+        for i in (0..bits).rev() {
+            self.current = self.current << 1 | ((value >> i) & 1) as u8;
+            self.used += 1;
+
+            if self.used == 8 {
+                self.bytes.push(self.current);
+                self.current = 0;
+                self.used = 0;
+            }
+        }
+    }
+
+    // Pad the last byte with zeros, which decoders read as an incomplete back reference
+    fn finish(mut self) -> Vec<u8> {
+        if self.used > 0 {
+            self.bytes.push(self.current << (8 - self.used));
+        }
+
+        self.bytes
+    }
+}
+
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    bit: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn pull(&mut self, bits: u8) -> Option<usize> {
+        if self.bit + bits as usize > self.bytes.len() * 8 {
+            return None;
+        }
+
+        let mut value = 0;
+
+        for _ in 0..bits {
+            let byte = self.bytes[self.bit / 8];
+            value = value << 1 | ((byte >> (7 - self.bit % 8)) & 1) as usize;
+            self.bit += 1;
+        }
+
+        Some(value)
+    }
+}
+
+// Longest chain of earlier positions sharing a hash looked at per byte
+const MAX_CHAIN: usize = 64;
+
+// LZSS with a 2^`window` bytes window and matches up to 2^`lookahead` bytes
+// long. A literal is a 1 bit followed by the byte, a back reference a 0 bit,
+// the distance minus one and the length minus one.
+fn heatshrink_encode(data: &[u8], window: u8, lookahead: u8) -> Vec<u8> {
+    let window_size = 1 << window;
+    let max_length = 1 << lookahead;
+    let hash = |i: usize| {
+        ((data[i] as usize) << 10 ^ (data[i + 1] as usize) << 5 ^ data[i + 2] as usize) & 0x7FFF
+    };
+
+    let mut out = BitWriter {
+        bytes: Vec::with_capacity(data.len() / 2),
+        current: 0,
+        used: 0,
+    };
+    let mut heads = vec![usize::MAX; 0x8000];
+    let mut previous = vec![usize::MAX; data.len()];
+    let mut i = 0;
+
+    while i < data.len() {
+        let mut best = (0, 0);
+
+        if i + 2 < data.len() {
+            let mut candidate = heads[hash(i)];
+            let mut chain = 0;
+
+            while candidate != usize::MAX && i - candidate < window_size && chain < MAX_CHAIN {
+                let length = data[candidate..]
+                    .iter()
+                    .zip(data[i..].iter())
+                    .take(max_length)
+                    .take_while(|(a, b)| a == b)
+                    .count();
+
+                if length > best.1 {
+                    best = (i - candidate, length);
+                }
+
+                candidate = previous[candidate];
+                chain += 1;
+            }
+        }
+
+        // Two byte matches cost as much as two literals
+        let step = if best.1 >= 3 {
+            out.push(0, 1);
+            out.push(best.0 - 1, window);
+            out.push(best.1 - 1, lookahead);
+            best.1
+        } else {
+            out.push(1, 1);
+            out.push(data[i] as usize, 8);
+            1
+        };
+
+        for j in (i..i + step).filter(|j| j + 2 < data.len()) {
+            previous[j] = heads[hash(j)];
+            heads[hash(j)] = j;
+        }
+
+        i += step;
+    }
+
+    out.finish()
+}
+
+fn heatshrink_decode(data: &[u8], window: u8, lookahead: u8) -> io::Result<Vec<u8>> {
+    let mut input = BitReader {
+        bytes: data,
+        bit: 0,
+    };
+    let mut out: Vec<u8> = Vec::with_capacity(data.len() * 2);
+
+    while let Some(tag) = input.pull(1) {
+        if tag == 1 {
+            match input.pull(8) {
+                Some(byte) => out.push(byte as u8),
+                None => break,
+            }
+        } else {
+            let distance = match input.pull(window) {
+                Some(index) => index + 1,
+                None => break,
+            };
+            let length = match input.pull(lookahead) {
+                Some(count) => count + 1,
+                None => break,
+            };
+
+            if distance > out.len() {
+                return Err(invalid(
+                    "heatshrink back reference before the start of the block",
+                ));
+            }
+
+            for _ in 0..length {
+                out.push(out[out.len() - distance]);
+            }
+        }
+    }
+
+    Ok(out)
+}
+
+// MeatPack packs the 15 most frequent G-code characters two per byte, a
+// nibble of 0b1111 meaning the character follows as a full byte
+const MEATPACK_TABLE: &[u8; 15] = b"0123456789. \nGX";
+const MEATPACK_FULL: u8 = 0xF;
+const MEATPACK_SIGNAL: u8 = 0xFF;
+const MEATPACK_ENABLE: u8 = 0xFB;
+const MEATPACK_DISABLE: u8 = 0xFA;
+
+fn meatpack_index(c: u8) -> u8 {
+    MEATPACK_TABLE
+        .iter()
+        .position(|&packed| packed == c)
+        .map(|i| i as u8)
+        .unwrap_or(MEATPACK_FULL)
+}
+
+fn meatpack_encode(text: &[u8]) -> Vec<u8> {
+    let mut out = vec![MEATPACK_SIGNAL, MEATPACK_SIGNAL, MEATPACK_ENABLE];
+    let mut pairs = text.chunks_exact(2);
+
+    for pair in &mut pairs {
+        let (low, high) = (meatpack_index(pair[0]), meatpack_index(pair[1]));
+
+        out.push(high << 4 | low);
+
+        if low == MEATPACK_FULL {
+            out.push(pair[0]);
+        }
+
+        if high == MEATPACK_FULL {
+            out.push(pair[1]);
+        }
+    }
+
+    // An odd character out is written unpacked
+    if let [last] = pairs.remainder() {
+        out.extend([MEATPACK_SIGNAL, MEATPACK_SIGNAL, MEATPACK_DISABLE, *last]);
+    }
+
+    out
+}
+
+fn meatpack_decode(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(data.len() * 2);
+    let mut packing = false;
+    let mut i = 0;
+    let next = |i: &mut usize| -> io::Result<u8> {
+        *i += 1;
+        data.get(*i - 1)
+            .copied()
+            .ok_or_else(|| invalid("truncated MeatPack data"))
+    };
+
+    while i < data.len() {
+        let byte = next(&mut i)?;
+
+        // Two signal bytes in a row cannot be packed characters, as 0xFF is not ASCII
+        if byte == MEATPACK_SIGNAL && data.get(i) == Some(&MEATPACK_SIGNAL) {
+            i += 1;
+
+            match next(&mut i)? {
+                MEATPACK_ENABLE => packing = true,
+                MEATPACK_DISABLE => packing = false,
+                _ => (),
+            }
+        } else if packing {
+            let full = [(byte & 0xF) == MEATPACK_FULL, byte >> 4 == MEATPACK_FULL];
+            let unpack = |nibble: u8, full: bool, i: &mut usize| -> io::Result<u8> {
+                if full {
+                    next(i)
+                } else {
+                    Ok(MEATPACK_TABLE[nibble as usize])
+                }
+            };
+
+            let low = unpack(byte & 0xF, full[0], &mut i)?;
+            let high = unpack(byte >> 4, full[1], &mut i)?;
+
+            out.push(low);
+            out.push(high);
+        } else {
+            out.push(byte);
+        }
+    }
+
+    Ok(out)
+}
+
+// Key and value pairs of a metadata block
+pub type Metadata = Vec<(String, String)>;
+
+// What a binary G-code file is made of, besides the G-code itself
+pub struct Content {
+    pub printer: Metadata,
+    pub print: Metadata,
+    pub slicer: Metadata,
+    // Settings the file was sliced with, written after the print metadata
+    pub settings: Metadata,
+    pub thumbnails: Vec<Thumbnail>,
+}
+
+impl Content {
+    pub fn new(
+        estimate: &Estimate,
+        profile: &Profile,
+        layer_height: f64,
+        layers: usize,
+        thumbnails: &[Thumbnail],
+    ) -> Self {
+        let print = vec![
+            (
+                "filament used [mm]".to_string(),
+                format!("{:.2}", estimate.filament_length),
+            ),
+            (
+                "filament used [g]".to_string(),
+                format!("{:.2}", estimate.filament_weight),
+            ),
+            (
+                "filament cost".to_string(),
+                format!("{:.2}", estimate.filament_cost),
+            ),
+            (
+                "estimated printing time (normal mode)".to_string(),
+                estimate::duration(estimate.time),
+            ),
+            ("total layers".to_string(), layers.to_string()),
+        ];
+        let printer = vec![
+            (
+                "printer_flavor".to_string(),
+                profile.flavor.gcode().name().to_string(),
+            ),
+            (
+                "filament_diameter".to_string(),
+                profile.filament_diameter.to_string(),
+            ),
+            (
+                "temperature".to_string(),
+                profile.first_layer_temperature.to_string(),
+            ),
+            (
+                "bed_temperature".to_string(),
+                profile.first_layer_bed_temperature.to_string(),
+            ),
+            (
+                "extrusion_width".to_string(),
+                profile.extrusion_width.to_string(),
+            ),
+        ];
+
+        let flag = |value: bool| if value { "1" } else { "0" }.to_string();
+        let settings = vec![
+            ("layer_height".to_string(), layer_height.to_string()),
+            (
+                "extrusion_width".to_string(),
+                profile.extrusion_width.to_string(),
+            ),
+            (
+                "first_layer_temperature".to_string(),
+                profile.first_layer_temperature.to_string(),
+            ),
+            ("temperature".to_string(), profile.temperature.to_string()),
+            (
+                "first_layer_bed_temperature".to_string(),
+                profile.first_layer_bed_temperature.to_string(),
+            ),
+            (
+                "bed_temperature".to_string(),
+                profile.bed_temperature.to_string(),
+            ),
+            (
+                "retract_length".to_string(),
+                profile.retract_length.to_string(),
+            ),
+            // In mm/s, as other slicers write it
+            (
+                "retract_speed".to_string(),
+                (profile.retract_speed / 60.0).to_string(),
+            ),
+            (
+                "use_firmware_retraction".to_string(),
+                flag(profile.firmware_retraction),
+            ),
+            (
+                "use_relative_e_distances".to_string(),
+                flag(profile.relative_extrusion),
+            ),
+            (
+                "avoid_crossing_perimeters".to_string(),
+                flag(profile.avoid_crossing_perimeters),
+            ),
+            ("spiral_vase".to_string(), flag(profile.spiral_vase)),
+            (
+                "bottom_solid_layers".to_string(),
+                profile.bottom_layers.to_string(),
+            ),
+            ("arc_fitting".to_string(), flag(profile.arc_fitting)),
+            ("fan_speed".to_string(), profile.fan_speed.to_string()),
+            (
+                "slowdown_below_layer_time".to_string(),
+                profile.min_layer_time.to_string(),
+            ),
+            (
+                "min_print_speed".to_string(),
+                profile.min_print_speed.to_string(),
+            ),
+            (
+                "filament_diameter".to_string(),
+                profile.filament_diameter.to_string(),
+            ),
+            (
+                "filament_density".to_string(),
+                profile.filament_density.to_string(),
+            ),
+            (
+                "filament_cost".to_string(),
+                profile.filament_cost.to_string(),
+            ),
+        ];
+
+        Content {
+            print,
+            printer,
+            slicer: vec![("Producer".to_string(), "Pancake 1.0".to_string())],
+            settings,
+            thumbnails: thumbnails.to_vec(),
+        }
+    }
+}
+
+fn ini(metadata: &[(String, String)]) -> Vec<u8> {
+    metadata
+        .iter()
+        .map(|(key, value)| format!("{}={}\n", key, value))
+        .collect::<String>()
+        .into_bytes()
+}
+
+// Header, parameters, data and CRC32 of a block
+fn block(out: &mut Vec<u8>, kind: u16, compression: u16, params: &[u8], raw: &[u8]) {
+    let data = match compression {
+        HEATSHRINK_11_4 => heatshrink_encode(raw, 11, 4),
+        HEATSHRINK_12_4 => heatshrink_encode(raw, 12, 4),
+        _ => raw.to_vec(),
+    };
+    let start = out.len();
+
+    out.extend(kind.to_le_bytes());
+    out.extend(compression.to_le_bytes());
+    out.extend((raw.len() as u32).to_le_bytes());
+
+    if compression != NO_COMPRESSION {
+        out.extend((data.len() as u32).to_le_bytes());
+    }
+
+    out.extend(params);
+    out.extend(data);
+
+    let crc = crc32(&out[start..]);
+    out.extend(crc.to_le_bytes());
+}
+
+// Pack the G-code text produced by `toolpath::write`
+pub fn write<W: io::Write>(out: &mut W, gcode: &str, content: &Content) -> io::Result<()> {
+    let mut bytes = vec![];
+
+    bytes.extend(MAGIC);
+    bytes.extend(VERSION.to_le_bytes());
+    bytes.extend(CHECKSUM_CRC32.to_le_bytes());
+
+    let slicer = ini(&content.slicer);
+
+    block(
+        &mut bytes,
+        FILE_METADATA,
+        NO_COMPRESSION,
+        &INI.to_le_bytes(),
+        &slicer,
+    );
+    block(
+        &mut bytes,
+        PRINTER_METADATA,
+        NO_COMPRESSION,
+        &INI.to_le_bytes(),
+        &ini(&content.printer),
+    );
+
+    for thumbnail in content.thumbnails.iter() {
+        let params: Vec<u8> = [PNG, thumbnail.width, thumbnail.height]
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .collect();
+
+        block(
+            &mut bytes,
+            THUMBNAIL,
+            NO_COMPRESSION,
+            &params,
+            &thumbnail.png,
+        );
+    }
+
+    block(
+        &mut bytes,
+        PRINT_METADATA,
+        NO_COMPRESSION,
+        &INI.to_le_bytes(),
+        &ini(&content.print),
+    );
+    block(
+        &mut bytes,
+        SLICER_METADATA,
+        NO_COMPRESSION,
+        &INI.to_le_bytes(),
+        &ini(&content.settings),
+    );
+
+    // Blocks end on line boundaries
+    let mut chunk = String::new();
+
+    for line in gcode.split_inclusive('\n') {
+        if !chunk.is_empty() && chunk.len() + line.len() > MAX_GCODE_BLOCK {
+            let packed = meatpack_encode(chunk.as_bytes());
+
+            block(
+                &mut bytes,
+                GCODE,
+                HEATSHRINK_12_4,
+                &MEATPACK_COMMENTS.to_le_bytes(),
+                &packed,
+            );
+            chunk.clear();
+        }
+
+        chunk.push_str(line);
+    }
+
+    if !chunk.is_empty() {
+        let packed = meatpack_encode(chunk.as_bytes());
+
+        block(
+            &mut bytes,
+            GCODE,
+            HEATSHRINK_12_4,
+            &MEATPACK_COMMENTS.to_le_bytes(),
+            &packed,
+        );
+    }
+
+    out.write_all(&bytes)
+}
+
+fn read_u16(bytes: &[u8], at: usize) -> io::Result<u16> {
+    bytes
+        .get(at..at + 2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .ok_or_else(|| invalid("truncated file"))
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> io::Result<u32> {
+    bytes
+        .get(at..at + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| invalid("truncated file"))
+}
+
+// Back to the ASCII G-code, checking every block on the way
+pub fn decode(bytes: &[u8]) -> io::Result<String> {
+    if bytes.get(0..4) != Some(&MAGIC[..]) {
+        return Err(invalid("not a binary G-code file"));
+    }
+
+    if read_u32(bytes, 4)? != VERSION {
+        return Err(invalid("unsupported binary G-code version"));
+    }
+
+    let checksum = read_u16(bytes, 8)?;
+    let mut gcode = vec![];
+    let mut at = 10;
+
+    while at < bytes.len() {
+        let start = at;
+        let kind = read_u16(bytes, at)?;
+        let compression = read_u16(bytes, at + 2)?;
+        let size = read_u32(bytes, at + 4)? as usize;
+
+        at += 8;
+
+        let stored = if compression == NO_COMPRESSION {
+            size
+        } else {
+            at += 4;
+            read_u32(bytes, at - 4)? as usize
+        };
+        let params = if kind == THUMBNAIL { 6 } else { 2 };
+        let encoding = read_u16(bytes, at)?;
+
+        at += params;
+
+        let data = bytes
+            .get(at..at + stored)
+            .ok_or_else(|| invalid("truncated block"))?;
+
+        at += stored;
+
+        if checksum == CHECKSUM_CRC32 {
+            if read_u32(bytes, at)? != crc32(&bytes[start..at]) {
+                return Err(invalid("block checksum mismatch"));
+            }
+
+            at += 4;
+        }
+
+        if kind != GCODE {
+            continue;
+        }
+
+        let raw = match compression {
+            NO_COMPRESSION => data.to_vec(),
+            HEATSHRINK_11_4 => heatshrink_decode(data, 11, 4)?,
+            HEATSHRINK_12_4 => heatshrink_decode(data, 12, 4)?,
+            _ => return Err(invalid("unsupported block compression")),
+        };
+
+        if raw.len() != size {
+            return Err(invalid("block size mismatch"));
+        }
+
+        match encoding {
+            PLAIN => gcode.extend(raw),
+            MEATPACK | MEATPACK_COMMENTS => gcode.extend(meatpack_decode(&raw)?),
+            _ => return Err(invalid("unsupported G-code encoding")),
+        }
+    }
+
+    String::from_utf8(gcode).map_err(|_| invalid("G-code is not valid text"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gcode::Vec4;
+    use crate::png;
+    use crate::toolpath::{self, Command, Heater, Role};
+
+    // A few layers of moves, long enough to be cut in several G-code blocks
+    fn gcode() -> String {
+        let mut commands = vec![
+            Command::Comment("FLAVOR:Marlin".to_string()),
+            Command::Temperature {
+                heater: Heater::Nozzle,
+                celsius: 210.0,
+                wait: true,
+            },
+            Command::Feedrate(1020.0),
+        ];
+        let mut e = 0.0;
+
+        for layer in 1..=40 {
+            commands.push(Command::Layer(layer));
+            commands.push(Command::Role(Role::Perimeter));
+            commands.push(Command::Fan(if layer > 1 { 255 } else { 0 }));
+
+            for i in 0..100 {
+                let angle = i as f64 * 0.0628;
+
+                e += 0.0451;
+                commands.push(Command::Move(Vec4 {
+                    x: 100.0 + 20.0 * angle.cos(),
+                    y: 100.0 + 20.0 * angle.sin(),
+                    z: layer as f64 * 0.2,
+                    e,
+                }));
+            }
+
+            commands.push(Command::ResetE);
+            e = 0.0;
+        }
+
+        let mut text = vec![];
+
+        toolpath::write(
+            &mut text,
+            &commands,
+            false,
+            Profile::default().flavor.gcode(),
+        )
+        .unwrap();
+        String::from_utf8(text).unwrap()
+    }
+
+    fn content() -> Content {
+        let thumbnail = Thumbnail {
+            width: 2,
+            height: 2,
+            png: png::encode(2, 2, &[255; 16]),
+        };
+
+        Content::new(
+            &Estimate::default(),
+            &Profile::default(),
+            0.2,
+            40,
+            &[thumbnail],
+        )
+    }
+
+    #[test]
+    fn round_trip() {
+        let text = gcode();
+        let mut bytes = vec![];
+
+        assert!(text.len() > MAX_GCODE_BLOCK);
+
+        write(&mut bytes, &text, &content()).unwrap();
+        assert_eq!(decode(&bytes).unwrap(), text);
+    }
+
+    #[test]
+    fn corrupted_block() {
+        let mut bytes = vec![];
+
+        write(&mut bytes, &gcode(), &content()).unwrap();
+
+        // Inside the data of the last G-code block, before its CRC32
+        let at = bytes.len() - 10;
+
+        bytes[at] ^= 0x10;
+        assert!(decode(&bytes).is_err());
+    }
+
+    #[test]
+    fn heatshrink() {
+        let text = gcode();
+        let samples: [&[u8]; 4] = [
+            b"",
+            b"G",
+            b"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa",
+            text.as_bytes(),
+        ];
+
+        for sample in samples.iter() {
+            for window in [11, 12] {
+                let packed = heatshrink_encode(sample, window, 4);
+
+                assert_eq!(heatshrink_decode(&packed, window, 4).unwrap(), *sample);
+            }
+        }
+
+        assert!(heatshrink_encode(text.as_bytes(), 12, 4).len() < text.len() / 2);
+    }
+
+    #[test]
+    fn heatshrink_reference_before_start() {
+        // A back reference bit, then distance 1 with nothing written yet
+        assert!(heatshrink_decode(&[0x00, 0x00, 0x00], 11, 4).is_err());
+    }
+
+    #[test]
+    fn meatpack() {
+        let text = gcode();
+        // Odd length, and characters outside the packed table
+        let samples: [&[u8]; 4] = [b"", b"G1 X10\n", b"M104 S210 ;Heat\n", text.as_bytes()];
+
+        for sample in samples.iter() {
+            assert_eq!(meatpack_decode(&meatpack_encode(sample)).unwrap(), *sample);
+        }
+
+        assert!(meatpack_encode(text.as_bytes()).len() < text.len() * 3 / 4);
+    }
+
+    #[test]
+    fn meatpack_truncated() {
+        // A packed byte announcing a full character that never comes
+        let data = [MEATPACK_SIGNAL, MEATPACK_SIGNAL, MEATPACK_ENABLE, 0xF0];
+
+        assert!(meatpack_decode(&data).is_err());
+    }
+}
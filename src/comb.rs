@@ -0,0 +1,221 @@
+use super::math::region::{cross, distance, normalize, segment_distance, sub, Point};
+use super::math::{Region, EPSILON};
+use super::slice::Slice;
+
+// True if segments (a, b) and (c, d) touch anywhere but near the ends of (a, b)
+fn blocks(a: Point, b: Point, c: Point, d: Point) -> bool {
+    let ab = sub(b, a);
+    let cd = sub(d, c);
+    let denom = cross(ab, cd);
+
+    if denom.abs() < EPSILON * EPSILON {
+        // Parallel edges only block when they overlap the travel away from its ends
+        let overlaps = |p: Point| {
+            segment_distance(p, a, b) < EPSILON
+                && distance(p, a) > EPSILON
+                && distance(p, b) > EPSILON
+        };
+
+        return overlaps(c) || overlaps(d);
+    }
+
+    let ac = sub(c, a);
+    let t = cross(ac, cd) / denom;
+    let u = cross(ac, ab) / denom;
+
+    if !(0.0..=1.0).contains(&t) || !(0.0..=1.0).contains(&u) {
+        return false;
+    }
+
+    let hit = (a.0 + ab.0 * t, a.1 + ab.1 * t);
+
+    distance(hit, a) > EPSILON && distance(hit, b) > EPSILON
+}
+
+// Travel planner for one layer: keeps moves inside the area enclosed by the
+// closed contours of a slice, going around holes through inset corners.
+#[derive(Debug, Clone)]
+pub struct Comb {
+    region: Region,
+    nodes: Vec<Point>,
+}
+
+impl Comb {
+    pub fn new(slice: &Slice, inset: f64) -> Self {
+        let mut comb = Comb {
+            region: Region::new(&slice.polygons),
+            nodes: vec![],
+        };
+
+        comb.nodes = comb.inset_corners(inset);
+        comb
+    }
+
+    // Corners of the contours pushed `inset` millimeters inside the part
+    fn inset_corners(&self, inset: f64) -> Vec<Point> {
+        let mut nodes = vec![];
+
+        for boundary in self.region.boundaries.iter() {
+            let len = boundary.len();
+
+            for (i, &point) in boundary.iter().enumerate() {
+                let prev = boundary[(i + len - 1) % len];
+                let next = boundary[(i + 1) % len];
+
+                let (n1, n2) = match (normalize(sub(point, prev)), normalize(sub(next, point))) {
+                    (Some(a), Some(b)) => ((-a.1, a.0), (-b.1, b.0)),
+                    _ => continue,
+                };
+
+                let bisector = match normalize((n1.0 + n2.0, n1.1 + n2.1)) {
+                    Some(bisector) => bisector,
+                    None => continue,
+                };
+
+                // Lengthen the offset on sharp corners, within reason
+                let miter = inset / (bisector.0 * n1.0 + bisector.1 * n1.1).max(0.25);
+
+                let candidates = [
+                    (point.0 + bisector.0 * miter, point.1 + bisector.1 * miter),
+                    (point.0 - bisector.0 * miter, point.1 - bisector.1 * miter),
+                ];
+
+                if let Some(node) = candidates
+                    .iter()
+                    .find(|c| self.region.contains(**c) && self.region.clearance(**c) > inset * 0.5)
+                {
+                    nodes.push(*node);
+                }
+            }
+        }
+
+        nodes
+    }
+
+    fn visible(&self, a: Point, b: Point) -> bool {
+        let middle = ((a.0 + b.0) / 2.0, (a.1 + b.1) / 2.0);
+
+        self.region.contains(middle) && !self.region.edges().any(|(c, d)| blocks(a, b, c, d))
+    }
+
+    // Waypoints from `from` to `to` (included) staying inside the part.
+    // None when one of the ends is outside or no such path exists.
+    pub fn route(&self, from: Point, to: Point) -> Option<Vec<Point>> {
+        if !self.region.contains(from) || !self.region.contains(to) {
+            return None;
+        }
+
+        if self.visible(from, to) {
+            return Some(vec![to]);
+        }
+
+        // Dijkstra over the visibility graph: 0 is `from`, 1 is `to`, then inset corners
+        let mut points = vec![from, to];
+        points.extend(self.nodes.iter().cloned());
+
+        let mut dist = vec![f64::INFINITY; points.len()];
+        let mut prev: Vec<Option<usize>> = vec![None; points.len()];
+        let mut done = vec![false; points.len()];
+
+        dist[0] = 0.0;
+
+        loop {
+            let current = (0..points.len())
+                .filter(|i| !done[*i] && dist[*i].is_finite())
+                .min_by(|a, b| dist[*a].partial_cmp(&dist[*b]).unwrap())?;
+
+            if current == 1 {
+                break;
+            }
+
+            done[current] = true;
+
+            for next in 1..points.len() {
+                if done[next] {
+                    continue;
+                }
+
+                let cost = dist[current] + distance(points[current], points[next]);
+
+                if cost < dist[next] && self.visible(points[current], points[next]) {
+                    dist[next] = cost;
+                    prev[next] = Some(current);
+                }
+            }
+        }
+
+        let mut path = vec![];
+        let mut current = 1;
+
+        while current != 0 {
+            path.push(points[current]);
+            current = prev[current]?;
+        }
+
+        path.reverse();
+        Some(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use stl_io::Vector;
+
+    use super::super::math::{Polygon, Segment};
+    use super::super::slice::Slice;
+    use super::Comb;
+
+    fn square(x: f64, y: f64, size: f64) -> Polygon {
+        let corners = [(x, y), (x + size, y), (x + size, y + size), (x, y + size)];
+
+        Polygon::new(
+            (0..4)
+                .map(|i| {
+                    let (a, b) = (corners[i], corners[(i + 1) % 4]);
+
+                    Segment {
+                        normal: Vector::new([0.0; 3]),
+                        vertices: [Vector::new([a.0, a.1, 0.0]), Vector::new([b.0, b.1, 0.0])],
+                    }
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn around_a_hole() {
+        let slice = Slice {
+            height: 0.0,
+            polygons: vec![square(0.0, 0.0, 20.0), square(8.0, 8.0, 4.0)],
+        };
+        let comb = Comb::new(&slice, 1.0);
+        let route = comb.route((2.0, 10.0), (18.0, 10.0)).unwrap();
+
+        assert!(route.len() > 1);
+        assert_eq!(route.last(), Some(&(18.0, 10.0)));
+
+        let mut from = (2.0, 10.0);
+
+        for to in route {
+            for step in 0..=100 {
+                let t = step as f64 / 100.0;
+                let (x, y) = (from.0 + (to.0 - from.0) * t, from.1 + (to.1 - from.1) * t);
+
+                assert!((0.0..=20.0).contains(&x) && (0.0..=20.0).contains(&y));
+                assert!(!(x > 8.0 && x < 12.0 && y > 8.0 && y < 12.0));
+            }
+
+            from = to;
+        }
+    }
+
+    #[test]
+    fn outside() {
+        let slice = Slice {
+            height: 0.0,
+            polygons: vec![square(0.0, 0.0, 20.0)],
+        };
+
+        assert_eq!(Comb::new(&slice, 1.0).route((-5.0, 5.0), (5.0, 5.0)), None);
+    }
+}
@@ -0,0 +1,356 @@
+use std::io;
+
+// Deflate streams as zlib, PNG and zip wrap them. Written as a single block
+// with the fixed Huffman codes, read with any kind of block.
+
+// Matches looked at per byte when searching the window
+const MAX_CHAIN: usize = 32;
+const WINDOW: usize = 32768;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+
+const LENGTH_BASES: [usize; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASES: [usize; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+// Deflate packs values least significant bit first, Huffman codes the other way round
+struct BitWriter {
+    bytes: Vec<u8>,
+    current: u32,
+    used: u8,
+}
+
+impl BitWriter {
+    fn bits(&mut self, value: usize, count: u8) {
+        for i in 0..count {
+            self.current |= (((value >> i) & 1) as u32) << self.used;
+            self.used += 1;
+
+            if self.used == 8 {
+                self.bytes.push(self.current as u8);
+                self.current = 0;
+                self.used = 0;
+            }
+        }
+    }
+
+    fn code(&mut self, code: usize, count: u8) {
+        for i in (0..count).rev() {
+            self.bits((code >> i) & 1, 1);
+        }
+    }
+
+    // Symbol of the fixed literal/length alphabet
+    fn symbol(&mut self, symbol: usize) {
+        match symbol {
+            0..=143 => self.code(0x30 + symbol, 8),
+            144..=255 => self.code(0x190 + symbol - 144, 9),
+            256..=279 => self.code(symbol - 256, 7),
+            _ => self.code(0xC0 + symbol - 280, 8),
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.used > 0 {
+            self.bytes.push(self.current as u8);
+        }
+
+        self.bytes
+    }
+}
+
+// Index of the last base not above `value`
+fn bucket(bases: &[usize], value: usize) -> usize {
+    bases.iter().rposition(|&base| base <= value).unwrap_or(0)
+}
+
+pub fn deflate(data: &[u8]) -> Vec<u8> {
+    let hash = |i: usize| {
+        ((data[i] as usize) << 10 ^ (data[i + 1] as usize) << 5 ^ data[i + 2] as usize) & 0x7FFF
+    };
+
+    let mut out = BitWriter {
+        bytes: vec![],
+        current: 0,
+        used: 0,
+    };
+    let mut heads = vec![usize::MAX; 0x8000];
+    let mut previous = vec![usize::MAX; data.len()];
+    let mut i = 0;
+
+    // Single final block with fixed codes
+    out.bits(1, 1);
+    out.bits(1, 2);
+
+    while i < data.len() {
+        let mut best = (0, 0);
+
+        if i + MIN_MATCH <= data.len() {
+            let mut candidate = heads[hash(i)];
+            let mut chain = 0;
+
+            while candidate != usize::MAX && i - candidate <= WINDOW && chain < MAX_CHAIN {
+                let length = data[candidate..]
+                    .iter()
+                    .zip(data[i..].iter())
+                    .take(MAX_MATCH)
+                    .take_while(|(a, b)| a == b)
+                    .count();
+
+                if length > best.1 {
+                    best = (i - candidate, length);
+                }
+
+                // Nothing can beat it, common on long runs of the same color
+                if length == MAX_MATCH {
+                    break;
+                }
+
+                candidate = previous[candidate];
+                chain += 1;
+            }
+        }
+
+        let step = if best.1 >= MIN_MATCH {
+            let (distance, length) = best;
+            let l = bucket(&LENGTH_BASES, length);
+            let d = bucket(&DISTANCE_BASES, distance);
+
+            out.symbol(257 + l);
+            out.bits(length - LENGTH_BASES[l], LENGTH_EXTRA[l]);
+            out.code(d, 5);
+            out.bits(distance - DISTANCE_BASES[d], DISTANCE_EXTRA[d]);
+            length
+        } else {
+            out.symbol(data[i] as usize);
+            1
+        };
+
+        for j in (i..i + step).filter(|j| j + MIN_MATCH <= data.len()) {
+            previous[j] = heads[hash(j)];
+            heads[hash(j)] = j;
+        }
+
+        i += step;
+    }
+
+    out.symbol(256);
+    out.finish()
+}
+
+// Order in which dynamic blocks give the code lengths of their code length alphabet
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+// Deflate packs values least significant bit first
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn bits(&mut self, count: u8) -> io::Result<usize> {
+        let mut value = 0;
+
+        for i in 0..count {
+            let byte = self
+                .bytes
+                .get(self.position / 8)
+                .ok_or_else(|| invalid("truncated deflate stream"))?;
+
+            value |= ((*byte as usize >> (self.position % 8)) & 1) << i;
+            self.position += 1;
+        }
+
+        Ok(value)
+    }
+
+    fn align(&mut self) {
+        self.position = (self.position + 7) / 8 * 8;
+    }
+}
+
+// Canonical Huffman code, as the number of codes of each length and the
+// symbols sorted by code
+struct Huffman {
+    counts: [usize; 16],
+    symbols: Vec<usize>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0; 16];
+
+        for length in lengths.iter() {
+            counts[*length as usize] += 1;
+        }
+
+        counts[0] = 0;
+
+        let mut symbols: Vec<usize> = (0..lengths.len()).filter(|s| lengths[*s] > 0).collect();
+        symbols.sort_by_key(|s| lengths[*s]);
+
+        Huffman { counts, symbols }
+    }
+
+    // Huffman codes come most significant bit first
+    fn decode(&self, reader: &mut BitReader) -> io::Result<usize> {
+        let (mut code, mut first, mut index) = (0, 0, 0);
+
+        for length in 1..16 {
+            code |= reader.bits(1)?;
+
+            let count = self.counts[length];
+
+            if code < first + count {
+                return Ok(self.symbols[index + code - first]);
+            }
+
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+
+        Err(invalid("invalid Huffman code"))
+    }
+}
+
+fn fixed() -> (Huffman, Huffman) {
+    let lengths: Vec<u8> = (0..288)
+        .map(|symbol| match symbol {
+            0..=143 => 8,
+            144..=255 => 9,
+            256..=279 => 7,
+            _ => 8,
+        })
+        .collect();
+
+    (Huffman::new(&lengths), Huffman::new(&[5; 30]))
+}
+
+fn dynamic(reader: &mut BitReader) -> io::Result<(Huffman, Huffman)> {
+    let literals = reader.bits(5)? + 257;
+    let distances = reader.bits(5)? + 1;
+    let count = reader.bits(4)? + 4;
+    let mut code_lengths = [0; 19];
+
+    for position in CODE_LENGTH_ORDER.iter().take(count) {
+        code_lengths[*position] = reader.bits(3)? as u8;
+    }
+
+    let code = Huffman::new(&code_lengths);
+    let mut lengths: Vec<u8> = vec![];
+
+    while lengths.len() < literals + distances {
+        let (value, repeat) = match code.decode(reader)? {
+            16 => (
+                *lengths
+                    .last()
+                    .ok_or_else(|| invalid("repeated code length with none before"))?,
+                3 + reader.bits(2)?,
+            ),
+            17 => (0, 3 + reader.bits(3)?),
+            18 => (0, 11 + reader.bits(7)?),
+            length => (length as u8, 1),
+        };
+
+        lengths.extend(std::iter::repeat(value).take(repeat));
+    }
+
+    if lengths.len() > literals + distances {
+        return Err(invalid("code lengths overflow their alphabets"));
+    }
+
+    Ok((
+        Huffman::new(&lengths[..literals]),
+        Huffman::new(&lengths[literals..]),
+    ))
+}
+
+pub fn inflate(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut reader = BitReader {
+        bytes: data,
+        position: 0,
+    };
+    let mut out = vec![];
+
+    loop {
+        let last = reader.bits(1)? == 1;
+
+        match reader.bits(2)? {
+            0 => {
+                reader.align();
+
+                let start = reader.position / 8;
+                let length = data
+                    .get(start..start + 2)
+                    .map(|length| u16::from_le_bytes([length[0], length[1]]) as usize)
+                    .ok_or_else(|| invalid("truncated deflate stream"))?;
+                let stored = data
+                    .get(start + 4..start + 4 + length)
+                    .ok_or_else(|| invalid("truncated deflate stream"))?;
+
+                out.extend(stored);
+                reader.position = (start + 4 + length) * 8;
+            }
+            kind @ (1 | 2) => {
+                let (literals, distances) = if kind == 1 {
+                    fixed()
+                } else {
+                    dynamic(&mut reader)?
+                };
+
+                loop {
+                    let symbol = literals.decode(&mut reader)?;
+
+                    match symbol {
+                        0..=255 => out.push(symbol as u8),
+                        256 => break,
+                        257..=285 => {
+                            let l = symbol - 257;
+                            let length = LENGTH_BASES[l] + reader.bits(LENGTH_EXTRA[l])?;
+                            let d = distances.decode(&mut reader)?;
+
+                            if d >= DISTANCE_BASES.len() {
+                                return Err(invalid("invalid distance code"));
+                            }
+
+                            let distance = DISTANCE_BASES[d] + reader.bits(DISTANCE_EXTRA[d])?;
+
+                            if distance > out.len() {
+                                return Err(invalid("distance before the start of the data"));
+                            }
+
+                            for _ in 0..length {
+                                out.push(out[out.len() - distance]);
+                            }
+                        }
+                        _ => return Err(invalid("invalid length code")),
+                    }
+                }
+            }
+            _ => return Err(invalid("invalid deflate block type")),
+        }
+
+        if last {
+            return Ok(out);
+        }
+    }
+}
@@ -0,0 +1,532 @@
+use std::f64::consts::PI;
+use std::fmt;
+
+use super::analyze;
+use super::gcode::Vec4;
+use super::profile::Profile;
+use super::toolpath::Command;
+
+// Slowest speed the planner brings the head down to at sharp corners, in mm/s
+const MIN_PLANNER_SPEED: f64 = 0.05;
+
+// Outcome of simulating a G-code stream on the machine of a profile
+#[derive(Debug, Clone, Default)]
+pub struct Estimate {
+    // Seconds
+    pub time: f64,
+    // Seconds spent in each layer, indexed by layer number
+    pub layer_times: Vec<f64>,
+    // Seconds elapsed once each command of the stream is done
+    pub elapsed: Vec<f64>,
+    // Filament pushed through the nozzle, in millimeters
+    pub filament_length: f64,
+    // Cubic millimeters
+    pub filament_volume: f64,
+    // Grams
+    pub filament_weight: f64,
+    // In the currency of `Profile::filament_cost`
+    pub filament_cost: f64,
+}
+
+// Seconds as hours, minutes and seconds
+pub fn duration(seconds: f64) -> String {
+    let seconds = seconds.round() as u64;
+
+    format!(
+        "{}h {}m {}s",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+impl Estimate {
+    // Lines for people, also written in the G-code header
+    fn summary(&self) -> Vec<String> {
+        vec![
+            format!("Print time: {}", duration(self.time)),
+            format!("Filament used: {:.3}m", self.filament_length / 1000.0),
+            format!("Filament volume: {:.2}cm3", self.filament_volume / 1000.0),
+            format!("Filament weight: {:.2}g", self.filament_weight),
+            format!("Filament cost: {:.2}", self.filament_cost),
+        ]
+    }
+
+    // Text of the comments summarizing the estimate, written at the top of the
+    // G-code. Layer times are in seconds, as `layer=seconds` pairs.
+    pub fn header(&self) -> Vec<String> {
+        let layers: Vec<String> = self
+            .layer_times
+            .iter()
+            .enumerate()
+            .filter(|(_, time)| **time > 0.0)
+            .map(|(layer, time)| format!("{}={:.1}", layer, time))
+            .collect();
+
+        let mut header = vec![
+            format!("TIME:{}", self.time.round() as u64),
+            format!("LAYER_TIMES:{}", layers.join(",")),
+        ];
+
+        header.extend(self.summary());
+        header
+    }
+}
+
+impl fmt::Display for Estimate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for line in self.summary().iter() {
+            writeln!(f, "{}", line)?;
+        }
+
+        Ok(())
+    }
+}
+
+// A move as seen by the firmware planner
+#[derive(Debug, Clone)]
+struct Block {
+    // Millimeters along XYZ, or along E for filament only moves
+    distance: f64,
+    // Unit vector of the move in XYZ, zero for filament only moves
+    direction: [f64; 3],
+    // Cruise speed and acceleration once axis limits are applied, in mm/s and mm/s²
+    nominal: f64,
+    acceleration: f64,
+    // Highest speed allowed when entering the block, set by the corner it makes
+    max_entry: f64,
+    // The head comes to a full stop after this block
+    stop_after: bool,
+    layer: Option<usize>,
+    // Index of the command the block comes from
+    command: usize,
+}
+
+// Scale a speed or acceleration along the move so no axis exceeds its own limit
+fn limit(value: f64, deltas: &[f64; 4], distance: f64, limits: &[f64; 4]) -> f64 {
+    deltas
+        .iter()
+        .zip(limits.iter())
+        .filter(|(delta, _)| delta.abs() > 0.0)
+        .fold(value, |value, (delta, limit)| {
+            value.min(limit * distance / delta.abs())
+        })
+}
+
+// Top speed allowed through the corner between two moves, from the junction
+// deviation model used by Marlin and Grbl
+fn junction_speed(previous: &Block, next: &Block, deviation: f64) -> f64 {
+    let cos_theta = -(previous.direction[0] * next.direction[0]
+        + previous.direction[1] * next.direction[1]
+        + previous.direction[2] * next.direction[2]);
+
+    if cos_theta > 0.999_999 {
+        // Going back the way it came
+        return MIN_PLANNER_SPEED;
+    }
+
+    let max = previous.nominal.min(next.nominal);
+
+    if cos_theta < -0.999_999 {
+        // Straight line
+        return max;
+    }
+
+    let sin_half = ((1.0 - cos_theta) / 2.0).sqrt();
+    let speed = (next.acceleration * deviation * sin_half / (1.0 - sin_half)).sqrt();
+
+    speed.max(MIN_PLANNER_SPEED).min(max)
+}
+
+// Duration of a block accelerating from `entry`, cruising, then decelerating to `exit`
+fn trapezoid_time(block: &Block, entry: f64, exit: f64) -> f64 {
+    let (d, a, cruise) = (block.distance, block.acceleration, block.nominal);
+    let accelerate = (cruise * cruise - entry * entry) / (2.0 * a);
+    let decelerate = (cruise * cruise - exit * exit) / (2.0 * a);
+
+    if accelerate + decelerate <= d {
+        (cruise - entry) / a + (cruise - exit) / a + (d - accelerate - decelerate) / cruise
+    } else {
+        // Never reaches cruise speed
+        let peak = ((2.0 * a * d + entry * entry + exit * exit) / 2.0).sqrt();
+
+        (peak - entry) / a + (peak - exit) / a
+    }
+}
+
+fn arc_length(from: &Vec4, to: &Vec4, center: (f64, f64), clockwise: bool) -> f64 {
+    let (cx, cy) = (from.x + center.0, from.y + center.1);
+    let radius = ((from.x - cx).powi(2) + (from.y - cy).powi(2)).sqrt();
+    let start = (from.y - cy).atan2(from.x - cx);
+    let end = (to.y - cy).atan2(to.x - cx);
+
+    let mut sweep = if clockwise { start - end } else { end - start };
+
+    if sweep <= 0.0 {
+        sweep += 2.0 * PI;
+    }
+
+    (radius * sweep).hypot(to.z - from.z)
+}
+
+// Where an axis letter of a G-code word goes
+fn axis(pos: &mut Vec4, letter: char) -> &mut f64 {
+    match letter {
+        'X' => &mut pos.x,
+        'Y' => &mut pos.y,
+        'Z' => &mut pos.z,
+        _ => &mut pos.e,
+    }
+}
+
+// Turns commands into blocks, following the head from one command to the next
+struct Planner<'a> {
+    profile: &'a Profile,
+    blocks: Vec<Block>,
+    pos: Vec4,
+    feedrate: f64,
+    layer: Option<usize>,
+    filament_length: f64,
+    // Modes raw G-code follows, structured commands always carry absolute positions
+    relative: bool,
+    relative_e: bool,
+}
+
+impl<'a> Planner<'a> {
+    // The head comes to rest before whatever comes next
+    fn stop(&mut self) {
+        if let Some(last) = self.blocks.last_mut() {
+            last.stop_after = true;
+        }
+    }
+
+    fn command(&mut self, command: &Command, index: usize) {
+        match command {
+            Command::Feedrate(f) | Command::Extrude { feedrate: f, .. } => self.feedrate = *f,
+            Command::Layer(i) => self.layer = Some(*i),
+            Command::ExtrusionMode { relative } => self.relative_e = *relative,
+            Command::Raw(text) => {
+                for line in text.lines() {
+                    self.raw(line, index);
+                }
+            }
+            // Waits empty the planner queue, firmware retractions are not
+            // timed but start and end at rest
+            Command::Temperature { wait: true, .. }
+            | Command::CoolDown { .. }
+            | Command::Retract
+            | Command::Unretract
+            | Command::Pause
+            | Command::Level => self.stop(),
+            _ => (),
+        }
+
+        let mut next = self.pos.clone();
+        command.apply(&mut next);
+
+        match command {
+            Command::ResetE => self.pos = next,
+            Command::Arc {
+                center, clockwise, ..
+            } => self.move_to(next, Some((*center, *clockwise)), index),
+            _ => self.move_to(next, None, index),
+        }
+    }
+
+    // Moves and modes of a line of raw G-code. Fans and temperatures set
+    // without waiting leave the planner alone, other commands empty it.
+    fn raw(&mut self, line: &str, index: usize) {
+        let words = analyze::parse_line(line);
+        let code = match words.first() {
+            Some(word) if "GM".contains(word.letter) && word.value.fract() == 0.0 => {
+                (word.letter, word.value as u32)
+            }
+            _ => return,
+        };
+        let words = &words[1..];
+
+        if let Some(f) = analyze::value(words, 'F').filter(|f| *f > 0.0) {
+            self.feedrate = f;
+        }
+
+        match code {
+            ('G', 0 | 1) => {
+                let mut next = self.pos.clone();
+
+                for letter in "XYZE".chars() {
+                    let relative = self.relative || (letter == 'E' && self.relative_e);
+
+                    if let Some(v) = analyze::value(words, letter).filter(|v| v.is_finite()) {
+                        let axis = axis(&mut next, letter);
+
+                        *axis = if relative { *axis + v } else { v };
+                    }
+                }
+
+                self.move_to(next, None, index);
+            }
+            ('G', 28) => {
+                let all = !words.iter().any(|w| "XYZ".contains(w.letter));
+
+                self.stop();
+
+                for letter in "XYZ".chars() {
+                    if all || analyze::value(words, letter).is_some() {
+                        *axis(&mut self.pos, letter) = 0.0;
+                    }
+                }
+            }
+            ('G', 90) => self.relative = false,
+            ('G', 91) => self.relative = true,
+            ('G', 92) => {
+                for letter in "XYZE".chars() {
+                    match analyze::value(words, letter) {
+                        Some(v) if v.is_finite() => *axis(&mut self.pos, letter) = v,
+                        _ if words.is_empty() => *axis(&mut self.pos, letter) = 0.0,
+                        _ => (),
+                    }
+                }
+            }
+            ('M', 82) => self.relative_e = false,
+            ('M', 83) => self.relative_e = true,
+            ('M', 73 | 104 | 106 | 107 | 117 | 140) => (),
+            _ => self.stop(),
+        }
+    }
+
+    // Queue the move from the current position to `next`, along the arc
+    // around a center relative to the start when given
+    fn move_to(&mut self, next: Vec4, arc: Option<((f64, f64), bool)>, index: usize) {
+        let pos = &self.pos;
+        let deltas = [
+            next.x - pos.x,
+            next.y - pos.y,
+            next.z - pos.z,
+            next.e - pos.e,
+        ];
+        let travel = match arc {
+            Some((center, clockwise)) => arc_length(pos, &next, center, clockwise),
+            None => (deltas[0].powi(2) + deltas[1].powi(2) + deltas[2].powi(2)).sqrt(),
+        };
+
+        self.filament_length += deltas[3];
+        self.pos = next;
+
+        let profile = self.profile;
+        let (distance, direction, acceleration) = if travel > 0.0 {
+            (
+                travel,
+                [deltas[0] / travel, deltas[1] / travel, deltas[2] / travel],
+                profile.acceleration,
+            )
+        } else if deltas[3].abs() > 0.0 {
+            (deltas[3].abs(), [0.0; 3], profile.retract_acceleration)
+        } else {
+            return;
+        };
+
+        let mut block = Block {
+            distance,
+            direction,
+            nominal: limit(
+                self.feedrate / 60.0,
+                &deltas,
+                distance,
+                &profile.max_feedrate,
+            ),
+            acceleration: limit(acceleration, &deltas, distance, &profile.max_acceleration),
+            max_entry: MIN_PLANNER_SPEED,
+            stop_after: travel == 0.0,
+            layer: self.layer,
+            command: index,
+        };
+
+        if let Some(previous) = self.blocks.last_mut() {
+            if travel == 0.0 {
+                // Filament only moves start and end at rest
+                previous.stop_after = true;
+            } else if !previous.stop_after {
+                block.max_entry = junction_speed(previous, &block, profile.junction_deviation);
+            }
+        }
+
+        self.blocks.push(block);
+    }
+}
+
+// Simulate `commands` starting at `from` with `feedrate` (mm/min) as the
+// current feedrate, modelling the acceleration, junction deviation and
+// feedrate limits of the profile
+pub fn simulate(commands: &[Command], from: &Vec4, feedrate: f64, profile: &Profile) -> Estimate {
+    let mut planner = Planner {
+        profile,
+        blocks: vec![],
+        pos: from.clone(),
+        feedrate,
+        layer: None,
+        filament_length: 0.0,
+        relative: false,
+        relative_e: false,
+    };
+
+    for (index, command) in commands.iter().enumerate() {
+        planner.command(command, index);
+    }
+
+    let blocks = planner.blocks;
+
+    // Backward pass: every block must be able to slow down to the next entry speed
+    let mut entries: Vec<f64> = blocks.iter().map(|b| b.max_entry).collect();
+    let mut exit = 0.0;
+
+    for (i, block) in blocks.iter().enumerate().rev() {
+        if block.stop_after {
+            exit = 0.0;
+        }
+
+        entries[i] =
+            entries[i].min((exit * exit + 2.0 * block.acceleration * block.distance).sqrt());
+        exit = entries[i];
+    }
+
+    // Forward pass: and to reach it accelerating from its own entry speed
+    let mut estimate = Estimate::default();
+    let mut command_times = vec![0.0; commands.len()];
+
+    for (i, block) in blocks.iter().enumerate() {
+        let reachable = (entries[i].powi(2) + 2.0 * block.acceleration * block.distance).sqrt();
+        let exit = match entries.get(i + 1) {
+            Some(next) if !block.stop_after => next.min(reachable),
+            _ => 0.0,
+        };
+
+        if let Some(next) = entries.get_mut(i + 1) {
+            *next = exit;
+        }
+
+        let time = trapezoid_time(block, entries[i], exit);
+
+        estimate.time += time;
+        command_times[block.command] += time;
+
+        if let Some(layer) = block.layer {
+            if estimate.layer_times.len() <= layer {
+                estimate.layer_times.resize(layer + 1, 0.0);
+            }
+
+            estimate.layer_times[layer] += time;
+        }
+    }
+
+    estimate.elapsed = command_times
+        .iter()
+        .scan(0.0, |elapsed, time| {
+            *elapsed += time;
+            Some(*elapsed)
+        })
+        .collect();
+
+    let radius = profile.filament_diameter / 2.0;
+
+    estimate.filament_length = planner.filament_length.max(0.0);
+    estimate.filament_volume = estimate.filament_length * PI * radius * radius;
+    estimate.filament_weight = estimate.filament_volume / 1000.0 * profile.filament_density;
+    estimate.filament_cost = estimate.filament_weight / 1000.0 * profile.filament_cost;
+
+    estimate
+}
+
+fn progress(elapsed: f64, total: f64) -> Command {
+    Command::Progress {
+        percent: (elapsed / total * 100.0).floor().clamp(0.0, 100.0) as u8,
+        minutes: ((total - elapsed).max(0.0) / 60.0).round() as u32,
+    }
+}
+
+// Insert progress markers ahead of every layer, and every `interval` seconds
+// within layers when non zero, from the estimate of the same `commands`
+pub fn add_progress(commands: Vec<Command>, estimate: &Estimate, interval: f64) -> Vec<Command> {
+    let total = estimate.time.max(f64::EPSILON);
+    let mut output = Vec::with_capacity(commands.len());
+    let mut last = 0.0;
+
+    for (index, command) in commands.into_iter().enumerate() {
+        // Time elapsed when the command starts
+        let elapsed = match index {
+            0 => 0.0,
+            _ => estimate
+                .elapsed
+                .get(index - 1)
+                .copied()
+                .unwrap_or(estimate.time),
+        };
+
+        let is_layer = matches!(command, Command::Layer(_));
+
+        if is_layer || (interval > 0.0 && elapsed - last >= interval) {
+            last = elapsed;
+
+            // After the layer comment, so slicer viewers keep it on its layer
+            if is_layer {
+                output.push(command);
+                output.push(progress(elapsed, total));
+                continue;
+            }
+
+            output.push(progress(elapsed, total));
+        }
+
+        output.push(command);
+    }
+
+    output.push(progress(estimate.time, total));
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::gcode::Vec4;
+    use super::super::profile::Profile;
+    use super::super::toolpath::Command;
+    use super::simulate;
+
+    fn at(x: f64, e: f64) -> Vec4 {
+        Vec4 {
+            x,
+            y: 0.0,
+            z: 0.0,
+            e,
+        }
+    }
+
+    #[test]
+    fn trapezoid() {
+        // 100mm/s reached after 0.1s and 5mm at 1000mm/s², both ways, leaving
+        // 90mm of cruise. The planner starts from its slowest speed, not rest.
+        let commands = vec![
+            Command::Layer(1),
+            Command::Feedrate(6000.0),
+            Command::Move(at(100.0, 4.0)),
+        ];
+        let estimate = simulate(&commands, &at(0.0, 0.0), 1500.0, &Profile::default());
+
+        assert!((estimate.time - 1.1).abs() < 1e-3, "{}", estimate.time);
+        assert!((estimate.layer_times[1] - 1.1).abs() < 1e-3);
+        assert!((estimate.filament_length - 4.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn raw_moves() {
+        // A purge pushing 10mm at 200mm/min, then the same length pulled back
+        // and pushed again in relative mode
+        let commands = vec![
+            Command::Raw("G1 E10 F200 ;purge".to_string()),
+            Command::Raw("M83\nG1 E-2\nG1 E2\nM82\nG92 E0".to_string()),
+            Command::Move(at(0.0, 1.0)),
+        ];
+        let estimate = simulate(&commands, &at(0.0, 0.0), 1500.0, &Profile::default());
+
+        assert!((estimate.filament_length - 11.0).abs() < 1e-9);
+        assert!((estimate.time - 4.5).abs() < 0.01, "{}", estimate.time);
+    }
+}
@@ -0,0 +1,70 @@
+use super::math::{Segment, X, Y};
+use super::slice::Slice;
+
+use stl_io::Vector;
+
+// Parallel lines covering the inside of the closed contours of a slice, `spacing`
+// millimeters apart. Lines run along X, or along Y when `along_y` is set, and
+// alternate direction so they can be printed as a zigzag.
+pub fn rectilinear(slice: &Slice, spacing: f64, along_y: bool) -> Vec<Segment> {
+    // Work in (u, v) coordinates where lines run along u
+    let (u, v) = if along_y { (Y, X) } else { (X, Y) };
+
+    let edges: Vec<((f64, f64), (f64, f64))> = slice
+        .polygons
+        .iter()
+        .filter(|polygon| polygon.is_closed())
+        .flat_map(|polygon| polygon.iter())
+        .map(|s| {
+            (
+                (s.vertices[0][u], s.vertices[0][v]),
+                (s.vertices[1][u], s.vertices[1][v]),
+            )
+        })
+        .collect();
+
+    let (min, max) = edges
+        .iter()
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |acc, (a, b)| {
+            (acc.0.min(a.1).min(b.1), acc.1.max(a.1).max(b.1))
+        });
+
+    let vertex = |pu: f64, pv: f64| {
+        let mut coords = [0.0, 0.0, slice.height];
+
+        coords[u] = pu;
+        coords[v] = pv;
+        Vector::new(coords)
+    };
+
+    let mut segments = vec![];
+    let mut line = min + spacing / 2.0;
+    let mut reverse = false;
+
+    while line < max {
+        // Even-odd: crossings pair up into spans lying inside the part
+        let mut crossings: Vec<f64> = edges
+            .iter()
+            .filter(|(a, b)| (a.1 > line) != (b.1 > line))
+            .map(|(a, b)| a.0 + (line - a.1) / (b.1 - a.1) * (b.0 - a.0))
+            .collect();
+
+        crossings.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+        if reverse {
+            crossings.reverse();
+        }
+
+        for span in crossings.chunks_exact(2) {
+            segments.push(Segment {
+                normal: Vector::new([0.0; 3]),
+                vertices: [vertex(span[0], line), vertex(span[1], line)],
+            });
+        }
+
+        reverse = !reverse;
+        line += spacing;
+    }
+
+    segments
+}
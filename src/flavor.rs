@@ -0,0 +1,223 @@
+use std::fmt;
+use std::str::FromStr;
+
+use super::toolpath::Heater;
+
+// How a firmware spells the commands `Printer` needs. The defaults are the
+// RepRap conventions most firmwares share, flavors override what differs.
+pub trait GcodeFlavor: fmt::Debug + Sync {
+    fn name(&self) -> &'static str;
+
+    // Digits written after the point of coordinates and feedrates, and of E values
+    fn decimals(&self) -> (usize, usize) {
+        (3, 5)
+    }
+
+    // Trailing zeros are dropped to keep lines short
+    fn number(&self, value: f64, decimals: usize) -> String {
+        let text = format!("{:.*}", decimals, value);
+        let text = if text.contains('.') {
+            text.trim_end_matches('0').trim_end_matches('.')
+        } else {
+            &text
+        };
+
+        match text {
+            "-0" => "0".to_string(),
+            text => text.to_string(),
+        }
+    }
+
+    fn comment(&self, text: &str) -> String {
+        format!(";{}", text)
+    }
+
+    fn temperature(&self, heater: Heater, celsius: f64, wait: bool) -> String {
+        let code = match (heater, wait) {
+            (Heater::Nozzle, false) => "M104",
+            (Heater::Nozzle, true) => "M109",
+            (Heater::Bed, false) => "M140",
+            (Heater::Bed, true) => "M190",
+        };
+
+        format!("{} S{}", code, self.number(celsius, 0))
+    }
+
+    // Set a heater and wait for it to get down to `celsius`
+    fn cool_down(&self, heater: Heater, celsius: f64) -> String {
+        let code = match heater {
+            Heater::Nozzle => "M109",
+            Heater::Bed => "M190",
+        };
+
+        format!("{} R{}", code, self.number(celsius, 0))
+    }
+
+    fn fan(&self, speed: u8) -> String {
+        match speed {
+            0 => "M107".to_string(),
+            speed => format!("M106 S{}", speed),
+        }
+    }
+
+    fn extrusion_mode(&self, relative: bool) -> String {
+        if relative { "M83" } else { "M82" }.to_string()
+    }
+
+    // Firmware retraction, using the length and speed set up on the printer
+    fn retract(&self) -> String {
+        "G10".to_string()
+    }
+
+    fn unretract(&self) -> String {
+        "G11".to_string()
+    }
+
+    // None when the firmware has no way to display it
+    fn progress(&self, percent: u8, minutes: u32) -> Option<String> {
+        Some(format!("M73 P{} R{}", percent, minutes))
+    }
+
+    // Stop until the user resumes the print
+    fn pause(&self) -> String;
+
+    // Probe the bed to compensate for its tilt
+    fn level(&self) -> String {
+        "G29".to_string()
+    }
+}
+
+#[derive(Debug)]
+pub struct Marlin;
+
+impl GcodeFlavor for Marlin {
+    fn name(&self) -> &'static str {
+        "Marlin"
+    }
+
+    fn pause(&self) -> String {
+        "M0".to_string()
+    }
+}
+
+#[derive(Debug)]
+pub struct RepRapFirmware;
+
+impl GcodeFlavor for RepRapFirmware {
+    fn name(&self) -> &'static str {
+        "RepRapFirmware"
+    }
+
+    // M109 waits for cooling too, R sets the standby temperature
+    fn cool_down(&self, heater: Heater, celsius: f64) -> String {
+        self.temperature(heater, celsius, true)
+    }
+
+    // Fan speeds are fractions, M107 is deprecated
+    fn fan(&self, speed: u8) -> String {
+        format!("M106 S{}", self.number(speed as f64 / 255.0, 2))
+    }
+
+    // Runs pause.g
+    fn pause(&self) -> String {
+        "M226".to_string()
+    }
+}
+
+#[derive(Debug)]
+pub struct Klipper;
+
+impl GcodeFlavor for Klipper {
+    fn name(&self) -> &'static str {
+        "Klipper"
+    }
+
+    fn cool_down(&self, heater: Heater, celsius: f64) -> String {
+        let sensor = match heater {
+            Heater::Nozzle => "extruder",
+            Heater::Bed => "heater_bed",
+        };
+
+        format!(
+            "{}\nTEMPERATURE_WAIT SENSOR={} MAXIMUM={}",
+            self.temperature(heater, celsius, false),
+            sensor,
+            self.number(celsius, 0)
+        )
+    }
+
+    // The remaining time is computed by Klipper itself
+    fn progress(&self, percent: u8, _minutes: u32) -> Option<String> {
+        Some(format!("M73 P{}", percent))
+    }
+
+    fn pause(&self) -> String {
+        "PAUSE".to_string()
+    }
+
+    fn level(&self) -> String {
+        "BED_MESH_CALIBRATE".to_string()
+    }
+}
+
+#[derive(Debug)]
+pub struct Smoothie;
+
+impl GcodeFlavor for Smoothie {
+    fn name(&self) -> &'static str {
+        "Smoothie"
+    }
+
+    // Without R, M109 only waits for the temperature to be reached from below
+    fn cool_down(&self, heater: Heater, celsius: f64) -> String {
+        format!("{}\nM116", self.temperature(heater, celsius, false))
+    }
+
+    fn progress(&self, _percent: u8, _minutes: u32) -> Option<String> {
+        None
+    }
+
+    // Suspend, resumed with M601 or from the panel
+    fn pause(&self) -> String {
+        "M600".to_string()
+    }
+
+    // Runs the configured levelling strategy
+    fn level(&self) -> String {
+        "G32".to_string()
+    }
+}
+
+// Firmware flavor of a profile, selected by name
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Flavor {
+    Marlin,
+    RepRapFirmware,
+    Klipper,
+    Smoothie,
+}
+
+impl Flavor {
+    pub fn gcode(self) -> &'static dyn GcodeFlavor {
+        match self {
+            Flavor::Marlin => &Marlin,
+            Flavor::RepRapFirmware => &RepRapFirmware,
+            Flavor::Klipper => &Klipper,
+            Flavor::Smoothie => &Smoothie,
+        }
+    }
+}
+
+impl FromStr for Flavor {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_lowercase().as_str() {
+            "marlin" => Ok(Flavor::Marlin),
+            "reprap" | "reprapfirmware" | "rrf" => Ok(Flavor::RepRapFirmware),
+            "klipper" => Ok(Flavor::Klipper),
+            "smoothie" | "smoothieware" => Ok(Flavor::Smoothie),
+            _ => Err(format!("Unknown G-code flavor: {}", name)),
+        }
+    }
+}
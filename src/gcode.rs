@@ -1,65 +1,59 @@
 pub const init: &'static str = "
-M82 ;absolute extrusion mode
 ;Begin Start Gcode for Dagoma DiscoUltimate
 ;Sliced: 03-10-2020 12:30:37
 ;Initial extruder: 0
 
-G90 ;Absolute positioning
-M106 S255 ;Fan on full
-G28 X Y ;Home stop X Y
-G1 X100 ;Centre back during cooldown in case of oozing
-M109 R90 ;Cooldown in case too hot
-G28 ;Centre
-G29 ;Auto-level
-M104 S215 ;Pre-heat
-M107 ;Fan off
-G0 X100 Y5 Z0.5 ;Front centre for degunk
-M109 S215 ;Wait for initial temp
-M83 ;E Relative
-G1 E10 F200 ;Degunk
-G1 E-3 F5000 ;Retract
-G0 Z3 ;Withdraw
-M82 ;E absolute
-G92 E0 ;E reset
-G1 F6000 ;Set feedrate
-";
+G90 ;Absolute positioning";
 
-pub const init2: &'static str = "
-G92 E0
-G92 E0
-G1 F3000 E-3.5
-;LAYER_COUNT:9
-;LAYER:0
-M107
-G0 F956.2 X81.405 Y69.576 Z0.26
-G1 F1020
-";
+// Preceded by the fan turned on full
+pub const init_centre: &'static str = "G28 X Y ;Home stop X Y
+G1 X100 ;Centre back during cooldown in case of oozing";
+
+// Preceded by the cooldown in case too hot, followed by the bed levelling
+pub const init_home: &'static str = "G28 ;Centre";
 
-pub const end: &'static str = "
-M106 S255 ;Fan on full
-M104 S0 ;Cool hotend
-M140 S0 ;Cool heated bed
-G91 ;Relative positioning
+// Preceded by the nozzle pre-heat and the fan turned off, see `Printer::print`
+pub const init_degunk: &'static str = "G0 X100 Y5 Z0.5 ;Front centre for degunk";
+
+// Preceded by the wait for first layer temperatures, in relative extrusion
+// mode, see `Printer::print`
+pub const init_purge: &'static str = "G1 E10 F200 ;Degunk
+G1 E-3 F5000 ;Retract
+G0 Z3 ;Withdraw";
+
+// Preceded by the fan turned on full and the heaters turned off
+pub const end: &'static str = "G91 ;Relative positioning
 G1 E-3 F5000 ;Retract filament to stop oozing
 G0 Z+3 ;Withdraw
 G90 ;Absolute positioning
-G28 X Y ;Home
-M109 R90 ;Wait until head has cooled to standby temp
-M107 ;Fan off
-M18 ;Stepper motors off
+G28 X Y ;Home";
 
-;Finish End Gcode for Dagoma DiscoUltimate
+// Preceded by the wait until the head has cooled to standby temp and the fan
+// turned off, followed by the absolute extrusion mode and the hotend off
+pub const end2: &'static str = "M18 ;Stepper motors off
 
-M82 ;absolute extrusion mode
-M104 S0
+;Finish End Gcode for Dagoma DiscoUltimate
 ";
 
 pub const FLOW: f64 = 0.045;
+pub const FEEDRATE: f64 = 1020.0;
 
-use stl_io::Vector;
+use std::io::{self, Write};
 
-use crate::math::equal_vertices;
+use stl_io::{Vector, Vertex};
 
+use crate::math::{equal_vertices, Polygon, Region, Segment};
+
+use super::arc;
+use super::bgcode;
+use super::comb::Comb;
+use super::estimate::{self, Estimate};
+use super::fill;
+use super::flavor::GcodeFlavor;
+use super::profile::Profile;
+use super::svg;
+use super::thumbnail::Thumbnail;
+use super::toolpath::{self, Command, Heater, Role};
 use super::Slice;
 use super::{X, Y, Z};
 
@@ -71,6 +65,71 @@ fn get_distance(a: Vec4, b: Vec4) -> f64 {
     (x * x + y * y + z * z).sqrt()
 }
 
+// One loop around `contour`, from its point closest to `from` and back there a
+// layer higher over `next`, if any
+fn spiral(
+    contour: &Polygon,
+    next: Option<&Polygon>,
+    from: (f64, f64),
+    layer_height: f64,
+) -> Option<Vec<Vertex>> {
+    // Start where the previous loop ended, splitting the contour there
+    let (start, (x, y)) = contour.closest_point(from.0, from.1)?;
+    let height = contour[start].vertices[0][Z];
+
+    let mut points: Vec<Vertex> = vec![Vector::new([x, y, height])];
+    points.extend(
+        contour
+            .iter()
+            .cycle()
+            .skip(start + 1)
+            .take(contour.len())
+            .map(|s| s.vertices[0]),
+    );
+    points.push(points[0]);
+    // Starting on a corner would leave a move of no length at the end
+    points.dedup_by(|a, b| equal_vertices(*a, *b));
+
+    let lengths: Vec<f64> = points
+        .windows(2)
+        .scan(0.0, |total, pair| {
+            *total +=
+                ((pair[1][X] - pair[0][X]).powi(2) + (pair[1][Y] - pair[0][Y]).powi(2)).sqrt();
+            Some(*total)
+        })
+        .collect();
+
+    let total = lengths.last().cloned().unwrap_or(0.0);
+    let rise = next.map_or(0.0, |_| layer_height);
+    let mut path = vec![points[0]];
+
+    for (point, length) in points.iter().skip(1).zip(lengths) {
+        let t = if total > 0.0 { length / total } else { 1.0 };
+        let (x, y) = next
+            .and_then(|next| next.closest_point(point[X], point[Y]))
+            .map(|(_, closest)| closest)
+            .map_or((point[X], point[Y]), |(nx, ny)| {
+                (
+                    point[X] + (nx - point[X]) * t,
+                    point[Y] + (ny - point[Y]) * t,
+                )
+            });
+
+        path.push(Vector::new([x, y, point[Z] + rise * t]));
+    }
+
+    Some(path)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Feature {
+    Wall,
+    // Printed partly over air
+    Overhang,
+    // Supported at both ends only
+    Bridge,
+}
+
 #[derive(Debug, Clone)]
 pub struct Vec4 {
     pub x: f64,
@@ -83,6 +142,17 @@ pub struct Vec4 {
 pub struct Printer {
     pub cur_pos: Vec4,
     pub offset: Vec4,
+    pub profile: Profile,
+    pub comb: Option<Comb>,
+    pub first_draw: bool,
+    // Where the nozzle goes down to for the first path, the first layer being
+    // printed at this height whatever the model height
+    pub first_layer_height: f64,
+    pub output: Vec<Command>,
+    // Contours of the previous layer, what the current one rests on
+    pub support: Option<Region>,
+    pub fan: u8,
+    pub layer_fan: u8,
 }
 
 impl Printer {
@@ -93,7 +163,7 @@ impl Printer {
         self.cur_pos.z = z;
         self.cur_pos.e = e;
 
-        println!("G1 X{} Y{} Z{} E{}", x, y, z, e);
+        self.output.push(Command::Move(self.cur_pos.clone()));
     }
 
     // Relative position
@@ -128,13 +198,252 @@ impl Printer {
         self.move_by(x, y, z, e);
     }
 
-    pub fn print<T>(input: T, layer_height: f64) -> Option<()>
+    fn retract(&mut self) {
+        if self.profile.firmware_retraction {
+            self.output.push(Command::Retract);
+            return;
+        }
+
+        self.cur_pos.e -= self.profile.retract_length;
+
+        self.output.push(Command::Extrude {
+            e: self.cur_pos.e,
+            feedrate: self.profile.retract_speed,
+        });
+    }
+
+    fn unretract(&mut self) {
+        if self.profile.firmware_retraction {
+            self.output.push(Command::Unretract);
+            return;
+        }
+
+        self.cur_pos.e += self.profile.retract_length;
+
+        self.output.push(Command::Extrude {
+            e: self.cur_pos.e,
+            feedrate: self.profile.retract_speed,
+        });
+        self.output.push(Command::Feedrate(FEEDRATE));
+    }
+
+    // Travel to a point of the model, combed inside the layer when enabled
+    fn travel_to(&mut self, target: Vertex) {
+        let from = (self.offset.x, self.offset.y);
+        let to = (target[X], target[Y]);
+        let route = self.comb.as_ref().map(|comb| comb.route(from, to));
+
+        match route {
+            Some(Some(waypoints)) => {
+                for (x, y) in waypoints.into_iter() {
+                    self.move_by(
+                        x - self.offset.x,
+                        y - self.offset.y,
+                        target[Z] - self.offset.z,
+                        0.0,
+                    );
+                    self.offset = Vec4 {
+                        x,
+                        y,
+                        z: target[Z],
+                        e: 0.0,
+                    };
+                }
+            }
+            route => {
+                // Leaving the part is unavoidable, retract to avoid stringing
+                let retract = route.is_some();
+
+                if retract {
+                    self.retract();
+                }
+
+                self.move_by(
+                    target[X] - self.offset.x,
+                    target[Y] - self.offset.y,
+                    target[Z] - self.offset.z,
+                    0.0,
+                );
+
+                if retract {
+                    self.unretract();
+                }
+            }
+        }
+
+        self.offset = Vec4 {
+            x: target[X],
+            y: target[Y],
+            z: target[Z],
+            e: 0.0,
+        };
+    }
+
+    // Reach the start of a path, travelling from the previous one if apart
+    fn start_at(&mut self, point: Vertex) {
+        if self.first_draw {
+            // Models stand around the origin, printed around the middle of the
+            // bed. The retraction of the start block is undone on the way.
+            let [width, depth, _] = self.profile.build_volume;
+
+            self.move_to(
+                point[X] + width / 2.0,
+                point[Y] + depth / 2.0,
+                self.first_layer_height,
+                0.0,
+            );
+        } else if !equal_vertices(
+            point,
+            Vector::new([self.offset.x, self.offset.y, self.offset.z]),
+        ) {
+            self.travel_to(point);
+        }
+    }
+
+    fn set_fan(&mut self, speed: u8) {
+        if speed != self.fan {
+            self.fan = speed;
+            self.output.push(Command::Fan(speed));
+        }
+    }
+
+    fn feature(&self, segment: &Segment) -> Feature {
+        let support = match self.support.as_ref() {
+            Some(support) => support,
+            None => return Feature::Wall,
+        };
+
+        let margin = self.profile.extrusion_width / 2.0;
+        let unsupported =
+            |x: f64, y: f64| !support.contains((x, y)) && support.clearance((x, y)) > margin;
+
+        let (a, b) = (segment.vertices[0], segment.vertices[1]);
+
+        if !unsupported((a[X] + b[X]) / 2.0, (a[Y] + b[Y]) / 2.0) {
+            Feature::Wall
+        } else if !unsupported(a[X], a[Y]) && !unsupported(b[X], b[Y]) {
+            Feature::Bridge
+        } else {
+            Feature::Overhang
+        }
+    }
+
+    // Print a segment of the model, travelling to its start first if needed
+    fn draw_segment(&mut self, segment: Segment) {
+        let first_point = segment.vertices[0];
+        let second_point = segment.vertices[1];
+
+        self.start_at(first_point);
+
+        let fan = match self.feature(&segment) {
+            Feature::Bridge => self.profile.bridge_fan_speed,
+            Feature::Overhang => self.profile.overhang_fan_speed,
+            Feature::Wall => None,
+        };
+
+        self.set_fan(fan.unwrap_or(self.layer_fan));
+
+        self.offset = Vec4 {
+            x: first_point[X],
+            y: first_point[Y],
+            z: first_point[Z],
+            e: 0.0,
+        };
+
+        self.print_by(
+            second_point[X] - self.offset.x,
+            second_point[Y] - self.offset.y,
+            second_point[Z] - self.offset.z,
+        );
+        self.offset = Vec4 {
+            x: second_point[X],
+            y: second_point[Y],
+            z: second_point[Z],
+            e: 0.0,
+        };
+
+        self.first_draw = false;
+    }
+
+    // Print a contour as a single loop rising by one layer, morphing into the
+    // contour of the next layer on the way so the spiral has no seam
+    fn print_spiral(&mut self, contour: &Polygon, next: Option<&Polygon>, layer_height: f64) {
+        let from = (self.offset.x, self.offset.y);
+        let points = match spiral(contour, next, from, layer_height) {
+            Some(points) => points,
+            None => return,
+        };
+
+        self.start_at(points[0]);
+
+        self.offset = Vec4 {
+            x: points[0][X],
+            y: points[0][Y],
+            z: points[0][Z],
+            e: 0.0,
+        };
+
+        for point in points.iter().skip(1) {
+            let (x, y, z) = (point[X], point[Y], point[Z]);
+
+            self.print_by(x - self.offset.x, y - self.offset.y, z - self.offset.z);
+            self.offset = Vec4 { x, y, z, e: 0.0 };
+        }
+
+        self.first_draw = false;
+    }
+
+    // Slow down the layer starting at `start` when it prints faster than the
+    // minimum layer time, running the fan at full speed to cool it
+    fn slow_down(&mut self, start: usize, from: &Vec4) {
+        if self.profile.min_layer_time <= 0.0 {
+            return;
+        }
+
+        let time = estimate::simulate(&self.output[start..], from, FEEDRATE, &self.profile).time;
+
+        if time <= 0.0 || time >= self.profile.min_layer_time {
+            return;
+        }
+
+        let factor = (time / self.profile.min_layer_time)
+            .max(self.profile.min_print_speed * 60.0 / FEEDRATE)
+            .min(1.0);
+        let boost = self.profile.max_fan_speed;
+        let layer_fan = self.layer_fan;
+
+        for command in self.output[start..].iter_mut() {
+            match command {
+                Command::Feedrate(feedrate) => *feedrate *= factor,
+                Command::Fan(speed) if *speed == layer_fan => *speed = boost,
+                _ => (),
+            }
+        }
+
+        if !matches!(self.output.get(start), Some(Command::Fan(_))) {
+            self.output.insert(start, Command::Fan(boost));
+        }
+
+        self.output
+            .insert(start + 1, Command::Feedrate(FEEDRATE * factor));
+        self.output.push(Command::Feedrate(FEEDRATE));
+
+        if self.fan == layer_fan {
+            self.fan = boost;
+        }
+    }
+
+    // Write the G-code for `input` to stdout, returning the estimated print time
+    // and filament usage
+    pub fn print<T>(
+        input: T,
+        layer_height: f64,
+        profile: &Profile,
+        thumbnails: &[Thumbnail],
+    ) -> io::Result<Estimate>
     where
         T: Iterator<Item = Slice>,
     {
-        println!("{}", init);
-        println!("{}", init2);
-
         let first_layer_height: f64 = layer_height / 2.0;
 
         let mut state = Printer {
@@ -150,73 +459,317 @@ impl Printer {
                 z: 0.0,
                 e: 0.0,
             },
+            profile: profile.clone(),
+            comb: None,
+            first_draw: true,
+            first_layer_height,
+            output: vec![],
+            support: None,
+            fan: 0,
+            layer_fan: 0,
         };
 
-        // Center print-head
+        let bed = profile.first_layer_bed_temperature;
+        let nozzle = profile.first_layer_temperature;
+
+        if bed > 0.0 {
+            state.output.push(Command::Temperature {
+                heater: Heater::Bed,
+                celsius: bed,
+                wait: false,
+            });
+        }
+
+        state
+            .output
+            .push(Command::ExtrusionMode { relative: false });
+        state.output.push(Command::Raw(init.to_string()));
+        state.output.push(Command::Fan(255));
+        state.output.push(Command::Raw(init_centre.to_string()));
+        state.output.push(Command::CoolDown {
+            heater: Heater::Nozzle,
+            celsius: 90.0,
+        });
+        state.output.push(Command::Raw(init_home.to_string()));
+        state.output.push(Command::Level);
+        state.output.push(Command::Temperature {
+            heater: Heater::Nozzle,
+            celsius: nozzle,
+            wait: false,
+        });
+        state.output.push(Command::Fan(0));
+        state.output.push(Command::Raw(init_degunk.to_string()));
+
+        if bed > 0.0 {
+            state.output.push(Command::Temperature {
+                heater: Heater::Bed,
+                celsius: bed,
+                wait: true,
+            });
+        }
+
+        state.output.push(Command::Temperature {
+            heater: Heater::Nozzle,
+            celsius: nozzle,
+            wait: true,
+        });
+        state.output.push(Command::ExtrusionMode { relative: true });
+        state.output.push(Command::Raw(init_purge.to_string()));
+        state.output.push(Command::ExtrusionMode {
+            relative: profile.relative_extrusion,
+        });
+        state.output.push(Command::ResetE);
+        state.output.push(Command::ResetE);
+        state.cur_pos.e = -3.5;
+        state.output.push(Command::Extrude {
+            e: state.cur_pos.e,
+            feedrate: 3000.0,
+        });
+        // Filled in once all the slices are drawn
+        let layer_count = state.output.len();
+        state.output.push(Command::Comment(String::new()));
+        state.output.push(Command::Fan(0));
+        state.output.push(Command::Feedrate(956.2));
+        state.move_to(81.405, 69.576, 0.26, state.cur_pos.e);
+        state.output.push(Command::Feedrate(FEEDRATE));
+        state.output.push(Command::Raw(String::new()));
+
+        // Clear of the purge line, until the first path
         state.move_by(0.0, 0.0, 10.0, 0.0);
-        state.move_to(100.0, 100.0, first_layer_height, 0.0);
 
-        let mut first_draw = true;
+        let mut input = input.peekable();
+        let mut i = 0;
 
         // Drawing slices
-        for (i, slice) in input.enumerate() {
-            if i == 5 {
-                // Fan full power at layer 5
-                println!("M106 S255");
+        while let Some(slice) = input.next() {
+            state.output.push(Command::Layer(i + 1));
+
+            if profile.pause_layers.contains(&(i + 1)) {
+                state.output.push(Command::Pause);
+            }
+
+            // After the marker, the fan and feedrate changes belong to the layer
+            let layer_start = state.output.len();
+            let layer_pos = state.cur_pos.clone();
+
+            state.layer_fan = profile.layer_fan_speed(i);
+            state.set_fan(state.layer_fan);
+
+            if profile.avoid_crossing_perimeters {
+                state.comb = Some(Comb::new(&slice, profile.comb_inset));
+            }
+
+            // Keep absolute E values small to avoid losing precision on long prints
+            if i > 0 && !profile.relative_extrusion {
+                state.output.push(Command::ResetE);
+                state.cur_pos.e = 0.0;
+            }
+
+            if i == 1 {
+                if profile.temperature != profile.first_layer_temperature {
+                    state.output.push(Command::Temperature {
+                        heater: Heater::Nozzle,
+                        celsius: profile.temperature,
+                        wait: false,
+                    });
+                }
+
+                if profile.bed_temperature != profile.first_layer_bed_temperature {
+                    state.output.push(Command::Temperature {
+                        heater: Heater::Bed,
+                        celsius: profile.bed_temperature,
+                        wait: false,
+                    });
+                }
             }
 
-            println!(";LAYER:{}", i + 1);
-            for polygon in slice.polygons.into_iter() {
-                for segment in polygon.into_iter() {
-                    let first_point = segment.vertices[0];
-                    let second_point = segment.vertices[1];
-
-                    if first_draw {
-                        state.offset = Vec4 {
-                            x: first_point[X],
-                            y: first_point[Y],
-                            z: first_point[Z],
-                            e: 0.0,
-                        };
+            let support =
+                if profile.bridge_fan_speed.is_some() || profile.overhang_fan_speed.is_some() {
+                    Some(Region::new(&slice.polygons))
+                } else {
+                    None
+                };
+
+            let spiral = profile.spiral_vase && i >= profile.bottom_layers;
+            let contours = profile.svg_dir.as_ref().map(|_| slice.polygons.clone());
+
+            state.output.push(Command::Role(Role::Perimeter));
+
+            match slice.outer_contour() {
+                Some(contour) if spiral => {
+                    let next = input.peek().and_then(|next| next.outer_contour());
+
+                    state.print_spiral(contour, next, layer_height);
+                }
+                _ => {
+                    let fill = if profile.spiral_vase {
+                        fill::rectilinear(&slice, profile.extrusion_width, i % 2 == 1)
                     } else {
-                        if !equal_vertices(
-                            first_point,
-                            Vector::new([state.offset.x, state.offset.y, state.offset.z]),
-                        ) {
-                            state.move_by(
-                                first_point[X] - state.offset.x,
-                                first_point[Y] - state.offset.y,
-                                first_point[Z] - state.offset.z,
-                                0.0,
-                            );
-                        }
-
-                        state.offset = Vec4 {
-                            x: first_point[X],
-                            y: first_point[Y],
-                            z: first_point[Z],
-                            e: 0.0,
-                        };
+                        vec![]
+                    };
+
+                    for segment in slice.polygons.into_iter().flatten() {
+                        state.draw_segment(segment);
                     }
 
-                    state.print_by(
-                        second_point[X] - state.offset.x,
-                        second_point[Y] - state.offset.y,
-                        second_point[Z] - state.offset.z,
-                    );
-                    state.offset = Vec4 {
-                        x: second_point[X],
-                        y: second_point[Y],
-                        z: second_point[Z],
-                        e: 0.0,
-                    };
+                    if !fill.is_empty() {
+                        state.output.push(Command::Role(Role::Infill));
+                    }
 
-                    first_draw = false;
+                    for segment in fill {
+                        state.draw_segment(segment);
+                    }
                 }
             }
+
+            state.slow_down(layer_start, &layer_pos);
+
+            if let (Some(dir), Some(contours)) = (profile.svg_dir.as_ref(), contours) {
+                // Model coordinates are printed shifted by a constant
+                let shift = (
+                    state.cur_pos.x - state.offset.x,
+                    state.cur_pos.y - state.offset.y,
+                );
+                let drawing = svg::layer(
+                    i + 1,
+                    &contours,
+                    &state.output[layer_start..],
+                    &layer_pos,
+                    shift,
+                    profile.extrusion_width,
+                );
+
+                std::fs::write(svg::path(dir, i + 1), drawing)?;
+            }
+            state.support = support;
+            i += 1;
+        }
+
+        if let Some(dir) = profile.svg_dir.as_ref() {
+            std::fs::write(format!("{}/index.html", dir), svg::index(i))?;
+        }
+
+        state.output.push(Command::Fan(255));
+
+        for heater in [Heater::Nozzle, Heater::Bed].iter() {
+            state.output.push(Command::Temperature {
+                heater: *heater,
+                celsius: 0.0,
+                wait: false,
+            });
+        }
+
+        state.output.push(Command::Raw(end.to_string()));
+        state.output.push(Command::CoolDown {
+            heater: Heater::Nozzle,
+            celsius: 90.0,
+        });
+        state.output.push(Command::Fan(0));
+        state.output.push(Command::Raw(end2.to_string()));
+        state
+            .output
+            .push(Command::ExtrusionMode { relative: false });
+        state.output.push(Command::Temperature {
+            heater: Heater::Nozzle,
+            celsius: 0.0,
+            wait: false,
+        });
+
+        state.output[layer_count] = Command::Comment(format!("LAYER_COUNT:{}", i));
+
+        let output = if profile.arc_fitting {
+            arc::fit(state.output, profile.arc_tolerance)
+        } else {
+            state.output
+        };
+
+        let origin = Vec4 {
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+            e: 0.0,
+        };
+        let estimate = estimate::simulate(&output, &origin, FEEDRATE, profile);
+
+        let output = if profile.progress {
+            estimate::add_progress(output, &estimate, profile.progress_interval)
+        } else {
+            output
+        };
+
+        let flavor: &'static dyn GcodeFlavor = profile.flavor.gcode();
+        let mut gcode = vec![];
+
+        // Binary files carry them in their own blocks
+        if !profile.binary_gcode {
+            for thumbnail in thumbnails.iter() {
+                gcode.extend(thumbnail.comments().into_iter().map(Command::Comment));
+            }
         }
 
-        println!("{}", end);
-        Some(())
+        gcode.push(Command::Comment(format!("FLAVOR:{}", flavor.name())));
+
+        gcode.extend(estimate.header().into_iter().map(Command::Comment));
+        gcode.extend(output);
+
+        let mut text = vec![];
+        let mut stdout = std::io::stdout();
+
+        toolpath::write(&mut text, &gcode, profile.relative_extrusion, flavor)?;
+
+        if profile.binary_gcode {
+            let content = bgcode::Content::new(&estimate, profile, layer_height, i, thumbnails);
+            let text = String::from_utf8(text)
+                .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+
+            bgcode::write(&mut stdout, &text, &content)?;
+        } else {
+            stdout.write_all(&text)?;
+        }
+
+        stdout.flush()?;
+        Ok(estimate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use stl_io::Vector;
+
+    use super::super::math::{Polygon, Segment};
+    use super::spiral;
+
+    fn square(size: f64, z: f64) -> Polygon {
+        let corners = [(0.0, 0.0), (size, 0.0), (size, size), (0.0, size)];
+
+        Polygon::new(
+            (0..4)
+                .map(|i| {
+                    let (a, b) = (corners[i], corners[(i + 1) % 4]);
+
+                    Segment {
+                        normal: Vector::new([0.0; 3]),
+                        vertices: [Vector::new([a.0, a.1, z]), Vector::new([b.0, b.1, z])],
+                    }
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn spiral_rises_by_a_layer() {
+        let (contour, next) = (square(10.0, 1.0), square(10.0, 1.2));
+        // From a corner, which the loop must not visit twice in a row
+        let points = spiral(&contour, Some(&next), (0.0, 0.0), 0.2).unwrap();
+        let (first, last) = (points[0], *points.last().unwrap());
+
+        assert_eq!(points.len(), 5);
+        // One loop, ending over where it started a layer higher
+        assert!((last[0] - first[0]).abs() < 1e-9 && (last[1] - first[1]).abs() < 1e-9);
+        assert!((last[2] - first[2] - 0.2).abs() < 1e-9);
+
+        for pair in points.windows(2) {
+            assert!(pair[1][2] > pair[0][2]);
+        }
     }
 }
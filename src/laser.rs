@@ -0,0 +1,619 @@
+use std::fmt::Write;
+use std::io;
+use std::str::FromStr;
+
+use super::math::region::{cross, normalize, sub, Point};
+use super::math::Region;
+use super::slice::Slice;
+
+// Gap between nested parts and around the sheet edges, in millimeters
+const SPACING: f64 = 2.0;
+// Height of the engraved layer numbers, shrunk down to this to fit small parts
+const TEXT_HEIGHT: f64 = 5.0;
+const MIN_TEXT_HEIGHT: f64 = 1.0;
+// Digits are this wide, and this far apart, relative to their height
+const DIGIT_WIDTH: f64 = 0.5;
+const DIGIT_GAP: f64 = 0.3;
+// Positions tried along each side of a part when looking for room to engrave
+const SEARCH_GRID: usize = 24;
+// Hairline strokes, which most laser software reads as vector cuts
+const STROKE_WIDTH: f64 = 0.1;
+
+const CUT: &str = "#ff0000";
+const ENGRAVE: &str = "#0000ff";
+
+// Seven segment strokes of every digit, through the corners top left, top
+// right, middle left, middle right, bottom left and bottom right
+const CORNERS: [Point; 6] = [
+    (0.0, 1.0),
+    (1.0, 1.0),
+    (0.0, 0.5),
+    (1.0, 0.5),
+    (0.0, 0.0),
+    (1.0, 0.0),
+];
+const DIGITS: [&[&[usize]]; 10] = [
+    &[&[0, 1, 5, 4, 0]],
+    &[&[1, 5]],
+    &[&[0, 1, 3, 2, 4, 5]],
+    &[&[0, 1, 5, 4], &[2, 3]],
+    &[&[0, 2, 3], &[1, 5]],
+    &[&[1, 0, 2, 3, 5, 4]],
+    &[&[1, 0, 4, 5, 3, 2]],
+    &[&[0, 1, 5]],
+    &[&[0, 1, 5, 4, 0], &[2, 3]],
+    &[&[3, 2, 0, 1, 5, 4]],
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Svg,
+    Dxf,
+}
+
+impl Format {
+    fn extension(&self) -> &'static str {
+        match self {
+            Format::Svg => "svg",
+            Format::Dxf => "dxf",
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_lowercase().as_str() {
+            "svg" => Ok(Format::Svg),
+            "dxf" => Ok(Format::Dxf),
+            _ => Err(format!("Unknown laser file format: {}", name)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub format: Format,
+    pub kerf: f64,
+    // Width and height of the sheets to nest the parts on, one file per layer without it
+    pub sheet: Option<(f64, f64)>,
+    pub engrave: bool,
+}
+
+// Outline of one piece of a layer ready to be cut, relative to its bounding box corner
+#[derive(Debug, Clone)]
+struct Part {
+    layer: usize,
+    cuts: Vec<Vec<Point>>,
+    engraving: Vec<Vec<Point>>,
+    // Where the bounding box corner was in the slice
+    origin: Point,
+    size: (f64, f64),
+}
+
+// Signed area, positive for counterclockwise boundaries
+fn area(boundary: &[Point]) -> f64 {
+    boundary
+        .iter()
+        .zip(boundary.iter().cycle().skip(1))
+        .map(|(a, b)| cross(*a, *b))
+        .sum::<f64>()
+        / 2.0
+}
+
+// Indices of the other boundaries enclosing the one at `index`
+fn containers(index: usize, region: &Region) -> Vec<usize> {
+    let p = region.boundaries[index][0];
+
+    (0..region.boundaries.len())
+        .filter(|i| *i != index)
+        .filter(|i| {
+            Region {
+                boundaries: vec![region.boundaries[*i].clone()],
+            }
+            .contains(p)
+        })
+        .collect()
+}
+
+// Boundary pushed `distance` millimeters away from the material, so the beam
+// burns outside the part and the part comes out at its true size
+fn offset(boundary: &[Point], distance: f64, hole: bool) -> Vec<Point> {
+    if distance <= 0.0 {
+        return boundary.to_vec();
+    }
+
+    // Left normals point inside counterclockwise boundaries
+    let away = if (area(boundary) > 0.0) == hole {
+        1.0
+    } else {
+        -1.0
+    };
+    let len = boundary.len();
+
+    boundary
+        .iter()
+        .enumerate()
+        .map(|(i, &point)| {
+            let prev = boundary[(i + len - 1) % len];
+            let next = boundary[(i + 1) % len];
+
+            let (n1, n2) = match (normalize(sub(point, prev)), normalize(sub(next, point))) {
+                (Some(a), Some(b)) => ((-a.1, a.0), (-b.1, b.0)),
+                _ => return point,
+            };
+
+            match normalize((n1.0 + n2.0, n1.1 + n2.1)) {
+                Some(bisector) => {
+                    // Lengthen the offset on sharp corners, within reason
+                    let miter = away * distance / (bisector.0 * n1.0 + bisector.1 * n1.1).max(0.25);
+
+                    (point.0 + bisector.0 * miter, point.1 + bisector.1 * miter)
+                }
+                None => point,
+            }
+        })
+        .collect()
+}
+
+// Strokes writing `number` with its center at `at`, `height` millimeters high
+fn text(number: usize, at: Point, height: f64) -> Vec<Vec<Point>> {
+    let digits: Vec<usize> = number
+        .to_string()
+        .chars()
+        .filter_map(|c| c.to_digit(10))
+        .map(|digit| digit as usize)
+        .collect();
+    let (width, gap) = (DIGIT_WIDTH * height, DIGIT_GAP * height);
+    let total = digits.len() as f64 * (width + gap) - gap;
+    let mut strokes = vec![];
+
+    for (i, digit) in digits.iter().enumerate() {
+        let left = at.0 - total / 2.0 + i as f64 * (width + gap);
+        let bottom = at.1 - height / 2.0;
+
+        for stroke in DIGITS[*digit].iter() {
+            strokes.push(
+                stroke
+                    .iter()
+                    .map(|&corner| {
+                        let (x, y) = CORNERS[corner];
+
+                        (left + x * width, bottom + y * height)
+                    })
+                    .collect(),
+            );
+        }
+    }
+
+    strokes
+}
+
+// Layer number engraved where the part is the widest, if it fits at all
+fn label(region: &Region, layer: usize) -> Vec<Vec<Point>> {
+    let (mut min, mut max) = (
+        (f64::INFINITY, f64::INFINITY),
+        (f64::NEG_INFINITY, f64::NEG_INFINITY),
+    );
+
+    for p in region.boundaries.iter().flatten() {
+        min = (min.0.min(p.0), min.1.min(p.1));
+        max = (max.0.max(p.0), max.1.max(p.1));
+    }
+
+    let best = (0..SEARCH_GRID * SEARCH_GRID)
+        .map(|i| {
+            let (u, v) = (
+                (i % SEARCH_GRID) as f64 + 0.5,
+                (i / SEARCH_GRID) as f64 + 0.5,
+            );
+
+            (
+                min.0 + (max.0 - min.0) * u / SEARCH_GRID as f64,
+                min.1 + (max.1 - min.1) * v / SEARCH_GRID as f64,
+            )
+        })
+        .filter(|p| region.contains(*p))
+        .map(|p| (p, region.clearance(p)))
+        .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+
+    let (at, clearance) = match best {
+        Some(best) => best,
+        None => return vec![],
+    };
+
+    // Largest text whose box stays within the clearance around its center
+    let digits = layer.to_string().len() as f64;
+    let ratio = digits * (DIGIT_WIDTH + DIGIT_GAP) - DIGIT_GAP;
+    let height = (2.0 * clearance / (ratio * ratio + 1.0).sqrt()).min(TEXT_HEIGHT);
+
+    if height < MIN_TEXT_HEIGHT {
+        return vec![];
+    }
+
+    text(layer, at, height)
+}
+
+// Pieces of a slice, each an outer contour with the holes right inside it
+fn parts(layer: usize, slice: &Slice, options: &Options) -> Vec<Part> {
+    let region = Region::new(&slice.polygons);
+    // Holes lie inside an odd number of boundaries, and belong to the smallest
+    let owners: Vec<(bool, usize)> = (0..region.boundaries.len())
+        .map(|i| {
+            let containers = containers(i, &region);
+            let owner = containers.iter().copied().min_by(|a, b| {
+                let (a, b) = (
+                    area(&region.boundaries[*a]).abs(),
+                    area(&region.boundaries[*b]).abs(),
+                );
+
+                a.partial_cmp(&b).unwrap()
+            });
+
+            match owner {
+                Some(owner) if containers.len() % 2 == 1 => (true, owner),
+                _ => (false, i),
+            }
+        })
+        .collect();
+
+    owners
+        .iter()
+        .enumerate()
+        .filter(|(_, (hole, _))| !hole)
+        .map(|(outer, _)| {
+            let members: Vec<usize> = (0..owners.len())
+                .filter(|i| owners[*i].1 == outer)
+                .collect();
+            let island = Region {
+                boundaries: members
+                    .iter()
+                    .map(|i| region.boundaries[*i].clone())
+                    .collect(),
+            };
+
+            let mut cuts: Vec<Vec<Point>> = members
+                .iter()
+                .map(|i| offset(&region.boundaries[*i], options.kerf / 2.0, owners[*i].0))
+                .collect();
+            let mut engraving = if options.engrave {
+                label(&island, layer)
+            } else {
+                vec![]
+            };
+
+            let (mut min, mut max) = (
+                (f64::INFINITY, f64::INFINITY),
+                (f64::NEG_INFINITY, f64::NEG_INFINITY),
+            );
+
+            for p in cuts.iter().flatten() {
+                min = (min.0.min(p.0), min.1.min(p.1));
+                max = (max.0.max(p.0), max.1.max(p.1));
+            }
+
+            for p in cuts.iter_mut().chain(engraving.iter_mut()).flatten() {
+                *p = sub(*p, min);
+            }
+
+            Part {
+                layer,
+                cuts,
+                engraving,
+                origin: min,
+                size: (max.0 - min.0, max.1 - min.1),
+            }
+        })
+        .collect()
+}
+
+// Row of parts along a sheet, as high as its first and tallest part
+#[derive(Debug, Clone)]
+struct Shelf {
+    bottom: f64,
+    height: f64,
+    filled: f64,
+}
+
+#[derive(Debug, Clone)]
+struct Sheet {
+    shelves: Vec<Shelf>,
+    // Parts and where their bounding box corner goes
+    placements: Vec<(usize, Point)>,
+}
+
+// First fit on shelves, tallest parts first, a new sheet starting when no
+// shelf has room left and no new one fits
+fn nest(parts: &[Part], size: (f64, f64)) -> io::Result<Vec<Sheet>> {
+    let mut sheets: Vec<Sheet> = vec![];
+    let mut order: Vec<usize> = (0..parts.len()).collect();
+
+    order.sort_by(|a, b| parts[*b].size.1.partial_cmp(&parts[*a].size.1).unwrap());
+
+    for index in order {
+        let (width, height) = parts[index].size;
+
+        if width + 2.0 * SPACING > size.0 || height + 2.0 * SPACING > size.1 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Layer {} is {:.1}x{:.1} mm, too large for a {}x{} mm sheet",
+                    parts[index].layer, width, height, size.0, size.1
+                ),
+            ));
+        }
+
+        let placed = sheets.iter_mut().any(|sheet| {
+            for shelf in sheet.shelves.iter_mut() {
+                if height <= shelf.height && shelf.filled + width + SPACING <= size.0 {
+                    sheet.placements.push((index, (shelf.filled, shelf.bottom)));
+                    shelf.filled += width + SPACING;
+                    return true;
+                }
+            }
+
+            let top = sheet
+                .shelves
+                .last()
+                .map_or(SPACING, |shelf| shelf.bottom + shelf.height + SPACING);
+
+            if top + height + SPACING <= size.1 {
+                sheet.placements.push((index, (SPACING, top)));
+                sheet.shelves.push(Shelf {
+                    bottom: top,
+                    height,
+                    filled: SPACING + width + SPACING,
+                });
+                return true;
+            }
+
+            false
+        });
+
+        if !placed {
+            sheets.push(Sheet {
+                shelves: vec![Shelf {
+                    bottom: SPACING,
+                    height,
+                    filled: SPACING + width + SPACING,
+                }],
+                placements: vec![(index, (SPACING, SPACING))],
+            });
+        }
+    }
+
+    Ok(sheets)
+}
+
+fn svg(size: (f64, f64), parts: &[(&Part, Point)]) -> String {
+    let mut body = String::new();
+    // SVG Y goes down
+    let point = |p: &Point, at: &Point| (at.0 + p.0, size.1 - at.1 - p.1);
+
+    for (part, at) in parts.iter() {
+        writeln!(body, "<g id=\"layer-{}\">", part.layer).unwrap();
+
+        for (strokes, color, closing) in [(&part.cuts, CUT, " Z"), (&part.engraving, ENGRAVE, "")] {
+            for stroke in strokes.iter() {
+                let d: Vec<String> = stroke
+                    .iter()
+                    .map(|p| {
+                        let (x, y) = point(p, at);
+
+                        format!("{:.3} {:.3}", x, y)
+                    })
+                    .collect();
+
+                writeln!(
+                    body,
+                    "<path d=\"M {}{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"{}\"/>",
+                    d.join(" L "),
+                    closing,
+                    color,
+                    STROKE_WIDTH
+                )
+                .unwrap();
+            }
+        }
+
+        writeln!(body, "</g>").unwrap();
+    }
+
+    format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 {:.3} {:.3}\" width=\"{:.3}mm\" height=\"{:.3}mm\">\n{}</svg>\n",
+        size.0, size.1, size.0, size.1, body
+    )
+}
+
+// AutoCAD R12 entities, which every laser software imports
+fn dxf(parts: &[(&Part, Point)]) -> String {
+    let mut out = String::new();
+
+    // Millimeters, with a layer to cut and one to engrave
+    out.push_str("0\nSECTION\n2\nHEADER\n9\n$INSUNITS\n70\n4\n0\nENDSEC\n");
+    out.push_str("0\nSECTION\n2\nTABLES\n0\nTABLE\n2\nLAYER\n70\n2\n");
+    out.push_str("0\nLAYER\n2\nCUT\n70\n0\n62\n1\n6\nCONTINUOUS\n");
+    out.push_str("0\nLAYER\n2\nENGRAVE\n70\n0\n62\n5\n6\nCONTINUOUS\n");
+    out.push_str("0\nENDTAB\n0\nENDSEC\n");
+    out.push_str("0\nSECTION\n2\nENTITIES\n");
+
+    for (part, at) in parts.iter() {
+        for (strokes, layer, closed) in [(&part.cuts, "CUT", 1), (&part.engraving, "ENGRAVE", 0)] {
+            for stroke in strokes.iter() {
+                write!(
+                    out,
+                    "0\nPOLYLINE\n8\n{}\n66\n1\n70\n{}\n10\n0.0\n20\n0.0\n30\n0.0\n",
+                    layer, closed
+                )
+                .unwrap();
+
+                for p in stroke.iter() {
+                    write!(
+                        out,
+                        "0\nVERTEX\n8\n{}\n10\n{:.4}\n20\n{:.4}\n30\n0.0\n",
+                        layer,
+                        at.0 + p.0,
+                        at.1 + p.1
+                    )
+                    .unwrap();
+                }
+
+                write!(out, "0\nSEQEND\n8\n{}\n", layer).unwrap();
+            }
+        }
+    }
+
+    out.push_str("0\nENDSEC\n0\nEOF\n");
+    out
+}
+
+fn document(format: Format, size: (f64, f64), parts: &[(&Part, Point)]) -> String {
+    match format {
+        Format::Svg => svg(size, parts),
+        Format::Dxf => dxf(parts),
+    }
+}
+
+// Writes the closed contours of every slice to `dir`, either one file per
+// layer or nested on sheets, and returns how many files were written
+pub fn export(slices: &[Slice], options: &Options, dir: &str) -> io::Result<usize> {
+    let parts: Vec<Part> = slices
+        .iter()
+        .enumerate()
+        .flat_map(|(i, slice)| parts(i + 1, slice, options))
+        .collect();
+    let extension = options.format.extension();
+
+    std::fs::create_dir_all(dir)?;
+
+    match options.sheet {
+        Some(sheet) => {
+            let sheets = nest(&parts, sheet)?;
+
+            for (i, nested) in sheets.iter().enumerate() {
+                let placed: Vec<(&Part, Point)> = nested
+                    .placements
+                    .iter()
+                    .map(|(index, at)| (&parts[*index], *at))
+                    .collect();
+
+                std::fs::write(
+                    format!("{}/sheet_{:03}.{}", dir, i + 1, extension),
+                    document(options.format, sheet, &placed),
+                )?;
+            }
+
+            Ok(sheets.len())
+        }
+        None => {
+            let mut layers: Vec<Vec<&Part>> = vec![];
+
+            for part in parts.iter() {
+                match layers.last_mut() {
+                    Some(layer) if layer[0].layer == part.layer => layer.push(part),
+                    _ => layers.push(vec![part]),
+                }
+            }
+
+            // Pieces of a layer stay where they are relative to each other
+            for layer in layers.iter() {
+                let (mut min, mut max) = (
+                    (f64::INFINITY, f64::INFINITY),
+                    (f64::NEG_INFINITY, f64::NEG_INFINITY),
+                );
+
+                for part in layer.iter() {
+                    min = (min.0.min(part.origin.0), min.1.min(part.origin.1));
+                    max = (
+                        max.0.max(part.origin.0 + part.size.0),
+                        max.1.max(part.origin.1 + part.size.1),
+                    );
+                }
+
+                let placed: Vec<(&Part, Point)> = layer
+                    .iter()
+                    .map(|part| {
+                        let at = sub(part.origin, min);
+
+                        (*part, (at.0 + SPACING, at.1 + SPACING))
+                    })
+                    .collect();
+                let size = (max.0 - min.0 + 2.0 * SPACING, max.1 - min.1 + 2.0 * SPACING);
+
+                std::fs::write(
+                    format!("{}/layer_{:04}.{}", dir, layer[0].layer, extension),
+                    document(options.format, size, &placed),
+                )?;
+            }
+
+            Ok(layers.len())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use stl_io::Vector;
+
+    use super::super::math::{Polygon, Segment};
+    use super::super::slice::Slice;
+    use super::{area, parts, Format, Options, Point};
+
+    // Square from its corners, clockwise when `clockwise`
+    fn square(x: f64, y: f64, size: f64, clockwise: bool) -> Polygon {
+        let mut corners = [(x, y), (x + size, y), (x + size, y + size), (x, y + size)];
+
+        if clockwise {
+            corners.reverse();
+        }
+
+        Polygon::new(
+            (0..4)
+                .map(|i| {
+                    let (a, b) = (corners[i], corners[(i + 1) % 4]);
+
+                    Segment {
+                        normal: Vector::new([0.0; 3]),
+                        vertices: [Vector::new([a.0, a.1, 0.0]), Vector::new([b.0, b.1, 0.0])],
+                    }
+                })
+                .collect(),
+        )
+    }
+
+    fn width(boundary: &[Point]) -> f64 {
+        let xs = boundary.iter().map(|p| p.0);
+
+        xs.clone().fold(f64::NEG_INFINITY, f64::max) - xs.fold(f64::INFINITY, f64::min)
+    }
+
+    #[test]
+    fn kerf_offset() {
+        let options = Options {
+            format: Format::Svg,
+            kerf: 0.4,
+            sheet: None,
+            engrave: false,
+        };
+
+        // Whichever way the contours wind, the beam stays off the material
+        for (outer, hole) in [(false, true), (false, false), (true, true)] {
+            let slice = Slice {
+                height: 0.0,
+                polygons: vec![square(0.0, 0.0, 20.0, outer), square(8.0, 8.0, 4.0, hole)],
+            };
+            let parts = parts(1, &slice, &options);
+
+            assert_eq!(parts.len(), 1);
+
+            let mut cuts = parts[0].cuts.clone();
+
+            cuts.sort_by(|a, b| area(b).abs().partial_cmp(&area(a).abs()).unwrap());
+
+            assert!((width(&cuts[0]) - 20.4).abs() < 1e-9);
+            assert!((width(&cuts[1]) - 3.6).abs() < 1e-9);
+            assert!((parts[0].size.0 - 20.4).abs() < 1e-9);
+        }
+    }
+}
@@ -1,42 +1,200 @@
-use clap::{App, Arg};
-use std::fs::OpenOptions;
-use stl_io::read_stl;
+use clap::{App, Arg, ArgMatches};
+use std::io::Write;
 
+mod analyze;
+mod arc;
 pub mod ast;
+mod bgcode;
+mod checksum;
+mod comb;
+mod deflate;
+mod estimate;
+mod fill;
+mod flavor;
 mod gcode;
+mod laser;
 mod math;
+mod mesh;
+mod png;
+mod profile;
+mod resin;
+mod script;
 mod slice;
 mod stage;
+mod svg;
+mod thumbnail;
+mod toolpath;
+mod xml;
+mod zip;
 
-use ast::{Axis, Transform};
+use ast::{Axis, Centering, Transform};
 use gcode::Printer;
-use math::{
-    Center, Displace, Highest, Homothety, Lowest, RotateX, RotateY, RotateZ, Scale, X, Y, Z,
-};
+use math::{Affine, Center, DropToBed, Highest, Lowest, Matrix, Quaternion, X, Y, Z};
+use profile::Profile;
 use slice::{IterSlices, Slice};
 use stage::{IterStages, Stage};
+use thumbnail::Thumbnail;
 
 use lalrpop_util::lalrpop_mod;
 
 lalrpop_mod!(pub transform);
 
-fn transformations(mut stl: stl_io::IndexedMesh, raw: &str) -> stl_io::IndexedMesh {
-    let transformations: Vec<Transform> = transform::TransformsParser::new().parse(raw).unwrap();
+// Unit vector along `axis`
+fn direction(axis: &Axis) -> [f64; 3] {
+    match axis {
+        Axis::X => [1.0, 0.0, 0.0],
+        Axis::Y => [0.0, 1.0, 0.0],
+        Axis::Z => [0.0, 0.0, 1.0],
+    }
+}
+
+// Orientation found by `name`, told so that it can be written down instead
+fn rotation(name: &str, orientation: mesh::Orientation) -> Matrix {
+    eprintln!(
+        "{} chose {}: {:.1}mm2 of overhangs, {:.2}cm3 of supports, {:.1}mm high",
+        name,
+        orientation,
+        orientation.overhang,
+        orientation.support / 1000.0,
+        orientation.height
+    );
+
+    let [x, y, z] = orientation.center;
+
+    Matrix::translation(-x, -y, -z)
+        .then(&Matrix::rotation(&Quaternion::from_axis_angle(
+            orientation.axis,
+            orientation.angle,
+        )))
+        .then(&Matrix::translation(x, y, z))
+}
+
+// The origin is the middle of the bed, which rotations, scales and mirrors go
+// around. Transforms known in advance add up to one matrix, applied once or
+// before those depending on where the model stands.
+fn transformations(
+    mut stl: stl_io::IndexedMesh,
+    transformations: Vec<Transform>,
+) -> stl_io::IndexedMesh {
+    let mut pending = Matrix::IDENTITY;
 
     for transform in transformations.into_iter() {
-        stl = match transform {
-            Transform::Rotate(Axis::X, theta) => stl.rotate_x(theta),
-            Transform::Rotate(Axis::Y, theta) => stl.rotate_y(theta),
-            Transform::Rotate(Axis::Z, theta) => stl.rotate_z(theta),
-            Transform::Move(x, y, z) => stl.displace(x, y, z),
-            Transform::Scale(x, y, z) => stl.scale(x, y, z),
-            Transform::Homothety(v) => stl.homothety(v),
-            Transform::Center => stl.center(),
-            _ => stl,
+        pending = match transform {
+            Transform::Rotate(axis, theta) => pending.then(&Matrix::rotation(
+                &Quaternion::from_axis_angle(direction(&axis), theta),
+            )),
+            Transform::RotateAxis(x, y, z, theta) => pending.then(&Matrix::rotation(
+                &Quaternion::from_axis_angle([x, y, z], theta),
+            )),
+            Transform::Move(x, y, z) => pending.then(&Matrix::translation(x, y, z)),
+            Transform::Scale(x, y, z) => pending.then(&Matrix::scaling(x, y, z)),
+            Transform::Homothety(v) => pending.then(&Matrix::scaling(v, v, v)),
+            Transform::Mirror(axis) => {
+                let [x, y, z] = direction(&axis).map(|value| 1.0 - 2.0 * value);
+
+                pending.then(&Matrix::scaling(x, y, z))
+            }
+            Transform::ScaleTo(axis, size) => {
+                stl = stl.affine(&pending);
+
+                let current = mesh::Properties::new(&stl).size()[axis.clone() as usize];
+
+                if current > 0.0 {
+                    let factor = size / current;
+
+                    stl = stl.affine(&Matrix::scaling(factor, factor, factor));
+                } else {
+                    eprintln!("The model is flat along {:?}, scale_to left it as is", axis);
+                }
+
+                Matrix::IDENTITY
+            }
+            Transform::Center(centering) => {
+                stl = stl.affine(&pending);
+                stl = match centering {
+                    Centering::BoundingBox | Centering::Bed => stl.center(0.0, 0.0),
+                    Centering::Mass => stl.center_mass(0.0, 0.0),
+                    Centering::Axis(axis) => stl.center_axis(axis as usize),
+                };
+                Matrix::IDENTITY
+            }
+            Transform::DropToBed => {
+                stl = stl.affine(&pending).drop_to_bed();
+                Matrix::IDENTITY
+            }
+            Transform::LayFlat => {
+                stl = stl.affine(&pending);
+                rotation("lay_flat", mesh::lay_flat(&stl))
+            }
+            Transform::AutoOrient => {
+                stl = stl.affine(&pending);
+                rotation("auto_orient", mesh::auto_orient(&stl))
+            }
+            Transform::PlaceOnBed => {
+                stl = stl.affine(&pending).center(0.0, 0.0).drop_to_bed();
+                Matrix::IDENTITY
+            }
         }
     }
 
-    stl
+    stl.affine(&pending)
+}
+
+// Parse an optional numeric argument, exiting on malformed values
+fn parse_value<T: std::str::FromStr>(matches: &ArgMatches, name: &str) -> Option<T> {
+    matches.value_of(name).map(|raw| {
+        raw.parse()
+            .unwrap_or_else(|_| panic!("Error: Invalid {}. Expected: number", name))
+    })
+}
+
+// Parse a WIDTHxHEIGHT image size, exiting on malformed values
+fn parse_size(raw: &str) -> (u16, u16) {
+    let size = raw.split_once('x').and_then(|(width, height)| {
+        match (width.parse::<u16>(), height.parse::<u16>()) {
+            (Ok(width), Ok(height)) if width > 0 && height > 0 => Some((width, height)),
+            _ => None,
+        }
+    });
+
+    size.unwrap_or_else(|| {
+        panic!(
+            "Error: Invalid thumbnail size {}. Expected: WIDTHxHEIGHT",
+            raw
+        )
+    })
+}
+
+// Parse a WIDTHxHEIGHT size, exiting on malformed values
+fn parse_area(raw: &str, name: &str) -> (f64, f64) {
+    let size = raw.split_once('x').and_then(|(width, height)| {
+        match (width.parse::<f64>(), height.parse::<f64>()) {
+            (Ok(width), Ok(height)) if width > 0.0 && height > 0.0 => Some((width, height)),
+            _ => None,
+        }
+    });
+
+    size.unwrap_or_else(|| panic!("Error: Invalid {} {}. Expected: WIDTHxHEIGHT", name, raw))
+}
+
+// Parse a WIDTHxDEPTHxHEIGHT volume, exiting on malformed values
+fn parse_volume(raw: &str) -> [f64; 3] {
+    let sizes: Vec<Option<f64>> = raw
+        .split('x')
+        .map(|size| {
+            size.parse()
+                .ok()
+                .filter(|size: &f64| size.is_finite() && *size > 0.0)
+        })
+        .collect();
+
+    match sizes[..] {
+        [Some(width), Some(depth), Some(height)] => [width, depth, height],
+        _ => panic!(
+            "Error: Invalid build volume {}. Expected: WIDTHxDEPTHxHEIGHT",
+            raw
+        ),
+    }
 }
 
 fn main() -> anyhow::Result<()> {
@@ -44,10 +202,110 @@ fn main() -> anyhow::Result<()> {
         .version("1.0")
         .author("Hugo S. <hsabouri@student.42.fr>")
         .about("Simple and fast 3D printing Slicer made in Rust")
+        .subcommand_negates_reqs(true)
+        .arg(
+            Arg::new("build_volume")
+                .takes_value(true)
+                .long("build-volume")
+                .global(true)
+                .help("Printable width, depth and height, e.g. 205x205x205"),
+        )
+        .subcommand(
+            App::new("analyze")
+                .about("Report the extents, extrusion and print time of a G-code file")
+                .arg(
+                    Arg::new("file")
+                        .required(true)
+                        .help(".gcode file to analyze")
+                        .value_name("FILE"),
+                ),
+        )
+        .subcommand(
+            App::new("decode")
+                .about("Print the text G-code of a binary G-code file")
+                .arg(
+                    Arg::new("file")
+                        .required(true)
+                        .help(".bgcode file to decode")
+                        .value_name("FILE"),
+                ),
+        )
+        .subcommand(
+            App::new("info")
+                .about("Report the size, volume, surface area and centre of mass of a mesh")
+                .arg(
+                    Arg::new("file")
+                        .required(true)
+                        .help("Mesh to measure, as STL, OBJ, PLY or 3MF")
+                        .value_name("FILE"),
+                )
+                .arg(
+                    Arg::new("json")
+                        .long("json")
+                        .help("Print JSON, with the properties of every object too"),
+                ),
+        )
+        .subcommand(
+            App::new("check")
+                .about("Report the defects of a mesh, and write it repaired")
+                .arg(
+                    Arg::new("file")
+                        .required(true)
+                        .help("Mesh to check, as STL, OBJ, PLY or 3MF")
+                        .value_name("FILE"),
+                )
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .takes_value(true)
+                        .value_name("OUT")
+                        .help("File to write the repaired mesh to, .stl or .3mf"),
+                )
+                .arg(
+                    Arg::new("ascii")
+                        .long("ascii")
+                        .help("Write ASCII instead of binary STL"),
+                ),
+        )
+        .subcommand(
+            App::new("transform")
+                .about("Write a model transformed, as binary or ASCII STL or 3MF")
+                .arg(
+                    Arg::new("file")
+                        .required(true)
+                        .help("Mesh to transform, as STL, OBJ, PLY or 3MF")
+                        .value_name("FILE"),
+                )
+                .arg(
+                    Arg::new("transform")
+                        .short('t')
+                        .long("transform")
+                        .takes_value(true)
+                        .help("Transform to apply, as for slicing"),
+                )
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .takes_value(true)
+                        .required(true)
+                        .value_name("OUT")
+                        .help("File to write, .stl or .3mf"),
+                )
+                .arg(
+                    Arg::new("ascii")
+                        .long("ascii")
+                        .help("Write ASCII instead of binary STL"),
+                ),
+        )
         .arg(
             Arg::new("model")
                 .required(true)
-                .help(".stl file to slice")
+                .help(
+                    "Mesh to slice, as STL, OBJ, PLY or 3MF. 3MF objects are all sliced with \
+                     these options, their own settings are ignored",
+                )
                 .value_name("MODEL"),
         )
         .arg(
@@ -63,15 +321,361 @@ fn main() -> anyhow::Result<()> {
                 .short('t')
                 .long("transform")
                 .takes_value(true)
-                .help("Transform the model before slicing"),
+                .help("Transform the model before slicing, around the middle of the bed"),
+        )
+        .arg(
+            Arg::new("no_drop_to_bed")
+                .long("no-drop-to-bed")
+                .help("Slice the model where it stands instead of putting its lowest point at Z = 0"),
+        )
+        .arg(
+            Arg::new("repair")
+                .long("repair")
+                .help("Merge vertices, fix the winding and fill small holes before slicing"),
+        )
+        .arg(
+            Arg::new("avoid_crossing_perimeters")
+                .long("avoid-crossing-perimeters")
+                .help("Keep travel moves inside the part, retracting only when leaving it"),
+        )
+        .arg(
+            Arg::new("spiral_vase")
+                .long("spiral-vase")
+                .help("Print the outer wall as a single continuous spiral after the bottom layers"),
+        )
+        .arg(
+            Arg::new("bottom_layers")
+                .takes_value(true)
+                .long("bottom-layers")
+                .default_value("3")
+                .help("Number of solid bottom layers in spiral vase mode"),
+        )
+        .arg(
+            Arg::new("arc_fitting")
+                .long("arc-fitting")
+                .help("Emit G2/G3 arcs for curved paths, the firmware must support them"),
+        )
+        .arg(
+            Arg::new("flavor")
+                .takes_value(true)
+                .long("flavor")
+                .possible_values(["marlin", "reprapfirmware", "klipper", "smoothie"])
+                .help("Firmware the G-code is written for [default: marlin]"),
+        )
+        .arg(
+            Arg::new("binary")
+                .long("binary")
+                .help("Write binary G-code (.bgcode) instead of text"),
+        )
+        .arg(
+            Arg::new("export_svg")
+                .takes_value(true)
+                .long("export-svg")
+                .value_name("DIR")
+                .help(
+                    "Draw every layer as SVG in this directory, with an index.html to browse them",
+                ),
+        )
+        .arg(
+            Arg::new("resin")
+                .takes_value(true)
+                .long("resin")
+                .value_name("OUT")
+                .help("Write the layers as MSLA printer images instead of G-code, in a .zip package or else a directory"),
+        )
+        .arg(
+            Arg::new("resolution")
+                .takes_value(true)
+                .long("resolution")
+                .value_name("WIDTHxHEIGHT")
+                .help("Resolution of the MSLA printer display in pixels"),
+        )
+        .arg(
+            Arg::new("display")
+                .takes_value(true)
+                .long("display")
+                .value_name("WIDTHxHEIGHT")
+                .help("Size of the MSLA printer display in millimeters"),
+        )
+        .arg(
+            Arg::new("exposure")
+                .takes_value(true)
+                .long("exposure")
+                .help("Exposure time of a resin layer in seconds"),
+        )
+        .arg(
+            Arg::new("bottom_exposure")
+                .takes_value(true)
+                .long("bottom-exposure")
+                .help("Exposure time of the first resin layers in seconds"),
+        )
+        .arg(
+            Arg::new("bottom_exposure_layers")
+                .takes_value(true)
+                .long("bottom-exposure-layers")
+                .help("Number of resin layers exposed for the bottom exposure time"),
+        )
+        .arg(
+            Arg::new("lift_height")
+                .takes_value(true)
+                .long("lift-height")
+                .help("Distance the build plate rises between resin layers in millimeters"),
+        )
+        .arg(
+            Arg::new("lift_speed")
+                .takes_value(true)
+                .long("lift-speed")
+                .help("Speed of the build plate rising between resin layers in mm/min"),
+        )
+        .arg(
+            Arg::new("antialiasing")
+                .takes_value(true)
+                .long("antialiasing")
+                .help("Samples along each side of a resin layer pixel, 1 for sharp edges"),
+        )
+        .arg(
+            Arg::new("laser")
+                .takes_value(true)
+                .long("laser")
+                .value_name("DIR")
+                .help("Write the contours of every layer as laser cutting files in this directory instead of G-code, the layer height being the sheet thickness"),
+        )
+        .arg(
+            Arg::new("laser_format")
+                .takes_value(true)
+                .long("laser-format")
+                .possible_values(["svg", "dxf"])
+                .help("Laser cutting file format, SVG by default"),
+        )
+        .arg(
+            Arg::new("kerf")
+                .takes_value(true)
+                .long("kerf")
+                .help("Width of the laser cut in millimeters, half of it is added around the parts"),
+        )
+        .arg(
+            Arg::new("sheet")
+                .takes_value(true)
+                .long("sheet")
+                .value_name("WIDTHxHEIGHT")
+                .help("Nest the laser cut parts on sheets of this size in millimeters"),
+        )
+        .arg(
+            Arg::new("engrave")
+                .long("engrave")
+                .help("Engrave the layer number on every laser cut part"),
+        )
+        .arg(
+            Arg::new("thumbnails")
+                .takes_value(true)
+                .long("thumbnails")
+                .multiple_occurrences(true)
+                .use_value_delimiter(true)
+                .help("Embed preview images of these sizes, e.g. 32x32,300x300"),
+        )
+        .arg(
+            Arg::new("firmware_retraction")
+                .long("firmware-retraction")
+                .help("Retract with G10/G11 using the retraction settings of the firmware"),
+        )
+        .arg(
+            Arg::new("pause_at_layer")
+                .takes_value(true)
+                .long("pause-at-layer")
+                .multiple_occurrences(true)
+                .use_value_delimiter(true)
+                .help("Pause the print before these layers, numbered from 1"),
+        )
+        .arg(
+            Arg::new("relative_extrusion")
+                .long("relative-extrusion")
+                .help("Write E values relative to the previous move instead of absolute"),
+        )
+        .arg(
+            Arg::new("temperature")
+                .takes_value(true)
+                .long("temperature")
+                .help("Nozzle temperature in °C after the first layer"),
+        )
+        .arg(
+            Arg::new("first_layer_temperature")
+                .takes_value(true)
+                .long("first-layer-temperature")
+                .help("Nozzle temperature in °C for the first layer"),
+        )
+        .arg(
+            Arg::new("bed_temperature")
+                .takes_value(true)
+                .long("bed-temperature")
+                .help("Bed temperature in °C after the first layer, 0 to leave it off"),
+        )
+        .arg(
+            Arg::new("first_layer_bed_temperature")
+                .takes_value(true)
+                .long("first-layer-bed-temperature")
+                .help("Bed temperature in °C for the first layer, 0 to leave it off"),
+        )
+        .arg(
+            Arg::new("fan_speed")
+                .takes_value(true)
+                .long("fan-speed")
+                .help("Fan speed (0-255) once fully ramped up"),
+        )
+        .arg(
+            Arg::new("fan_start_layer")
+                .takes_value(true)
+                .long("fan-start-layer")
+                .help("Layer the fan starts at"),
+        )
+        .arg(
+            Arg::new("fan_full_layer")
+                .takes_value(true)
+                .long("fan-full-layer")
+                .help("Layer the fan reaches full speed at"),
+        )
+        .arg(
+            Arg::new("bridge_fan_speed")
+                .takes_value(true)
+                .long("bridge-fan-speed")
+                .help("Fan speed (0-255) while printing bridges"),
+        )
+        .arg(
+            Arg::new("overhang_fan_speed")
+                .takes_value(true)
+                .long("overhang-fan-speed")
+                .help("Fan speed (0-255) while printing overhangs"),
+        )
+        .arg(
+            Arg::new("min_layer_time")
+                .takes_value(true)
+                .long("min-layer-time")
+                .help("Slow down layers faster than this many seconds, boosting the fan"),
+        )
+        .arg(
+            Arg::new("no_progress")
+                .long("no-progress")
+                .help("Don't write M73 progress markers"),
+        )
+        .arg(
+            Arg::new("progress_interval")
+                .takes_value(true)
+                .long("progress-interval")
+                .help("Also write progress every this many seconds within long layers"),
+        )
+        .arg(
+            Arg::new("filament_diameter")
+                .takes_value(true)
+                .long("filament-diameter")
+                .help("Filament diameter in millimeters"),
+        )
+        .arg(
+            Arg::new("filament_density")
+                .takes_value(true)
+                .long("filament-density")
+                .help("Filament density in g/cm³, for the weight estimate"),
+        )
+        .arg(
+            Arg::new("filament_cost")
+                .takes_value(true)
+                .long("filament-cost")
+                .help("Filament cost per kilogram, for the cost estimate"),
         )
         .get_matches();
 
+    let defaults = Profile::default();
+    let build_volume = matches
+        .value_of("build_volume")
+        .map(parse_volume)
+        .unwrap_or(defaults.build_volume);
+
+    match matches.subcommand() {
+        Some(("analyze", matches)) => {
+            let path = matches.value_of("file").unwrap();
+            let profile = Profile {
+                build_volume,
+                ..Profile::default()
+            };
+
+            print!(
+                "{}",
+                analyze::analyze(&std::fs::read_to_string(path)?, &profile)
+            );
+            return Ok(());
+        }
+        Some(("decode", matches)) => {
+            let path = matches.value_of("file").unwrap();
+            let gcode = bgcode::decode(&std::fs::read(path)?)?;
+
+            std::io::stdout().write_all(gcode.as_bytes())?;
+            return Ok(());
+        }
+        Some(("info", matches)) => {
+            let path = matches.value_of("file").unwrap();
+            let parts = mesh::load(path)?;
+            let objects: Vec<(String, mesh::Properties)> = parts
+                .iter()
+                .map(|part| (part.name.clone(), mesh::Properties::new(&part.mesh)))
+                .collect();
+            let model = mesh::Properties::new(&mesh::merge(parts));
+
+            if !matches.is_present("json") {
+                print!("{}", model);
+                return Ok(());
+            }
+
+            let objects: Vec<String> = objects
+                .iter()
+                .map(|(name, properties)| format!("    {}", properties.json(name, "    ")))
+                .collect();
+
+            println!(
+                "{{\n  \"model\": {},\n  \"objects\": [\n{}\n  ]\n}}",
+                model.json(path, "  "),
+                objects.join(",\n")
+            );
+            return Ok(());
+        }
+        Some(("check", matches)) => {
+            let stl = mesh::merge(mesh::load(matches.value_of("file").unwrap())?);
+
+            print!("{}", mesh::check(&stl));
+
+            if let Some(out) = matches.value_of("output") {
+                let (repaired, repairs) = mesh::repair(&stl);
+
+                mesh::save(&repaired, out, matches.is_present("ascii"))?;
+                println!("{}", repairs);
+                println!("Wrote {} triangles to {}", repaired.faces.len(), out);
+            }
+
+            return Ok(());
+        }
+        Some(("transform", matches)) => {
+            let transforms = matches
+                .value_of("transform")
+                .map(script::parse)
+                .transpose()?;
+            let mut stl = mesh::merge(mesh::load(matches.value_of("file").unwrap())?);
+            let out = matches.value_of("output").unwrap();
+
+            if let Some(transforms) = transforms {
+                stl = transformations(stl, transforms);
+            }
+
+            if mesh::orient(&mut stl) {
+                eprintln!("The mesh was inside out, its faces were turned");
+            }
+
+            mesh::save(&stl, out, matches.is_present("ascii"))?;
+            println!("Wrote {} triangles to {}", stl.faces.len(), out);
+            return Ok(());
+        }
+        _ => (),
+    }
+
     let file_path = matches
         .value_of("model")
-        .expect("Error: No .stl file. Expected: String");
-
-    let mut file = OpenOptions::new().read(true).open(file_path).unwrap();
+        .expect("Error: No model file. Expected: String");
 
     let layer_height: f64 = matches
         .value_of("layer_height")
@@ -79,12 +683,131 @@ fn main() -> anyhow::Result<()> {
         .parse()
         .expect("Error: Invalid layer_height. Expected: float");
 
-    let mut stl = read_stl(&mut file).unwrap();
+    let temperature = parse_value(&matches, "temperature").unwrap_or(defaults.temperature);
+    let bed_temperature =
+        parse_value(&matches, "bed_temperature").unwrap_or(defaults.bed_temperature);
+
+    let profile = Profile {
+        avoid_crossing_perimeters: matches.is_present("avoid_crossing_perimeters"),
+        spiral_vase: matches.is_present("spiral_vase"),
+        bottom_layers: parse_value(&matches, "bottom_layers").unwrap_or(defaults.bottom_layers),
+        arc_fitting: matches.is_present("arc_fitting"),
+        firmware_retraction: matches.is_present("firmware_retraction"),
+        binary_gcode: matches.is_present("binary"),
+        build_volume,
+        svg_dir: matches.value_of("export_svg").map(String::from),
+        thumbnails: matches
+            .values_of("thumbnails")
+            .map(|values| values.map(parse_size).collect())
+            .unwrap_or_default(),
+        flavor: matches
+            .value_of("flavor")
+            .map(|raw| raw.parse().unwrap())
+            .unwrap_or(defaults.flavor),
+        pause_layers: matches
+            .values_of("pause_at_layer")
+            .map(|values| {
+                values
+                    .map(|raw| {
+                        raw.parse().unwrap_or_else(|_| {
+                            panic!("Error: Invalid pause_at_layer. Expected: number")
+                        })
+                    })
+                    .collect()
+            })
+            .unwrap_or_default(),
+        relative_extrusion: matches.is_present("relative_extrusion"),
+        temperature,
+        first_layer_temperature: parse_value(&matches, "first_layer_temperature")
+            .unwrap_or(temperature),
+        bed_temperature,
+        first_layer_bed_temperature: parse_value(&matches, "first_layer_bed_temperature")
+            .unwrap_or(bed_temperature),
+        fan_speed: parse_value(&matches, "fan_speed").unwrap_or(defaults.fan_speed),
+        fan_start_layer: parse_value(&matches, "fan_start_layer")
+            .unwrap_or(defaults.fan_start_layer),
+        fan_full_layer: parse_value(&matches, "fan_full_layer").unwrap_or(defaults.fan_full_layer),
+        bridge_fan_speed: parse_value(&matches, "bridge_fan_speed"),
+        overhang_fan_speed: parse_value(&matches, "overhang_fan_speed"),
+        min_layer_time: parse_value(&matches, "min_layer_time").unwrap_or(defaults.min_layer_time),
+        filament_diameter: parse_value(&matches, "filament_diameter")
+            .unwrap_or(defaults.filament_diameter),
+        filament_density: parse_value(&matches, "filament_density")
+            .unwrap_or(defaults.filament_density),
+        filament_cost: parse_value(&matches, "filament_cost").unwrap_or(defaults.filament_cost),
+        progress: !matches.is_present("no_progress"),
+        progress_interval: parse_value(&matches, "progress_interval")
+            .unwrap_or(defaults.progress_interval),
+        ..defaults
+    };
+
+    if let Some(dir) = profile.svg_dir.as_ref() {
+        std::fs::create_dir_all(dir)?;
+    }
+
+    // Mistakes in the script show before a long load
+    let transforms = matches
+        .value_of("transform")
+        .map(script::parse)
+        .transpose()?;
+    let parts = mesh::load(file_path)?;
+
+    if parts.len() > 1 {
+        eprintln!(
+            "Slicing {} objects: {}",
+            parts.len(),
+            parts
+                .iter()
+                .map(|part| part.name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+
+    // One profile slices every part, settings of single objects can't apply
+    for part in parts.iter().filter(|part| !part.settings.is_empty()) {
+        eprintln!(
+            "Ignoring the settings of {}: {}",
+            part.name,
+            part.settings
+                .iter()
+                .map(|(key, _)| key.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+
+    let mut stl = mesh::merge(parts);
+
+    if matches.is_present("repair") {
+        let (repaired, repairs) = mesh::repair(&stl);
+
+        eprintln!("{}", repairs);
+        stl = repaired;
+    }
+
+    // Models start from the middle of the bed, the origin of the transforms
+    stl = stl.center(0.0, 0.0);
+
+    if let Some(transforms) = transforms {
+        stl = transformations(stl, transforms);
+    }
 
-    if let Some(raw) = matches.value_of("transform") {
-        stl = transformations(stl, raw);
+    if !matches.is_present("no_drop_to_bed") {
+        stl = stl.drop_to_bed();
     }
 
+    // Contours are oriented by the normals, stored ones can't be trusted
+    if mesh::orient(&mut stl) {
+        eprintln!("The mesh was inside out, its faces were turned");
+    }
+
+    let thumbnails: Vec<Thumbnail> = profile
+        .thumbnails
+        .iter()
+        .map(|&(width, height)| thumbnail::render(&stl, width, height))
+        .collect();
+
     let slices: Vec<Slice> = stl
         .iter_stages()
         .unwrap()
@@ -92,7 +815,110 @@ fn main() -> anyhow::Result<()> {
         .unwrap()
         .collect();
 
-    Printer::print(slices.into_iter(), layer_height);
+    if let Some(out) = matches.value_of("resin") {
+        let defaults = resin::Options::default();
+        let options = resin::Options {
+            resolution: matches
+                .value_of("resolution")
+                .map(|raw| {
+                    let (width, height) = parse_area(raw, "resolution");
+
+                    (width as u32, height as u32)
+                })
+                .unwrap_or(defaults.resolution),
+            display: matches
+                .value_of("display")
+                .map(|raw| parse_area(raw, "display size"))
+                .unwrap_or(defaults.display),
+            exposure: parse_value(&matches, "exposure").unwrap_or(defaults.exposure),
+            bottom_exposure: parse_value(&matches, "bottom_exposure")
+                .unwrap_or(defaults.bottom_exposure),
+            bottom_layers: parse_value(&matches, "bottom_exposure_layers")
+                .unwrap_or(defaults.bottom_layers),
+            lift_height: parse_value(&matches, "lift_height").unwrap_or(defaults.lift_height),
+            lift_speed: parse_value(&matches, "lift_speed").unwrap_or(defaults.lift_speed),
+            antialiasing: parse_value(&matches, "antialiasing").unwrap_or(defaults.antialiasing),
+            ..defaults
+        };
+        let layers = resin::export(&slices, layer_height, &options, &thumbnails, out)?;
+
+        println!("Wrote {} layers to {}", layers, out);
+        return Ok(());
+    }
+
+    if let Some(dir) = matches.value_of("laser") {
+        let options = laser::Options {
+            format: matches
+                .value_of("laser_format")
+                .map(|raw| raw.parse().unwrap())
+                .unwrap_or(laser::Format::Svg),
+            kerf: parse_value(&matches, "kerf").unwrap_or(0.0),
+            sheet: matches
+                .value_of("sheet")
+                .map(|raw| parse_area(raw, "sheet size")),
+            engrave: matches.is_present("engrave"),
+        };
+        let files = laser::export(&slices, &options, dir)?;
+
+        println!("Wrote {} files to {}", files, dir);
+        return Ok(());
+    }
+
+    let estimate = Printer::print(slices.into_iter(), layer_height, &profile, &thumbnails)?;
 
+    // Stdout carries the G-code
+    eprint!("{}", estimate);
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::ast::{Axis, Transform};
+    use super::{mesh, script, transformations};
+
+    #[test]
+    fn expressions() {
+        let transforms = script::parse("move(-10, 2*3, 0), rotate(z, 90deg)").unwrap();
+
+        assert!(matches!(
+            transforms[0],
+            Transform::Move(x, y, z) if x == -10.0 && y == 6.0 && z == 0.0
+        ));
+        assert!(matches!(
+            transforms[1],
+            Transform::Rotate(Axis::Z, angle) if (angle - std::f64::consts::FRAC_PI_2).abs() < 1e-12
+        ));
+    }
+
+    #[test]
+    fn orientation_can_be_pasted() {
+        let cube = mesh::merge(
+            mesh::load(concat!(env!("CARGO_MANIFEST_DIR"), "/stl_files/cube.stl")).unwrap(),
+        );
+        let tilted = transformations(
+            cube,
+            script::parse("rotate_axis(1, 1, 0, 30deg), move(5, 7, 3)").unwrap(),
+        );
+        let written = mesh::lay_flat(&tilted).to_string();
+        let chosen = transformations(tilted.clone(), script::parse("lay_flat()").unwrap());
+        let pasted = transformations(tilted.clone(), script::parse(&written).unwrap());
+        let (before, after) = (
+            mesh::Properties::new(&tilted),
+            mesh::Properties::new(&chosen),
+        );
+
+        // Turned around the middle of its bounding box, which stays in place
+        for axis in 0..3 {
+            let middle =
+                |properties: &mesh::Properties| (properties.min[axis] + properties.max[axis]) / 2.0;
+
+            assert!((middle(&before) - middle(&after)).abs() < 1e-9);
+        }
+
+        for (a, b) in chosen.vertices.iter().zip(pasted.vertices.iter()) {
+            for axis in 0..3 {
+                assert!((a[axis] - b[axis]).abs() < 1e-3, "{} left {:?}", written, b);
+            }
+        }
+    }
+}
@@ -0,0 +1,287 @@
+use std::ops::Mul;
+
+use stl_io::{IndexedMesh, Vector};
+
+// Rotation as a unit quaternion
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quaternion {
+    pub w: f64,
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+impl Quaternion {
+    pub const IDENTITY: Quaternion = Quaternion {
+        w: 1.0,
+        x: 0.0,
+        y: 0.0,
+        z: 0.0,
+    };
+
+    // Rotation of `angle` radians around `axis`, counter-clockwise when the
+    // axis points at the viewer. No rotation around a null axis.
+    pub fn from_axis_angle(axis: [f64; 3], angle: f64) -> Self {
+        let length = (axis[0] * axis[0] + axis[1] * axis[1] + axis[2] * axis[2]).sqrt();
+
+        if length == 0.0 || !length.is_finite() {
+            return Quaternion::IDENTITY;
+        }
+
+        let (sin, cos) = (angle / 2.0).sin_cos();
+
+        Quaternion {
+            w: cos,
+            x: axis[0] / length * sin,
+            y: axis[1] / length * sin,
+            z: axis[2] / length * sin,
+        }
+    }
+}
+
+// Hamilton product, `self * other` rotating by `other` first
+impl Mul for Quaternion {
+    type Output = Quaternion;
+
+    fn mul(self, other: Quaternion) -> Quaternion {
+        Quaternion {
+            w: self.w * other.w - self.x * other.x - self.y * other.y - self.z * other.z,
+            x: self.w * other.x + self.x * other.w + self.y * other.z - self.z * other.y,
+            y: self.w * other.y - self.x * other.z + self.y * other.w + self.z * other.x,
+            z: self.w * other.z + self.x * other.y - self.y * other.x + self.z * other.w,
+        }
+    }
+}
+
+// Affine transform, points being columns multiplied on the right. The last
+// row stays 0 0 0 1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Matrix(pub [[f64; 4]; 4]);
+
+impl Matrix {
+    pub const IDENTITY: Matrix = Matrix([
+        [1.0, 0.0, 0.0, 0.0],
+        [0.0, 1.0, 0.0, 0.0],
+        [0.0, 0.0, 1.0, 0.0],
+        [0.0, 0.0, 0.0, 1.0],
+    ]);
+
+    pub fn translation(x: f64, y: f64, z: f64) -> Self {
+        let mut matrix = Matrix::IDENTITY;
+
+        matrix.0[0][3] = x;
+        matrix.0[1][3] = y;
+        matrix.0[2][3] = z;
+        matrix
+    }
+
+    pub fn scaling(x: f64, y: f64, z: f64) -> Self {
+        let mut matrix = Matrix::IDENTITY;
+
+        matrix.0[0][0] = x;
+        matrix.0[1][1] = y;
+        matrix.0[2][2] = z;
+        matrix
+    }
+
+    pub fn rotation(q: &Quaternion) -> Self {
+        // Normalized again, a rotation must not scale
+        let length = (q.w * q.w + q.x * q.x + q.y * q.y + q.z * q.z).sqrt();
+        let (w, x, y, z) = (q.w / length, q.x / length, q.y / length, q.z / length);
+
+        Matrix([
+            [
+                1.0 - 2.0 * (y * y + z * z),
+                2.0 * (x * y - w * z),
+                2.0 * (x * z + w * y),
+                0.0,
+            ],
+            [
+                2.0 * (x * y + w * z),
+                1.0 - 2.0 * (x * x + z * z),
+                2.0 * (y * z - w * x),
+                0.0,
+            ],
+            [
+                2.0 * (x * z - w * y),
+                2.0 * (y * z + w * x),
+                1.0 - 2.0 * (x * x + y * y),
+                0.0,
+            ],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    // `self` then `next`
+    pub fn then(&self, next: &Matrix) -> Matrix {
+        let mut out = [[0.0; 4]; 4];
+
+        for (row, values) in out.iter_mut().enumerate() {
+            for (column, value) in values.iter_mut().enumerate() {
+                *value = (0..4).map(|k| next.0[row][k] * self.0[k][column]).sum();
+            }
+        }
+
+        Matrix(out)
+    }
+
+    pub fn apply(&self, p: [f64; 3]) -> [f64; 3] {
+        let m = &self.0;
+
+        [0, 1, 2].map(|row| m[row][0] * p[0] + m[row][1] * p[1] + m[row][2] * p[2] + m[row][3])
+    }
+
+    // Of the linear part, negative when the transform mirrors
+    pub fn determinant(&self) -> f64 {
+        let m = &self.0;
+
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+
+    // Inverse transpose of the linear part, which takes normals along. None
+    // when the transform flattens the model.
+    pub fn normal_matrix(&self) -> Option<[[f64; 3]; 3]> {
+        let m = &self.0;
+        let determinant = self.determinant();
+
+        if determinant == 0.0 || !determinant.is_finite() {
+            return None;
+        }
+
+        // Cofactors over the determinant
+        let cofactor = |row: usize, column: usize| {
+            let (r0, r1) = ((row + 1) % 3, (row + 2) % 3);
+            let (c0, c1) = ((column + 1) % 3, (column + 2) % 3);
+
+            (m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]) / determinant
+        };
+
+        Some([0, 1, 2].map(|row| [0, 1, 2].map(|column| cofactor(row, column))))
+    }
+}
+
+pub trait Affine {
+    fn affine(self, matrix: &Matrix) -> Self;
+}
+
+impl Affine for IndexedMesh {
+    // Normals follow through the inverse transpose, and the winding is turned
+    // when mirroring so that it still agrees with them
+    fn affine(mut self, matrix: &Matrix) -> Self {
+        for vertex in self.vertices.iter_mut() {
+            *vertex = Vector::new(matrix.apply([vertex[0], vertex[1], vertex[2]]));
+        }
+
+        let normals = matrix.normal_matrix();
+        let mirrored = matrix.determinant() < 0.0;
+
+        for face in self.faces.iter_mut() {
+            if mirrored {
+                face.vertices.swap(1, 2);
+            }
+
+            let n = match normals {
+                Some(m) => [0, 1, 2].map(|row| {
+                    m[row][0] * face.normal[0]
+                        + m[row][1] * face.normal[1]
+                        + m[row][2] * face.normal[2]
+                }),
+                None => [0.0; 3],
+            };
+            let length = (n[0] * n[0] + n[1] * n[1] + n[2] * n[2]).sqrt();
+
+            face.normal = if length > 0.0 {
+                Vector::new(n.map(|value| value / length))
+            } else {
+                Vector::new([0.0; 3])
+            };
+        }
+
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use stl_io::{IndexedMesh, IndexedTriangle, Vector};
+
+    use super::{Affine, Matrix, Quaternion};
+
+    fn close(a: [f64; 3], b: [f64; 3]) -> bool {
+        (0..3).all(|axis| (a[axis] - b[axis]).abs() < 1e-9)
+    }
+
+    // Unit normal of the face from its winding
+    fn winding(mesh: &IndexedMesh) -> [f64; 3] {
+        let [a, b, c] = mesh.faces[0].vertices.map(|i| mesh.vertices[i]);
+        let (u, v) = (
+            [b[0] - a[0], b[1] - a[1], b[2] - a[2]],
+            [c[0] - a[0], c[1] - a[1], c[2] - a[2]],
+        );
+        let n = [
+            u[1] * v[2] - u[2] * v[1],
+            u[2] * v[0] - u[0] * v[2],
+            u[0] * v[1] - u[1] * v[0],
+        ];
+        let length = (n[0] * n[0] + n[1] * n[1] + n[2] * n[2]).sqrt();
+
+        n.map(|value| value / length)
+    }
+
+    // A face of the plane x + y = 1, facing away from the origin
+    fn face() -> IndexedMesh {
+        let half = 0.5f64.sqrt();
+
+        IndexedMesh {
+            vertices: vec![
+                Vector::new([1.0, 0.0, 0.0]),
+                Vector::new([0.0, 1.0, 0.0]),
+                Vector::new([1.0, 0.0, 1.0]),
+            ],
+            faces: vec![IndexedTriangle {
+                normal: Vector::new([half, half, 0.0]),
+                vertices: [0, 1, 2],
+            }],
+        }
+    }
+
+    fn normal(mesh: &IndexedMesh) -> [f64; 3] {
+        let n = mesh.faces[0].normal;
+
+        [n[0], n[1], n[2]]
+    }
+
+    #[test]
+    fn then_order() {
+        let turn = Matrix::rotation(&Quaternion::from_axis_angle(
+            [0.0, 0.0, 1.0],
+            std::f64::consts::FRAC_PI_2,
+        ));
+        let shift = Matrix::translation(1.0, 0.0, 0.0);
+
+        assert!(close(shift.then(&turn).apply([0.0; 3]), [0.0, 1.0, 0.0]));
+        assert!(close(turn.then(&shift).apply([0.0; 3]), [1.0, 0.0, 0.0]));
+    }
+
+    #[test]
+    fn non_uniform_scale_normals() {
+        let mesh = face().affine(&Matrix::scaling(2.0, 1.0, 1.0));
+        let expected = [1.0 / 5f64.sqrt(), 2.0 / 5f64.sqrt(), 0.0];
+
+        // Scaling the normal itself would tilt it the other way, to 2 1 0
+        assert!(close(normal(&mesh), expected));
+        assert!(close(winding(&mesh), expected));
+    }
+
+    #[test]
+    fn mirror_winding() {
+        let mesh = face().affine(&Matrix::scaling(-1.0, 1.0, 1.0));
+        let half = 0.5f64.sqrt();
+
+        assert_eq!(mesh.faces[0].vertices, [0, 2, 1]);
+        assert!(close(normal(&mesh), [-half, half, 0.0]));
+        assert!(close(winding(&mesh), normal(&mesh)));
+    }
+}
@@ -1,9 +1,15 @@
 use std::convert::From;
 use stl_io::{Triangle, Vector, Vertex};
 
+use super::mesh::Properties;
+
+mod matrix;
 mod polygon;
+pub mod region;
 
+pub use matrix::{Affine, Matrix, Quaternion};
 pub use polygon::Polygon;
+pub use region::Region;
 
 pub const Z: usize = 2;
 pub const Y: usize = 1;
@@ -52,14 +58,17 @@ impl Segment {
         self
     }
 
+    // Turns the segment so that the part lies on its left, outer contours
+    // running counter-clockwise and holes clockwise. The normal of the face
+    // must point outwards.
     pub fn correct_direction(&mut self) {
         let a = self.vertices[0];
         let b = self.vertices[1];
-        let c = self.normal;
+        let n = self.normal;
 
-        let det = (b[X] - a[X]) * (c[Y] - a[Y]) - (b[Y] - a[Y]) * (c[X] - a[X]);
+        let det = (b[X] - a[X]) * n[Y] - (b[Y] - a[Y]) * n[X];
 
-        if det < 0.0 {
+        if det > 0.0 {
             self.vertices[0] = b;
             self.vertices[1] = a;
         }
@@ -115,133 +124,73 @@ impl Highest for stl_io::IndexedMesh {
     }
 }
 
-pub trait Scale {
-    fn scale(self, x: f64, y: f64, z: f64) -> Self;
-}
-
-impl Scale for stl_io::IndexedMesh {
-    fn scale(mut self, x: f64, y: f64, z: f64) -> Self {
-        for vertice in self.vertices.iter_mut() {
-            let x = vertice[X] * x;
-            let y = vertice[Y] * y;
-            let z = vertice[Z] * z;
-
-            *vertice = Vertex::new([x, y, z])
-        }
-
-        self
-    }
-}
-
-pub trait Homothety {
-    fn homothety(self, s: f64) -> Self;
-}
-
-impl<T> Homothety for T
-where
-    T: Scale,
-{
-    fn homothety(self, s: f64) -> Self {
-        self.scale(s, s, s)
-    }
-}
-
 pub trait Displace {
     fn displace(self, x: f64, y: f64, z: f64) -> Self;
 }
 
 impl Displace for stl_io::IndexedMesh {
-    fn displace(mut self, x: f64, y: f64, z: f64) -> Self {
-        for vertice in self.vertices.iter_mut() {
-            let x = vertice[X] + x;
-            let y = vertice[Y] + y;
-            let z = vertice[Z] + z;
-
-            *vertice = Vertex::new([x, y, z])
-        }
-
-        self
+    fn displace(self, x: f64, y: f64, z: f64) -> Self {
+        self.affine(&Matrix::translation(x, y, z))
     }
 }
 
-// TODO: Use Quaternions
-pub trait RotateX {
-    fn rotate_x(self, theta: f64) -> Self;
+pub trait Center {
+    // Moves the middle of the bounding box over (x, y), the height untouched
+    fn center(self, x: f64, y: f64) -> Self;
+    // Same with the centre of mass
+    fn center_mass(self, x: f64, y: f64) -> Self;
+    // Moves the middle of the bounding box to 0 along `axis` only
+    fn center_axis(self, axis: usize) -> Self;
 }
 
-impl RotateX for stl_io::IndexedMesh {
-    fn rotate_x(mut self, theta: f64) -> Self {
-        let cos_t = theta.cos();
-        let sin_t = theta.sin();
-
-        for vertice in self.vertices.iter_mut() {
-            let y = cos_t * vertice[Y] - sin_t * vertice[Z];
-            let z = sin_t * vertice[Y] + cos_t * vertice[Z];
-
-            *vertice = Vertex::new([vertice[X], y, z])
+impl Center for stl_io::IndexedMesh {
+    fn center(self, x: f64, y: f64) -> Self {
+        if self.vertices.is_empty() {
+            return self;
         }
 
-        self
-    }
-}
-pub trait RotateY {
-    fn rotate_y(self, theta: f64) -> Self;
-}
+        let properties = Properties::new(&self);
+        let middle = [
+            (properties.min[X] + properties.max[X]) / 2.0,
+            (properties.min[Y] + properties.max[Y]) / 2.0,
+        ];
 
-impl RotateY for stl_io::IndexedMesh {
-    fn rotate_y(mut self, theta: f64) -> Self {
-        let cos_t = theta.cos();
-        let sin_t = theta.sin();
-
-        for vertice in self.vertices.iter_mut() {
-            let x = cos_t * vertice[X] + sin_t * vertice[Z];
-            let z = cos_t * vertice[Z] - sin_t * vertice[X];
+        self.displace(x - middle[X], y - middle[Y], 0.0)
+    }
 
-            *vertice = Vertex::new([x, vertice[Y], z])
+    fn center_mass(self, x: f64, y: f64) -> Self {
+        if self.vertices.is_empty() {
+            return self;
         }
 
-        self
-    }
-}
-pub trait RotateZ {
-    fn rotate_z(self, theta: f64) -> Self;
-}
-
-impl RotateZ for stl_io::IndexedMesh {
-    fn rotate_z(mut self, theta: f64) -> Self {
-        let cos_t = theta.cos();
-        let sin_t = theta.sin();
+        let centroid = Properties::new(&self).centroid;
 
-        for vertice in self.vertices.iter_mut() {
-            let x = cos_t * vertice[X] - sin_t * vertice[Y];
-            let y = sin_t * vertice[X] + cos_t * vertice[Y];
+        self.displace(x - centroid[X], y - centroid[Y], 0.0)
+    }
 
-            *vertice = Vertex::new([x, y, vertice[Z]])
+    fn center_axis(self, axis: usize) -> Self {
+        if self.vertices.is_empty() {
+            return self;
         }
 
-        self
+        let properties = Properties::new(&self);
+        let mut offset = [0.0; 3];
+
+        offset[axis] = -(properties.min[axis] + properties.max[axis]) / 2.0;
+        self.displace(offset[X], offset[Y], offset[Z])
     }
 }
 
-pub trait Center {
-    fn center(self) -> Self;
+pub trait DropToBed {
+    // Moves the lowest point to Z = 0
+    fn drop_to_bed(self) -> Self;
 }
 
-impl Center for stl_io::IndexedMesh {
-    fn center(self) -> Self {
-        let len = self.vertices.len() as f64;
-        let first = self.vertices.iter().next();
-
-        if let Some(first) = first {
-            let total = self.vertices.iter().fold(*first, |acc, v| {
-                Vector::new([acc[X] + v[X], acc[Y] + v[Y], acc[Z] + v[Z]])
-            });
-
-            let offset = [total[X] / len, total[Y] / len, total[Z] / len];
-
-            self.displace(offset[X], offset[Y], offset[Z])
-        } else {
-            self
+impl DropToBed for stl_io::IndexedMesh {
+    fn drop_to_bed(self) -> Self {
+        match self.lowest() {
+            Some(lowest) => self.displace(0.0, 0.0, -lowest),
+            None => self,
         }
     }
 }
@@ -1,6 +1,6 @@
 use std::{convert::Into, ops::Deref};
 
-use super::{equal_vertices, Segment};
+use super::{equal_vertices, Segment, X, Y};
 
 #[derive(Debug, Copy, Clone, PartialEq)]
 enum Push {
@@ -18,6 +18,51 @@ impl Polygon {
         Self(into)
     }
 
+    // A polygon is closed when its last segment ends where the first one starts
+    pub fn is_closed(&self) -> bool {
+        match (self.0.first(), self.0.last()) {
+            (Some(first), Some(last)) => {
+                self.0.len() > 2 && equal_vertices(first.vertices[0], last.vertices[1])
+            }
+            _ => false,
+        }
+    }
+
+    // Signed area of the polygon in the XY plane, positive when counter-clockwise
+    pub fn area(&self) -> f64 {
+        self.0
+            .iter()
+            .map(|s| s.vertices[0][X] * s.vertices[1][Y] - s.vertices[1][X] * s.vertices[0][Y])
+            .sum::<f64>()
+            / 2.0
+    }
+
+    // Point of the polygon closest to (x, y) in the XY plane, along with the
+    // index of the segment it lies on
+    pub fn closest_point(&self, x: f64, y: f64) -> Option<(usize, (f64, f64))> {
+        self.0
+            .iter()
+            .enumerate()
+            .map(|(i, s)| {
+                let (a, b) = (s.vertices[0], s.vertices[1]);
+                let (dx, dy) = (b[X] - a[X], b[Y] - a[Y]);
+                let len = dx * dx + dy * dy;
+                let t = if len > 0.0 {
+                    (((x - a[X]) * dx + (y - a[Y]) * dy) / len).clamp(0.0, 1.0)
+                } else {
+                    0.0
+                };
+
+                (i, (a[X] + dx * t, a[Y] + dy * t))
+            })
+            .min_by(|(_, a), (_, b)| {
+                let da = (a.0 - x).powi(2) + (a.1 - y).powi(2);
+                let db = (b.0 - x).powi(2) + (b.1 - y).powi(2);
+
+                da.partial_cmp(&db).unwrap()
+            })
+    }
+
     fn add_segment(&mut self, segment: Segment, action: Push) {
         use Push::*;
 
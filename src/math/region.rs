@@ -0,0 +1,101 @@
+use super::{Polygon, EPSILON, X, Y};
+
+pub type Point = (f64, f64);
+
+pub fn sub(a: Point, b: Point) -> Point {
+    (a.0 - b.0, a.1 - b.1)
+}
+
+pub fn cross(a: Point, b: Point) -> f64 {
+    a.0 * b.1 - a.1 * b.0
+}
+
+pub fn distance(a: Point, b: Point) -> f64 {
+    let (x, y) = sub(b, a);
+
+    (x * x + y * y).sqrt()
+}
+
+pub fn normalize(a: Point) -> Option<Point> {
+    let len = (a.0 * a.0 + a.1 * a.1).sqrt();
+
+    if len < EPSILON {
+        None
+    } else {
+        Some((a.0 / len, a.1 / len))
+    }
+}
+
+pub fn segment_distance(p: Point, a: Point, b: Point) -> f64 {
+    let ab = sub(b, a);
+    let len = ab.0 * ab.0 + ab.1 * ab.1;
+
+    if len < EPSILON * EPSILON {
+        return distance(p, a);
+    }
+
+    let ap = sub(p, a);
+    let t = ((ap.0 * ab.0 + ap.1 * ab.1) / len).clamp(0.0, 1.0);
+
+    distance(p, (a.0 + ab.0 * t, a.1 + ab.1 * t))
+}
+
+// Area enclosed by the closed polygons of a slice, holes included, in the XY plane
+#[derive(Debug, Clone)]
+pub struct Region {
+    pub boundaries: Vec<Vec<Point>>,
+}
+
+impl Region {
+    pub fn new(polygons: &[Polygon]) -> Self {
+        Region {
+            boundaries: polygons
+                .iter()
+                .filter(|polygon| polygon.is_closed())
+                .map(|polygon| {
+                    polygon
+                        .iter()
+                        .map(|segment| (segment.vertices[0][X], segment.vertices[0][Y]))
+                        .collect()
+                })
+                .collect(),
+        }
+    }
+
+    pub fn edges(&self) -> impl Iterator<Item = (Point, Point)> + '_ {
+        self.boundaries.iter().flat_map(|boundary| {
+            boundary
+                .iter()
+                .zip(boundary.iter().cycle().skip(1))
+                .map(|(a, b)| (*a, *b))
+        })
+    }
+
+    // Distance to the nearest contour
+    pub fn clearance(&self, p: Point) -> f64 {
+        self.edges()
+            .map(|(a, b)| segment_distance(p, a, b))
+            .fold(f64::INFINITY, f64::min)
+    }
+
+    // Even-odd test, points lying on a contour count as inside
+    pub fn contains(&self, p: Point) -> bool {
+        if self.clearance(p) < EPSILON {
+            return true;
+        }
+
+        let mut inside = false;
+
+        for (a, b) in self.edges() {
+            if (a.1 > p.1) != (b.1 > p.1) {
+                let x = a.0 + (p.1 - a.1) / (b.1 - a.1) * (b.0 - a.0);
+
+                if x > p.0 {
+                    inside = !inside;
+                }
+            }
+        }
+
+        inside
+    }
+}
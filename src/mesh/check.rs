@@ -0,0 +1,556 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use stl_io::{IndexedMesh, Vector, Vertex};
+
+use super::{from_polygons, orient, signed_volume, winding_normal};
+
+// Vertices closer than this are the same vertex, in millimeters
+const MERGE_DISTANCE: f64 = 1e-5;
+// Faces smaller than this have no area, in mm²
+const DEGENERATE_AREA: f64 = 1e-10;
+// Holes bordered by more edges than this are left open
+const MAX_HOLE_EDGES: usize = 64;
+
+// Defects of a mesh, edges counted once whatever the faces using them
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    pub vertices: usize,
+    pub faces: usize,
+    // Vertices at the place of an earlier one
+    pub duplicate_vertices: usize,
+    // Edges of a single face, bordering a hole
+    pub open_edges: usize,
+    // Edges of three faces or more
+    pub non_manifold_edges: usize,
+    // Edges both faces run through the same way, one of them being inside out
+    pub inconsistent_edges: usize,
+    // Faces without area
+    pub degenerate_faces: usize,
+    // Faces over the same vertices as an earlier one
+    pub duplicate_faces: usize,
+    // Faces whose stored normal points against their winding
+    pub flipped_normals: usize,
+    // Whether the faces all point inwards, the volume they enclose being negative
+    pub inverted: bool,
+}
+
+impl Report {
+    pub fn is_clean(&self) -> bool {
+        self.duplicate_vertices == 0
+            && self.open_edges == 0
+            && self.non_manifold_edges == 0
+            && self.inconsistent_edges == 0
+            && self.degenerate_faces == 0
+            && self.duplicate_faces == 0
+            && self.flipped_normals == 0
+            && !self.inverted
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Vertices: {}", self.vertices)?;
+        writeln!(f, "Faces: {}", self.faces)?;
+        writeln!(f, "Duplicate vertices: {}", self.duplicate_vertices)?;
+        writeln!(f, "Open edges: {}", self.open_edges)?;
+        writeln!(f, "Non-manifold edges: {}", self.non_manifold_edges)?;
+        writeln!(f, "Inconsistent winding edges: {}", self.inconsistent_edges)?;
+        writeln!(f, "Degenerate faces: {}", self.degenerate_faces)?;
+        writeln!(f, "Duplicate faces: {}", self.duplicate_faces)?;
+        writeln!(f, "Flipped normals: {}", self.flipped_normals)?;
+        writeln!(
+            f,
+            "Inside out: {}",
+            if self.inverted { "yes" } else { "no" }
+        )?;
+
+        if self.is_clean() {
+            writeln!(f, "The mesh is sound")
+        } else {
+            writeln!(f, "The mesh needs repairing")
+        }
+    }
+}
+
+// What `repair` changed
+#[derive(Debug, Clone, Default)]
+pub struct Repairs {
+    pub merged_vertices: usize,
+    pub removed_faces: usize,
+    pub flipped_faces: usize,
+    pub filled_holes: usize,
+    // Holes too large or too tangled to fill
+    pub open_holes: usize,
+    // Whether the faces all pointed inwards
+    pub inverted: bool,
+}
+
+impl fmt::Display for Repairs {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Merged {} vertices, removed {} faces, flipped {} faces, filled {} holes",
+            self.merged_vertices, self.removed_faces, self.flipped_faces, self.filled_holes
+        )?;
+
+        if self.open_holes > 0 {
+            write!(f, ", {} holes left open", self.open_holes)?;
+        }
+
+        if self.inverted {
+            write!(f, ", turned the mesh right side out")?;
+        }
+
+        Ok(())
+    }
+}
+
+fn area(a: Vertex, b: Vertex, c: Vertex) -> f64 {
+    let (u, v) = (
+        [b[0] - a[0], b[1] - a[1], b[2] - a[2]],
+        [c[0] - a[0], c[1] - a[1], c[2] - a[2]],
+    );
+    let n = [
+        u[1] * v[2] - u[2] * v[1],
+        u[2] * v[0] - u[0] * v[2],
+        u[0] * v[1] - u[1] * v[0],
+    ];
+
+    (n[0] * n[0] + n[1] * n[1] + n[2] * n[2]).sqrt() / 2.0
+}
+
+// Index of the first vertex within `MERGE_DISTANCE` of each vertex. Vertices
+// are kept in cells that wide, and the cells around each one searched, so
+// close vertices on both sides of a cell border merge too.
+fn weld(vertices: &[Vertex]) -> Vec<usize> {
+    let mut cells: HashMap<[i64; 3], Vec<usize>> = HashMap::new();
+
+    vertices
+        .iter()
+        .enumerate()
+        .map(|(i, v)| {
+            let cell = [0, 1, 2].map(|axis| (v[axis] / MERGE_DISTANCE).floor() as i64);
+            let close = |j: &usize| {
+                let w = vertices[*j];
+
+                (0..3).map(|axis| (w[axis] - v[axis]).powi(2)).sum::<f64>()
+                    <= MERGE_DISTANCE * MERGE_DISTANCE
+            };
+            let neighbours = (-1..=1).flat_map(|x| {
+                (-1..=1).flat_map(move |y| {
+                    (-1..=1).map(move |z| [cell[0] + x, cell[1] + y, cell[2] + z])
+                })
+            });
+            let first = neighbours
+                .filter_map(|key| cells.get(&key))
+                .flatten()
+                .copied()
+                .find(close);
+
+            first.unwrap_or_else(|| {
+                cells.entry(cell).or_default().push(i);
+                i
+            })
+        })
+        .collect()
+}
+
+// Faces of each edge, given as its ordered vertices, with whether they run
+// through it from the lower index to the higher
+fn edges(faces: &[[usize; 3]]) -> HashMap<(usize, usize), Vec<(usize, bool)>> {
+    let mut edges: HashMap<(usize, usize), Vec<(usize, bool)>> = HashMap::new();
+
+    for (i, face) in faces.iter().enumerate() {
+        for corner in 0..3 {
+            let (a, b) = (face[corner], face[(corner + 1) % 3]);
+
+            edges
+                .entry((a.min(b), a.max(b)))
+                .or_default()
+                .push((i, a < b));
+        }
+    }
+
+    edges
+}
+
+pub fn check(mesh: &IndexedMesh) -> Report {
+    let welded = weld(&mesh.vertices);
+    let mut report = Report {
+        vertices: mesh.vertices.len(),
+        faces: mesh.faces.len(),
+        duplicate_vertices: welded.iter().enumerate().filter(|(i, j)| i != *j).count(),
+        inverted: signed_volume(mesh) < 0.0,
+        ..Report::default()
+    };
+    let mut seen = HashSet::new();
+    let mut faces = vec![];
+
+    for face in mesh.faces.iter() {
+        let [a, b, c] = face.vertices.map(|index| mesh.vertices[index]);
+        let indices = face.vertices.map(|index| welded[index]);
+        let winding = winding_normal(a, b, c);
+        let dot = (0..3)
+            .map(|axis| winding[axis] * face.normal[axis])
+            .sum::<f64>();
+
+        if dot < 0.0 {
+            report.flipped_normals += 1;
+        }
+
+        // Faces along a line still join their edges, collapsed ones don't
+        if indices[0] == indices[1] || indices[1] == indices[2] || indices[2] == indices[0] {
+            report.degenerate_faces += 1;
+            continue;
+        }
+
+        if area(a, b, c) <= DEGENERATE_AREA {
+            report.degenerate_faces += 1;
+        }
+
+        let mut sorted = indices;
+        sorted.sort_unstable();
+
+        if !seen.insert(sorted) {
+            report.duplicate_faces += 1;
+            continue;
+        }
+
+        faces.push(indices);
+    }
+
+    for users in edges(&faces).values() {
+        match users[..] {
+            [_] => report.open_edges += 1,
+            [(_, first), (_, second)] if first == second => report.inconsistent_edges += 1,
+            [_, _] => (),
+            _ => report.non_manifold_edges += 1,
+        }
+    }
+
+    report
+}
+
+// Turns faces so that each runs through its shared edges the other way from
+// its neighbour, going over the faces reached through manifold edges. Returns
+// how many were turned.
+fn unify_winding(faces: &mut [[usize; 3]]) -> usize {
+    let edges = edges(faces);
+    let mut done = vec![false; faces.len()];
+    let mut flipped = 0;
+
+    for start in 0..faces.len() {
+        if done[start] {
+            continue;
+        }
+
+        done[start] = true;
+
+        let mut queue = vec![start];
+
+        while let Some(current) = queue.pop() {
+            for corner in 0..3 {
+                let (a, b) = (faces[current][corner], faces[current][(corner + 1) % 3]);
+
+                let other = match edges[&(a.min(b), a.max(b))][..] {
+                    [(first, _), (second, _)] => {
+                        if first == current {
+                            second
+                        } else {
+                            first
+                        }
+                    }
+                    _ => continue,
+                };
+
+                if done[other] {
+                    continue;
+                }
+
+                // The neighbour must run from b to a
+                let face = faces[other];
+                let same_way = (0..3).any(|i| face[i] == a && face[(i + 1) % 3] == b);
+
+                if same_way {
+                    faces[other].swap(1, 2);
+                    flipped += 1;
+                }
+
+                done[other] = true;
+                queue.push(other);
+            }
+        }
+    }
+
+    flipped
+}
+
+// Removes the faces along a line, by flipping their longest edge when they are
+// part of a closed surface, the face across being split at their middle
+// corner. Returns how many went.
+fn remove_slivers(vertices: &[Vertex], faces: &mut Vec<[usize; 3]>) -> usize {
+    let flat = |face: &[usize; 3]| {
+        area(vertices[face[0]], vertices[face[1]], vertices[face[2]]) <= DEGENERATE_AREA
+    };
+    let users = edges(faces);
+    let count = faces.len();
+
+    // Those on the border of a hole would only come back as its filling
+    faces.retain(|face| {
+        !flat(face)
+            || (0..3).all(|corner| {
+                let (a, b) = (face[corner], face[(corner + 1) % 3]);
+
+                users[&(a.min(b), a.max(b))].len() > 1
+            })
+    });
+
+    let mut removed = count - faces.len();
+
+    // Each flip leaves one flat face less, slivers next to each other waiting
+    // for their neighbours to go first
+    loop {
+        let edges = edges(faces);
+        let flip = faces
+            .iter()
+            .enumerate()
+            .filter(|(_, face)| flat(face))
+            .find_map(|(i, face)| {
+                let length = |corner: usize| {
+                    let (a, b) = (
+                        vertices[face[(corner + 1) % 3]],
+                        vertices[face[(corner + 2) % 3]],
+                    );
+
+                    (0..3).map(|axis| (a[axis] - b[axis]).powi(2)).sum::<f64>()
+                };
+                // The middle corner faces the longest edge
+                let middle = (0..3).max_by(|x, y| length(*x).total_cmp(&length(*y)))?;
+                let (m, a, b) = (face[middle], face[(middle + 1) % 3], face[(middle + 2) % 3]);
+                let other = match edges[&(a.min(b), a.max(b))][..] {
+                    [(first, _), (second, _)] if first == i => second,
+                    [(first, _), (_, _)] => first,
+                    _ => return None,
+                };
+                let across = faces[other];
+                // Running from b to a, as consistent winding has it
+                let corner = (0..3).find(|c| across[*c] == b && across[(*c + 1) % 3] == a)?;
+
+                if flat(&across) {
+                    return None;
+                }
+
+                Some((i, other, m, a, b, across[(corner + 2) % 3]))
+            });
+
+        let (sliver, other, m, a, b, d) = match flip {
+            Some(flip) => flip,
+            None => return removed,
+        };
+
+        faces[other] = [b, m, d];
+        faces.push([m, a, d]);
+        faces.swap_remove(sliver);
+        removed += 1;
+    }
+}
+
+// Closes the loops of open edges short enough, with a fan of triangles around
+// their middle. Returns the filled and left open holes.
+fn fill_holes(vertices: &mut Vec<Vertex>, faces: &mut Vec<[usize; 3]>) -> (usize, usize) {
+    // The filling face runs through each open edge the other way
+    let mut next: HashMap<usize, Vec<usize>> = HashMap::new();
+
+    for (&(low, high), users) in edges(faces).iter() {
+        match users[..] {
+            [(_, true)] => next.entry(high).or_default().push(low),
+            [(_, false)] => next.entry(low).or_default().push(high),
+            _ => (),
+        }
+    }
+
+    let mut visited = HashSet::new();
+    let (mut filled, mut open) = (0, 0);
+    let mut starts: Vec<usize> = next.keys().copied().collect();
+
+    starts.sort_unstable();
+
+    for start in starts {
+        if visited.contains(&start) {
+            continue;
+        }
+
+        let mut hole = vec![start];
+        let mut tangled = false;
+
+        visited.insert(start);
+
+        loop {
+            let current = *hole.last().unwrap();
+            let following = match next.get(&current).map(|next| &next[..]) {
+                Some([following]) => *following,
+                // Several holes meeting at a vertex, or a dead end
+                _ => {
+                    tangled = true;
+                    break;
+                }
+            };
+
+            if following == start {
+                break;
+            }
+
+            if !visited.insert(following) {
+                tangled = true;
+                break;
+            }
+
+            hole.push(following);
+        }
+
+        if tangled || hole.len() > MAX_HOLE_EDGES {
+            open += 1;
+            continue;
+        }
+
+        if hole.len() == 3 {
+            faces.push([hole[0], hole[1], hole[2]]);
+        } else {
+            let middle = [0, 1, 2].map(|axis| {
+                hole.iter().map(|index| vertices[*index][axis]).sum::<f64>() / hole.len() as f64
+            });
+            let center = vertices.len();
+
+            vertices.push(Vector::new(middle));
+
+            for i in 0..hole.len() {
+                faces.push([hole[i], hole[(i + 1) % hole.len()], center]);
+            }
+        }
+
+        filled += 1;
+    }
+
+    (filled, open)
+}
+
+// Sound copy of `mesh`: vertices at the same place merged, degenerate and
+// duplicate faces removed, winding made consistent and facing outwards, small
+// holes filled and normals recomputed from the winding
+pub fn repair(mesh: &IndexedMesh) -> (IndexedMesh, Repairs) {
+    let welded = weld(&mesh.vertices);
+    let mut repairs = Repairs::default();
+
+    // Kept vertices numbered anew
+    let mut numbers = vec![usize::MAX; mesh.vertices.len()];
+    let mut vertices = vec![];
+
+    for (i, first) in welded.iter().enumerate() {
+        if i == *first {
+            numbers[i] = vertices.len();
+            vertices.push(mesh.vertices[i]);
+        } else {
+            repairs.merged_vertices += 1;
+        }
+    }
+
+    let mut seen = HashSet::new();
+    let mut faces = vec![];
+
+    for face in mesh.faces.iter() {
+        let indices = face.vertices.map(|index| numbers[welded[index]]);
+        let mut sorted = indices;
+
+        sorted.sort_unstable();
+
+        // Collapsed faces never reach `seen`, duplicates fail to enter it
+        if sorted[0] == sorted[1] || sorted[1] == sorted[2] || !seen.insert(sorted) {
+            repairs.removed_faces += 1;
+        } else {
+            faces.push(indices);
+        }
+    }
+
+    repairs.flipped_faces = unify_winding(&mut faces);
+    repairs.removed_faces += remove_slivers(&vertices, &mut faces);
+
+    let (filled, open) = fill_holes(&mut vertices, &mut faces);
+
+    repairs.filled_holes = filled;
+    repairs.open_holes = open;
+
+    let polygons = faces.into_iter().map(|face| face.to_vec()).collect();
+    let mut repaired = from_polygons(vertices, polygons);
+
+    repairs.inverted = orient(&mut repaired);
+    (repaired, repairs)
+}
+
+#[cfg(test)]
+mod tests {
+    use stl_io::{IndexedMesh, Vector};
+
+    use super::super::load;
+    use super::{check, repair, weld};
+
+    fn cube() -> IndexedMesh {
+        let parts = load(concat!(env!("CARGO_MANIFEST_DIR"), "/stl_files/cube.stl")).unwrap();
+
+        parts[0].mesh.clone()
+    }
+
+    #[test]
+    fn weld_across_cells() {
+        // Pairs on both sides of a cell border, and of a rounding one
+        let vertices = [
+            Vector::new([0.49e-5, 0.0, 0.0]),
+            Vector::new([0.51e-5, 0.0, 0.0]),
+            Vector::new([5.99e-5, 0.0, 0.0]),
+            Vector::new([6.01e-5, 0.0, 0.0]),
+        ];
+
+        assert_eq!(weld(&vertices), vec![0, 0, 2, 2]);
+    }
+
+    #[test]
+    fn fill_missing_face() {
+        let mut mesh = cube();
+
+        mesh.faces.remove(3);
+        assert_eq!(check(&mesh).open_edges, 3);
+
+        let (repaired, repairs) = repair(&mesh);
+
+        assert_eq!(repairs.filled_holes, 1);
+        assert_eq!(repaired.faces.len(), 12);
+        assert!(check(&repaired).is_clean(), "{}", check(&repaired));
+    }
+
+    #[test]
+    fn turn_flipped_face() {
+        let mut mesh = cube();
+
+        mesh.faces[5].vertices.swap(0, 1);
+        assert!(check(&mesh).inconsistent_edges > 0);
+
+        let (repaired, repairs) = repair(&mesh);
+
+        assert_eq!(repairs.flipped_faces, 1);
+        assert!(!repairs.inverted);
+        assert!(check(&repaired).is_clean(), "{}", check(&repaired));
+    }
+
+    #[test]
+    fn inside_out() {
+        let parts = load(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/stl_files/pikachu_1gen_flowalistik.STL"
+        ))
+        .unwrap();
+        let report = check(&parts[0].mesh);
+
+        assert!(report.inverted);
+        assert!(!report.is_clean());
+    }
+}
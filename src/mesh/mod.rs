@@ -0,0 +1,336 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+
+use stl_io::{IndexedMesh, IndexedTriangle, Vector, Vertex};
+
+mod check;
+mod obj;
+mod orientation;
+mod ply;
+mod properties;
+mod stl;
+mod threemf;
+
+pub use check::{check, repair};
+pub use orientation::{auto_orient, lay_flat, Orientation};
+pub use properties::Properties;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    BinaryStl,
+    AsciiStl,
+    Obj,
+    Ply,
+    ThreeMf,
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+// First three numbers of a line, finite ones only
+fn point(words: &[&str]) -> Option<Vertex> {
+    let values: Vec<f64> = words
+        .iter()
+        .take(3)
+        .map(|word| word.parse().ok().filter(|value: &f64| value.is_finite()))
+        .collect::<Option<_>>()?;
+
+    match values[..] {
+        [x, y, z] => Some(Vector::new([x, y, z])),
+        _ => None,
+    }
+}
+
+// Content tells most formats apart, the extension is left for OBJ which has no header
+fn detect(path: &Path, bytes: &[u8]) -> Option<Format> {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_lowercase());
+    let start = String::from_utf8_lossy(&bytes[..bytes.len().min(512)]).to_lowercase();
+
+    if bytes.starts_with(b"ply") {
+        return Some(Format::Ply);
+    }
+
+    // 3MF packages are zip archives
+    if bytes.starts_with(b"PK\x03\x04") {
+        return Some(Format::ThreeMf);
+    }
+
+    // Binary STL headers may start with "solid" too, their size gives them away
+    if stl::is_binary(bytes) {
+        return Some(Format::BinaryStl);
+    }
+
+    // Names may run past the bytes looked at, what follows the first line tells
+    if start.trim_start().starts_with("solid") {
+        let next = bytes
+            .iter()
+            .position(|byte| *byte == b'\n')
+            .map_or(&[][..], |newline| &bytes[newline + 1..]);
+        let next = String::from_utf8_lossy(&next[..next.len().min(512)]).to_lowercase();
+
+        if start.contains("facet")
+            || start.contains("endsolid")
+            || next.trim_start().starts_with("facet")
+            || next.trim_start().starts_with("endsolid")
+        {
+            return Some(Format::AsciiStl);
+        }
+    }
+
+    match extension.as_deref() {
+        Some("obj") => Some(Format::Obj),
+        Some("stl") => Some(Format::BinaryStl),
+        _ if start.lines().any(|line| line.starts_with("v ")) => Some(Format::Obj),
+        _ => None,
+    }
+}
+
+// Normal given by the winding of the triangle, zero when it is degenerate
+fn winding_normal(a: Vertex, b: Vertex, c: Vertex) -> Vertex {
+    let (u, v) = (
+        [b[0] - a[0], b[1] - a[1], b[2] - a[2]],
+        [c[0] - a[0], c[1] - a[1], c[2] - a[2]],
+    );
+    let n = [
+        u[1] * v[2] - u[2] * v[1],
+        u[2] * v[0] - u[0] * v[2],
+        u[0] * v[1] - u[1] * v[0],
+    ];
+    let length = (n[0] * n[0] + n[1] * n[1] + n[2] * n[2]).sqrt();
+
+    if length > 0.0 {
+        Vector::new([n[0] / length, n[1] / length, n[2] / length])
+    } else {
+        Vector::new([0.0, 0.0, 0.0])
+    }
+}
+
+// Volume enclosed by the faces, negative when they face inwards. Sums the
+// tetrahedra the faces make with the origin, which only a closed mesh makes exact.
+pub fn signed_volume(mesh: &IndexedMesh) -> f64 {
+    mesh.faces
+        .iter()
+        .map(|face| {
+            let [a, b, c] = face.vertices.map(|index| mesh.vertices[index]);
+
+            (a[0] * (b[1] * c[2] - b[2] * c[1]) - a[1] * (b[0] * c[2] - b[2] * c[0])
+                + a[2] * (b[0] * c[1] - b[1] * c[0]))
+                / 6.0
+        })
+        .sum()
+}
+
+// Sets the normals from the winding, whatever the file stored, after turning
+// every face when the mesh is inside out. Returns whether it was.
+pub fn orient(mesh: &mut IndexedMesh) -> bool {
+    let inverted = signed_volume(mesh) < 0.0;
+
+    let vertices = &mesh.vertices;
+
+    for face in mesh.faces.iter_mut() {
+        if inverted {
+            face.vertices.swap(1, 2);
+        }
+
+        let [a, b, c] = face.vertices.map(|index| vertices[index]);
+
+        face.normal = winding_normal(a, b, c);
+    }
+
+    inverted
+}
+
+// Mesh of polygons given by vertex indices, split in fans of triangles
+fn from_polygons(vertices: Vec<Vertex>, polygons: Vec<Vec<usize>>) -> IndexedMesh {
+    let mut faces = vec![];
+
+    for polygon in polygons.iter() {
+        for i in 1..polygon.len().saturating_sub(1) {
+            let indices = [polygon[0], polygon[i], polygon[i + 1]];
+
+            faces.push(IndexedTriangle {
+                normal: winding_normal(
+                    vertices[indices[0]],
+                    vertices[indices[1]],
+                    vertices[indices[2]],
+                ),
+                vertices: indices,
+            });
+        }
+    }
+
+    IndexedMesh { vertices, faces }
+}
+
+// Mesh of separate triangles, sharing the vertices found at the exact same place
+fn from_triangles(triangles: Vec<(Vertex, [Vertex; 3])>) -> IndexedMesh {
+    let mut indices: HashMap<[u64; 3], usize> = HashMap::new();
+    let mut mesh = IndexedMesh {
+        vertices: vec![],
+        faces: vec![],
+    };
+
+    for (normal, corners) in triangles {
+        let mut face = [0; 3];
+
+        for (index, corner) in face.iter_mut().zip(corners.iter()) {
+            let key = [
+                corner[0].to_bits(),
+                corner[1].to_bits(),
+                corner[2].to_bits(),
+            ];
+
+            *index = *indices.entry(key).or_insert_with(|| {
+                mesh.vertices.push(*corner);
+                mesh.vertices.len() - 1
+            });
+        }
+
+        let normal = if normal[0] == 0.0 && normal[1] == 0.0 && normal[2] == 0.0 {
+            winding_normal(corners[0], corners[1], corners[2])
+        } else {
+            normal
+        };
+
+        mesh.faces.push(IndexedTriangle {
+            normal,
+            vertices: face,
+        });
+    }
+
+    mesh
+}
+
+// Object placed on the build plate, one per file except for 3MF projects
+#[derive(Debug, Clone)]
+pub struct Part {
+    pub name: String,
+    pub mesh: IndexedMesh,
+    // Slicer settings given to this object only, by PrusaSlicer name. Parts
+    // are sliced together with one profile, these are only reported.
+    pub settings: Vec<(String, String)>,
+}
+
+// Every part in a single mesh for the slicer
+pub fn merge(parts: Vec<Part>) -> IndexedMesh {
+    let mut mesh = IndexedMesh {
+        vertices: vec![],
+        faces: vec![],
+    };
+
+    for part in parts.into_iter() {
+        let start = mesh.vertices.len();
+
+        mesh.faces
+            .extend(part.mesh.faces.into_iter().map(|face| IndexedTriangle {
+                normal: face.normal,
+                vertices: face.vertices.map(|index| start + index),
+            }));
+        mesh.vertices.extend(part.mesh.vertices);
+    }
+
+    mesh
+}
+
+// Reads a binary or ASCII STL, OBJ, PLY or 3MF file
+pub fn load(path: &str) -> io::Result<Vec<Part>> {
+    let path = Path::new(path);
+    let bytes = std::fs::read(path)
+        .map_err(|error| io::Error::new(error.kind(), format!("{}: {}", path.display(), error)))?;
+
+    let read: fn(&[u8]) -> io::Result<IndexedMesh> = match detect(path, &bytes) {
+        Some(Format::BinaryStl) => stl::read_binary,
+        Some(Format::AsciiStl) => stl::read_ascii,
+        Some(Format::Obj) => obj::read,
+        Some(Format::Ply) => ply::read,
+        Some(Format::ThreeMf) => {
+            let parts = threemf::read(&bytes)
+                .map_err(|error| invalid(format!("{}: {}", path.display(), error)))?
+                .parts();
+
+            if parts.iter().all(|part| part.mesh.faces.is_empty()) {
+                return Err(invalid(format!("{}: no triangles found", path.display())));
+            }
+
+            return Ok(parts);
+        }
+        None => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{}: unsupported file format, expected STL, OBJ, PLY or 3MF",
+                    path.display()
+                ),
+            ))
+        }
+    };
+    let mesh = read(&bytes).map_err(|error| invalid(format!("{}: {}", path.display(), error)))?;
+
+    if mesh.faces.is_empty() {
+        return Err(invalid(format!("{}: no triangles found", path.display())));
+    }
+
+    Ok(vec![Part {
+        name: path
+            .file_stem()
+            .map_or_else(String::new, |stem| stem.to_string_lossy().into_owned()),
+        mesh,
+        settings: vec![],
+    }])
+}
+
+// Writes `mesh` as 3MF or as STL depending on the extension of `path`, binary
+// unless `ascii`
+pub fn save(mesh: &IndexedMesh, path: &str, ascii: bool) -> io::Result<()> {
+    let path = Path::new(path);
+    let name = path
+        .file_stem()
+        .map_or_else(String::new, |stem| stem.to_string_lossy().into_owned());
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_lowercase());
+
+    let bytes = match extension.as_deref() {
+        Some("3mf") => threemf::write(mesh, &name),
+        Some("stl") if ascii => stl::write_ascii(mesh, &name),
+        Some("stl") => stl::write_binary(mesh),
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{}: unsupported output format, expected .stl or .3mf",
+                    path.display()
+                ),
+            ))
+        }
+    };
+
+    std::fs::write(path, bytes)
+        .map_err(|error| io::Error::new(error.kind(), format!("{}: {}", path.display(), error)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{detect, Format};
+    use std::path::Path;
+
+    #[test]
+    fn long_ascii_stl_name() {
+        let text = format!(
+            "solid {}\nfacet normal 0 0 1\nouter loop\nvertex 0 0 0\nvertex 1 0 0\n\
+             vertex 0 1 0\nendloop\nendfacet\nendsolid\n",
+            "part".repeat(200)
+        );
+
+        assert_eq!(
+            detect(Path::new("part.stl"), text.as_bytes()),
+            Some(Format::AsciiStl)
+        );
+    }
+}
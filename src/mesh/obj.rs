@@ -0,0 +1,66 @@
+use std::io;
+
+use stl_io::IndexedMesh;
+
+use super::{from_polygons, invalid, point};
+
+// Wavefront OBJ, of which only vertex positions and faces matter for slicing
+pub fn read(bytes: &[u8]) -> io::Result<IndexedMesh> {
+    let text = String::from_utf8_lossy(bytes);
+    let mut vertices = vec![];
+    let mut polygons = vec![];
+
+    for (number, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("");
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let error = |message: String| invalid(format!("line {}: {}", number + 1, message));
+
+        match words.first().copied() {
+            Some("v") => vertices.push(
+                point(&words[1..]).ok_or_else(|| error("expected 3 numbers after `v`".into()))?,
+            ),
+            Some("f") => {
+                // Corners are v, v/vt, v/vt/vn or v//vn, counted from 1, or
+                // backwards from the last vertex when negative
+                let polygon = words[1..]
+                    .iter()
+                    .map(|word| {
+                        let index: i64 = word
+                            .split('/')
+                            .next()
+                            .and_then(|index| index.parse().ok())
+                            .ok_or_else(|| error(format!("invalid face corner `{}`", word)))?;
+                        let resolved = if index > 0 {
+                            index - 1
+                        } else {
+                            vertices.len() as i64 + index
+                        };
+
+                        if index == 0 || resolved < 0 || resolved >= vertices.len() as i64 {
+                            Err(error(format!(
+                                "vertex {} doesn't exist, {} are defined so far",
+                                index,
+                                vertices.len()
+                            )))
+                        } else {
+                            Ok(resolved as usize)
+                        }
+                    })
+                    .collect::<io::Result<Vec<usize>>>()?;
+
+                if polygon.len() < 3 {
+                    return Err(error(format!(
+                        "face with {} vertices, expected at least 3",
+                        polygon.len()
+                    )));
+                }
+
+                polygons.push(polygon);
+            }
+            // Texture coordinates, normals, groups, materials and lines
+            _ => (),
+        }
+    }
+
+    Ok(from_polygons(vertices, polygons))
+}
@@ -0,0 +1,341 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use stl_io::IndexedMesh;
+
+use super::{signed_volume, Properties};
+
+// Faces leaning further than this from the vertical, downwards, need supports
+const OVERHANG: f64 = 45.0;
+// Faces this close to the lowest point rest on the bed
+const ON_BED: f64 = 0.05;
+// Directions spread over the sphere tried by `auto_orient`, besides the planes
+// the model could lie on
+const DIRECTIONS: usize = 256;
+const PLANES: usize = 64;
+// Best directions of the spread searched around
+const REFINED: usize = 8;
+// Height only costs time, overhangs cost supports and surface quality
+const HEIGHT_WEIGHT: f64 = 0.25;
+// Steps of the search around the best direction found, in degrees
+const FINEST_STEP: f64 = 0.2;
+// Rotations smaller than this, in degrees, leave the model as it is
+const NEGLIGIBLE_ANGLE: f64 = 1e-4;
+
+// Rotation around an axis through the middle of the bounding box, with what it
+// leaves to print
+#[derive(Debug, Clone)]
+pub struct Orientation {
+    pub center: [f64; 3],
+    pub axis: [f64; 3],
+    // In radians
+    pub angle: f64,
+    // Area of the faces needing supports, in mm²
+    pub overhang: f64,
+    // Room between those faces and the bed, in mm³
+    pub support: f64,
+    pub height: f64,
+}
+
+struct Face {
+    // Outwards, whatever the winding
+    normal: [f64; 3],
+    area: f64,
+    centroid: [f64; 3],
+}
+
+fn dot(a: [f64; 3], b: [f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn cross(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn normalize(a: [f64; 3]) -> [f64; 3] {
+    let length = dot(a, a).sqrt();
+
+    a.map(|value| value / length)
+}
+
+fn faces(mesh: &IndexedMesh) -> Vec<Face> {
+    let outwards = if signed_volume(mesh) < 0.0 { -1.0 } else { 1.0 };
+
+    mesh.faces
+        .iter()
+        .filter_map(|face| {
+            let [a, b, c] = face.vertices.map(|index| mesh.vertices[index]);
+            let (u, v) = (
+                [b[0] - a[0], b[1] - a[1], b[2] - a[2]],
+                [c[0] - a[0], c[1] - a[1], c[2] - a[2]],
+            );
+            let n = cross(u, v);
+            let length = dot(n, n).sqrt();
+
+            (length > 0.0).then(|| Face {
+                normal: n.map(|value| outwards * value / length),
+                area: length / 2.0,
+                centroid: [0, 1, 2].map(|axis| (a[axis] + b[axis] + c[axis]) / 3.0),
+            })
+        })
+        .collect()
+}
+
+// Outward normals of the flat areas the model could rest on, largest first,
+// then the one facing the bed already and the others in order of their normal.
+// Coplanar faces add up, and a plane with any vertex past it is not a side.
+fn planes(mesh: &IndexedMesh, faces: &[Face]) -> Vec<[f64; 3]> {
+    let mut areas: HashMap<[i64; 4], ([f64; 3], f64, f64)> = HashMap::new();
+
+    for face in faces.iter() {
+        let offset = dot(face.normal, face.centroid);
+        let key = [
+            (face.normal[0] * 1e4).round() as i64,
+            (face.normal[1] * 1e4).round() as i64,
+            (face.normal[2] * 1e4).round() as i64,
+            (offset * 1e3).round() as i64,
+        ];
+        let plane = areas.entry(key).or_insert((face.normal, offset, 0.0));
+
+        plane.2 += face.area;
+    }
+
+    let mut planes: Vec<([f64; 3], f64, f64)> = areas.into_values().collect();
+
+    // Areas are summed in no particular order, equal ones may differ a little
+    planes.sort_by(|a, b| {
+        (b.2 * 1e6)
+            .round()
+            .total_cmp(&(a.2 * 1e6).round())
+            .then(a.0[2].total_cmp(&b.0[2]))
+            .then(a.0[0].total_cmp(&b.0[0]))
+            .then(a.0[1].total_cmp(&b.0[1]))
+    });
+    planes
+        .into_iter()
+        .filter(|(normal, offset, _)| {
+            mesh.vertices
+                .iter()
+                .all(|vertex| dot(*normal, [vertex[0], vertex[1], vertex[2]]) <= offset + 1e-3)
+        })
+        .map(|(normal, _, _)| normal)
+        .take(PLANES)
+        .collect()
+}
+
+// Orientation pointing `down` at the bed
+fn orientation(
+    mesh: &IndexedMesh,
+    faces: &[Face],
+    center: [f64; 3],
+    down: [f64; 3],
+) -> Orientation {
+    // Heights once rotated, the bed being at the lowest
+    let (low, high) =
+        mesh.vertices
+            .iter()
+            .fold((f64::INFINITY, f64::NEG_INFINITY), |(low, high), vertex| {
+                let z = -dot(down, [vertex[0], vertex[1], vertex[2]]);
+
+                (low.min(z), high.max(z))
+            });
+    let limit = -OVERHANG.to_radians().cos();
+    let (mut overhang, mut support) = (0.0, 0.0);
+
+    for face in faces.iter() {
+        let z = -dot(down, face.normal);
+        let height = -dot(down, face.centroid) - low;
+
+        if z < limit && height > ON_BED {
+            overhang += face.area;
+            support += face.area * -z * height;
+        }
+    }
+
+    // Turning `down` onto -Z, around their cross product
+    let axis = [-down[1], down[0], 0.0];
+    let angle = (-down[2]).clamp(-1.0, 1.0).acos();
+    let (axis, angle) = if angle.to_degrees() < NEGLIGIBLE_ANGLE {
+        ([0.0, 0.0, 1.0], 0.0)
+    } else if dot(axis, axis).sqrt() > 1e-9 {
+        (axis, angle)
+    } else {
+        ([1.0, 0.0, 0.0], std::f64::consts::PI)
+    };
+
+    Orientation {
+        center,
+        axis,
+        angle,
+        overhang,
+        support,
+        height: (high - low).max(0.0),
+    }
+}
+
+// Rotation laying the largest flat side of the model on the bed
+pub fn lay_flat(mesh: &IndexedMesh) -> Orientation {
+    let faces = faces(mesh);
+    let center = middle(&Properties::new(mesh));
+    let down = planes(mesh, &faces)
+        .first()
+        .copied()
+        .unwrap_or([0.0, 0.0, -1.0]);
+
+    orientation(mesh, &faces, center, down)
+}
+
+// Rotation with the least overhangs, supports and height, each counted against
+// what the model could have at most. Tries the flat sides of the model and
+// directions spread evenly around, then closer and closer around the best one.
+// The current orientation stays on a tie.
+pub fn auto_orient(mesh: &IndexedMesh) -> Orientation {
+    let faces = faces(mesh);
+    let properties = Properties::new(mesh);
+    let area = properties.area;
+    let diameter = dot(properties.size(), properties.size()).sqrt();
+    let center = middle(&properties);
+
+    if area == 0.0 || !diameter.is_finite() || diameter == 0.0 {
+        return orientation(mesh, &faces, center, [0.0, 0.0, -1.0]);
+    }
+
+    let cost = |orientation: &Orientation| {
+        orientation.overhang / area
+            + orientation.support / (area * diameter)
+            + HEIGHT_WEIGHT * orientation.height / diameter
+    };
+    // Fibonacci sphere
+    let golden = std::f64::consts::PI * (3.0 - 5f64.sqrt());
+    let spread = (0..DIRECTIONS).map(|i| {
+        let z = 1.0 - 2.0 * (i as f64 + 0.5) / DIRECTIONS as f64;
+        let radius = (1.0 - z * z).sqrt();
+        let (sin, cos) = (golden * i as f64).sin_cos();
+
+        [radius * cos, radius * sin, z]
+    });
+    let evaluate = |down: [f64; 3]| {
+        let orientation = orientation(mesh, &faces, center, down);
+        let cost = cost(&orientation);
+
+        (down, orientation, cost)
+    };
+    let mut candidates: Vec<([f64; 3], Orientation, f64)> = std::iter::once([0.0, 0.0, -1.0])
+        .chain(planes(mesh, &faces))
+        .chain(spread)
+        .map(evaluate)
+        .collect();
+
+    // Stable, the current orientation comes first among equals
+    candidates.sort_by(|a, b| a.2.total_cmp(&b.2));
+    candidates.truncate(REFINED);
+    candidates
+        .into_iter()
+        .map(|mut best| {
+            // Spacing of the spread directions to start with
+            let mut step = (4.0 * std::f64::consts::PI / DIRECTIONS as f64).sqrt();
+
+            while step > FINEST_STEP.to_radians() {
+                let center = best.0;
+                let across = if center[0].abs() < 0.9 {
+                    [1.0, 0.0, 0.0]
+                } else {
+                    [0.0, 1.0, 0.0]
+                };
+                let u = normalize(cross(center, across));
+                let v = cross(center, u);
+                let mut moved = false;
+
+                for k in 0..8 {
+                    let (sin, cos) = (k as f64 * std::f64::consts::FRAC_PI_4).sin_cos();
+                    let candidate = evaluate(normalize([0, 1, 2].map(|axis| {
+                        center[axis] * step.cos() + (u[axis] * cos + v[axis] * sin) * step.sin()
+                    })));
+
+                    if candidate.2 < best.2 - 1e-9 {
+                        best = candidate;
+                        moved = true;
+                    }
+                }
+
+                if !moved {
+                    step /= 2.0;
+                }
+            }
+
+            best
+        })
+        .reduce(|best, candidate| {
+            if candidate.2 < best.2 - 1e-9 {
+                candidate
+            } else {
+                best
+            }
+        })
+        .map(|(_, orientation, _)| orientation)
+        .unwrap()
+}
+
+// Trailing zeros dropped, for a short transform to copy
+// Middle of the bounding box, the model staying there when it turns
+fn middle(properties: &Properties) -> [f64; 3] {
+    [0, 1, 2].map(|axis| (properties.min[axis] + properties.max[axis]) / 2.0)
+}
+
+fn short(value: f64) -> String {
+    let text = format!("{:.4}", value);
+    let text = text.trim_end_matches('0').trim_end_matches('.');
+
+    match text {
+        "-0" => "0".to_string(),
+        _ => text.to_string(),
+    }
+}
+
+// As transforms, `rotate_axis(x, y, z, angle)` between the moves bringing the
+// middle of the bounding box to the origin and back
+impl fmt::Display for Orientation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let length = dot(self.axis, self.axis).sqrt();
+        let axis = self.axis.map(|value| short(value / length));
+        let [x, y, z] = self.center;
+
+        write!(
+            f,
+            "move({}, {}, {}), rotate_axis({}, {}, {}, {}deg), move({}, {}, {})",
+            short(-x),
+            short(-y),
+            short(-z),
+            axis[0],
+            axis[1],
+            axis[2],
+            short(self.angle.to_degrees()),
+            short(x),
+            short(y),
+            short(z)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{load, merge};
+    use super::lay_flat;
+
+    #[test]
+    fn cube_stays_as_it_is() {
+        let mesh = merge(load(concat!(env!("CARGO_MANIFEST_DIR"), "/stl_files/cube.stl")).unwrap());
+
+        // Sides are gathered in a map, whose order changes from one to the next
+        for _ in 0..16 {
+            assert_eq!(
+                lay_flat(&mesh).to_string(),
+                "move(0, 0, 0), rotate_axis(0, 0, 1, 0deg), move(0, 0, 0)"
+            );
+        }
+    }
+}
@@ -0,0 +1,292 @@
+use std::io;
+
+use stl_io::{IndexedMesh, Vector};
+
+use super::{from_polygons, invalid};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Encoding {
+    Ascii,
+    LittleEndian,
+    BigEndian,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Type {
+    Int8,
+    Uint8,
+    Int16,
+    Uint16,
+    Int32,
+    Uint32,
+    Float32,
+    Float64,
+}
+
+impl Type {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "char" | "int8" => Some(Type::Int8),
+            "uchar" | "uint8" => Some(Type::Uint8),
+            "short" | "int16" => Some(Type::Int16),
+            "ushort" | "uint16" => Some(Type::Uint16),
+            "int" | "int32" => Some(Type::Int32),
+            "uint" | "uint32" => Some(Type::Uint32),
+            "float" | "float32" => Some(Type::Float32),
+            "double" | "float64" => Some(Type::Float64),
+            _ => None,
+        }
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            Type::Int8 | Type::Uint8 => 1,
+            Type::Int16 | Type::Uint16 => 2,
+            Type::Int32 | Type::Uint32 | Type::Float32 => 4,
+            Type::Float64 => 8,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Property {
+    Scalar(String, Type),
+    // Count type then item type
+    List(String, Type, Type),
+}
+
+impl Property {
+    fn name(&self) -> &str {
+        match self {
+            Property::Scalar(name, _) | Property::List(name, _, _) => name,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+// Values of the body one after the other, whatever the encoding
+struct Reader<'a> {
+    encoding: Encoding,
+    bytes: &'a [u8],
+    position: usize,
+    words: std::str::SplitAsciiWhitespace<'a>,
+}
+
+impl<'a> Reader<'a> {
+    fn value(&mut self, kind: Type) -> io::Result<f64> {
+        if self.encoding == Encoding::Ascii {
+            return self
+                .words
+                .next()
+                .ok_or_else(|| invalid("unexpected end of file".into()))?
+                .parse()
+                .map_err(|_| invalid("invalid number".into()));
+        }
+
+        let size = kind.size();
+        let raw = self
+            .bytes
+            .get(self.position..self.position + size)
+            .ok_or_else(|| invalid("unexpected end of file".into()))?;
+        let mut buffer = [0; 8];
+
+        buffer[..size].copy_from_slice(raw);
+
+        if self.encoding == Encoding::BigEndian {
+            buffer[..size].reverse();
+        }
+
+        self.position += size;
+
+        Ok(match kind {
+            Type::Int8 => buffer[0] as i8 as f64,
+            Type::Uint8 => buffer[0] as f64,
+            Type::Int16 => i16::from_le_bytes([buffer[0], buffer[1]]) as f64,
+            Type::Uint16 => u16::from_le_bytes([buffer[0], buffer[1]]) as f64,
+            Type::Int32 => i32::from_le_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]) as f64,
+            Type::Uint32 => u32::from_le_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]) as f64,
+            Type::Float32 => {
+                f32::from_le_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]) as f64
+            }
+            Type::Float64 => f64::from_le_bytes(buffer),
+        })
+    }
+
+    // Scalar properties as one value, lists as all of their items
+    fn property(&mut self, property: &Property) -> io::Result<Vec<f64>> {
+        match property {
+            Property::Scalar(_, kind) => Ok(vec![self.value(*kind)?]),
+            Property::List(_, count, item) => {
+                let count = self.value(*count)?;
+
+                (0..count as usize).map(|_| self.value(*item)).collect()
+            }
+        }
+    }
+}
+
+fn header(text: &str) -> io::Result<(Encoding, Vec<Element>)> {
+    let mut encoding = None;
+    let mut elements: Vec<Element> = vec![];
+
+    for (number, line) in text.lines().enumerate() {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let error = |message: String| invalid(format!("line {}: {}", number + 1, message));
+
+        match words[..] {
+            ["ply"] | [] => (),
+            ["comment", ..] | ["obj_info", ..] => (),
+            ["format", format, _] => {
+                encoding = Some(match format {
+                    "ascii" => Encoding::Ascii,
+                    "binary_little_endian" => Encoding::LittleEndian,
+                    "binary_big_endian" => Encoding::BigEndian,
+                    _ => return Err(error(format!("unknown PLY format `{}`", format))),
+                })
+            }
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count
+                    .parse()
+                    .map_err(|_| error(format!("invalid element count `{}`", count)))?,
+                properties: vec![],
+            }),
+            ["property", "list", count, item, name] => {
+                let (count, item) = match (Type::parse(count), Type::parse(item)) {
+                    (Some(count), Some(item)) => (count, item),
+                    _ => return Err(error(format!("unknown type in `{}`", line.trim()))),
+                };
+
+                elements
+                    .last_mut()
+                    .ok_or_else(|| error("property before any element".into()))?
+                    .properties
+                    .push(Property::List(name.to_string(), count, item));
+            }
+            ["property", kind, name] => {
+                let kind = Type::parse(kind)
+                    .ok_or_else(|| error(format!("unknown property type `{}`", kind)))?;
+
+                elements
+                    .last_mut()
+                    .ok_or_else(|| error("property before any element".into()))?
+                    .properties
+                    .push(Property::Scalar(name.to_string(), kind));
+            }
+            _ => return Err(error(format!("unexpected `{}`", line.trim()))),
+        }
+    }
+
+    let encoding = encoding.ok_or_else(|| invalid("missing PLY format line".into()))?;
+
+    Ok((encoding, elements))
+}
+
+// Stanford PLY, ASCII or binary, keeping the vertex positions and faces
+pub fn read(bytes: &[u8]) -> io::Result<IndexedMesh> {
+    let end = bytes
+        .windows(10)
+        .position(|window| window == b"end_header")
+        .ok_or_else(|| invalid("missing end_header".into()))?;
+    let body = bytes[end..]
+        .iter()
+        .position(|byte| *byte == b'\n')
+        .map_or(bytes.len(), |newline| end + newline + 1);
+    let (encoding, elements) = header(&String::from_utf8_lossy(&bytes[..end]))?;
+
+    let text = match encoding {
+        Encoding::Ascii => std::str::from_utf8(&bytes[body..])
+            .map_err(|_| invalid("ASCII PLY body isn't text".into()))?,
+        _ => "",
+    };
+    let mut reader = Reader {
+        encoding,
+        bytes: &bytes[body..],
+        position: 0,
+        words: text.split_ascii_whitespace(),
+    };
+
+    let mut vertices = vec![];
+    let mut polygons = vec![];
+
+    for element in elements.iter() {
+        let position = |name: &str| {
+            element
+                .properties
+                .iter()
+                .position(|property| property.name() == name)
+        };
+
+        match element.name.as_str() {
+            "vertex" => {
+                let (x, y, z) = match (position("x"), position("y"), position("z")) {
+                    (Some(x), Some(y), Some(z)) => (x, y, z),
+                    _ => return Err(invalid("vertices without x, y and z".into())),
+                };
+
+                for _ in 0..element.count {
+                    let values = element
+                        .properties
+                        .iter()
+                        .map(|property| reader.property(property))
+                        .collect::<io::Result<Vec<Vec<f64>>>>()?;
+
+                    let vertex = [values[x][0], values[y][0], values[z][0]];
+
+                    if vertex.iter().any(|value| !value.is_finite()) {
+                        return Err(invalid(format!(
+                            "vertex {} isn't a finite point",
+                            vertices.len()
+                        )));
+                    }
+
+                    vertices.push(Vector::new(vertex));
+                }
+            }
+            "face" => {
+                let indices = position("vertex_indices")
+                    .or_else(|| position("vertex_index"))
+                    .ok_or_else(|| invalid("faces without vertex_indices".into()))?;
+
+                for i in 0..element.count {
+                    let mut values = element
+                        .properties
+                        .iter()
+                        .map(|property| reader.property(property))
+                        .collect::<io::Result<Vec<Vec<f64>>>>()?;
+                    let polygon = values.swap_remove(indices);
+
+                    if let Some(index) = polygon
+                        .iter()
+                        .find(|index| **index < 0.0 || **index >= vertices.len() as f64)
+                    {
+                        return Err(invalid(format!(
+                            "face {} uses vertex {}, only {} are defined",
+                            i,
+                            index,
+                            vertices.len()
+                        )));
+                    }
+
+                    polygons.push(polygon.into_iter().map(|index| index as usize).collect());
+                }
+            }
+            // Edges, materials and whatever else, read past to reach the next elements
+            _ => {
+                for _ in 0..element.count {
+                    for property in element.properties.iter() {
+                        reader.property(property)?;
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(from_polygons(vertices, polygons))
+}
@@ -0,0 +1,205 @@
+use std::fmt;
+
+use stl_io::IndexedMesh;
+
+#[derive(Debug, Clone)]
+pub struct Properties {
+    pub vertices: usize,
+    pub triangles: usize,
+    // Axis aligned bounding box, in millimeters
+    pub min: [f64; 3],
+    pub max: [f64; 3],
+    // Negative when the faces point inwards, in mm³
+    pub volume: f64,
+    // In mm²
+    pub area: f64,
+    // Centre of mass of the solid, or of the surface when it encloses nothing
+    pub centroid: [f64; 3],
+}
+
+impl Properties {
+    pub fn new(mesh: &IndexedMesh) -> Self {
+        let mut min = [f64::INFINITY; 3];
+        let mut max = [f64::NEG_INFINITY; 3];
+
+        for vertex in mesh.vertices.iter() {
+            for axis in 0..3 {
+                min[axis] = min[axis].min(vertex[axis]);
+                max[axis] = max[axis].max(vertex[axis]);
+            }
+        }
+
+        // Sums of the tetrahedra made with the origin, as `signed_volume` does
+        let mut volume = 0.0;
+        let mut area = 0.0;
+        // Moments of the tetrahedra and of the faces
+        let mut solid = [0.0; 3];
+        let mut surface = [0.0; 3];
+
+        for face in mesh.faces.iter() {
+            let [a, b, c] = face.vertices.map(|index| mesh.vertices[index]);
+            let (u, v) = (
+                [b[0] - a[0], b[1] - a[1], b[2] - a[2]],
+                [c[0] - a[0], c[1] - a[1], c[2] - a[2]],
+            );
+            let n = [
+                u[1] * v[2] - u[2] * v[1],
+                u[2] * v[0] - u[0] * v[2],
+                u[0] * v[1] - u[1] * v[0],
+            ];
+            let face_area = (n[0] * n[0] + n[1] * n[1] + n[2] * n[2]).sqrt() / 2.0;
+            let tetrahedron = (a[0] * (b[1] * c[2] - b[2] * c[1])
+                - a[1] * (b[0] * c[2] - b[2] * c[0])
+                + a[2] * (b[0] * c[1] - b[1] * c[0]))
+                / 6.0;
+
+            volume += tetrahedron;
+            area += face_area;
+
+            for axis in 0..3 {
+                let sum = a[axis] + b[axis] + c[axis];
+
+                solid[axis] += tetrahedron * sum / 4.0;
+                surface[axis] += face_area * sum / 3.0;
+            }
+        }
+
+        // Open or flat meshes enclose next to nothing, their solid centroid
+        // would be noise
+        let centroid = if volume.abs() > 1e-9 * area.max(1.0).powf(1.5) {
+            solid.map(|moment| moment / volume)
+        } else if area > 0.0 {
+            surface.map(|moment| moment / area)
+        } else {
+            [0, 1, 2].map(|axis| (min[axis] + max[axis]) / 2.0)
+        };
+
+        Properties {
+            vertices: mesh.vertices.len(),
+            triangles: mesh.faces.len(),
+            min,
+            max,
+            volume,
+            area,
+            centroid,
+        }
+    }
+
+    pub fn size(&self) -> [f64; 3] {
+        [0, 1, 2].map(|axis| (self.max[axis] - self.min[axis]).max(0.0))
+    }
+
+    // JSON object named `name`, its lines but the first indented by `indent`
+    pub fn json(&self, name: &str, indent: &str) -> String {
+        let fields = [
+            ("name", string(name)),
+            ("vertices", self.vertices.to_string()),
+            ("triangles", self.triangles.to_string()),
+            ("min", vector(self.min)),
+            ("max", vector(self.max)),
+            ("size", vector(self.size())),
+            ("volume", number(self.volume)),
+            ("area", number(self.area)),
+            ("centroid", vector(self.centroid)),
+        ];
+        let lines: Vec<String> = fields
+            .iter()
+            .map(|(key, value)| format!("{}  \"{}\": {}", indent, key, value))
+            .collect();
+
+        format!("{{\n{}\n{}}}", lines.join(",\n"), indent)
+    }
+}
+
+// Infinite bounds of a mesh without vertices have no JSON number
+fn number(value: f64) -> String {
+    if value.is_finite() {
+        value.to_string()
+    } else {
+        "null".to_string()
+    }
+}
+
+fn vector(values: [f64; 3]) -> String {
+    format!(
+        "[{}, {}, {}]",
+        number(values[0]),
+        number(values[1]),
+        number(values[2])
+    )
+}
+
+fn string(text: &str) -> String {
+    let mut out = String::from("\"");
+
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+
+    out.push('"');
+    out
+}
+
+impl fmt::Display for Properties {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let size = self.size();
+
+        writeln!(f, "Vertices: {}", self.vertices)?;
+        writeln!(f, "Triangles: {}", self.triangles)?;
+        writeln!(
+            f,
+            "Bounding box: X {:.3}..{:.3} Y {:.3}..{:.3} Z {:.3}..{:.3}",
+            self.min[0], self.max[0], self.min[1], self.max[1], self.min[2], self.max[2]
+        )?;
+        writeln!(
+            f,
+            "Size: {:.3} x {:.3} x {:.3}mm",
+            size[0], size[1], size[2]
+        )?;
+        writeln!(f, "Volume: {:.3}cm3", self.volume / 1000.0)?;
+        writeln!(f, "Surface area: {:.3}cm2", self.area / 100.0)?;
+        writeln!(
+            f,
+            "Centre of mass: X {:.3} Y {:.3} Z {:.3}",
+            self.centroid[0], self.centroid[1], self.centroid[2]
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use stl_io::Vector;
+
+    use super::super::load;
+    use super::Properties;
+
+    #[test]
+    fn cube() {
+        let mut mesh = load(concat!(env!("CARGO_MANIFEST_DIR"), "/stl_files/cube.stl"))
+            .unwrap()
+            .remove(0)
+            .mesh;
+
+        // Away from the origin, the centroid can't be right by chance
+        for vertex in mesh.vertices.iter_mut() {
+            *vertex = Vector::new([vertex[0] + 3.0, vertex[1] + 4.0, vertex[2] + 5.0]);
+        }
+
+        let properties = Properties::new(&mesh);
+
+        assert_eq!((properties.vertices, properties.triangles), (8, 12));
+        assert!((properties.volume - 8.0).abs() < 1e-9);
+        assert!((properties.area - 24.0).abs() < 1e-9);
+
+        for (axis, center) in [3.0, 4.0, 5.0].iter().enumerate() {
+            assert!((properties.centroid[axis] - center).abs() < 1e-9);
+        }
+    }
+}
@@ -0,0 +1,151 @@
+use std::io;
+
+use stl_io::{IndexedMesh, Vector, Vertex};
+
+use super::{from_triangles, invalid, point, winding_normal};
+
+// Binary STL files hold an 80 bytes header, a triangle count, then 50 bytes per triangle
+const HEADER: usize = 84;
+const TRIANGLE: usize = 50;
+
+fn count(bytes: &[u8]) -> Option<usize> {
+    bytes
+        .get(80..HEADER)
+        .map(|count| u32::from_le_bytes([count[0], count[1], count[2], count[3]]) as usize)
+}
+
+pub fn is_binary(bytes: &[u8]) -> bool {
+    count(bytes).map_or(false, |count| bytes.len() == HEADER + count * TRIANGLE)
+}
+
+pub fn read_binary(bytes: &[u8]) -> io::Result<IndexedMesh> {
+    let count = count(bytes).ok_or_else(|| {
+        invalid(format!(
+            "binary STL of {} bytes, shorter than its {} bytes header",
+            bytes.len(),
+            HEADER
+        ))
+    })?;
+    let size = HEADER + count * TRIANGLE;
+
+    if bytes.len() < size {
+        return Err(invalid(format!(
+            "truncated binary STL, {} triangles announced but only {} present",
+            count,
+            (bytes.len() - HEADER) / TRIANGLE
+        )));
+    }
+
+    // Some exporters append data after the triangles
+    stl_io::read_stl(&mut io::Cursor::new(&bytes[..size]))
+}
+
+pub fn read_ascii(bytes: &[u8]) -> io::Result<IndexedMesh> {
+    let text = String::from_utf8_lossy(bytes);
+    let mut triangles = vec![];
+    let mut normal = Vector::new([0.0; 3]);
+    let mut corners = vec![];
+    let mut facet = None;
+
+    for (number, line) in text.lines().enumerate() {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let error = |message: String| invalid(format!("line {}: {}", number + 1, message));
+        let keyword = words.first().map(|word| word.to_lowercase());
+
+        match keyword.as_deref() {
+            Some("facet") => {
+                normal = match words.get(1).map(|word| word.to_lowercase()).as_deref() {
+                    Some("normal") => point(&words[2..])
+                        .ok_or_else(|| error("expected 3 numbers after `facet normal`".into()))?,
+                    _ => Vector::new([0.0; 3]),
+                };
+                corners.clear();
+                facet = Some(number + 1);
+            }
+            Some("vertex") => corners.push(
+                point(&words[1..])
+                    .ok_or_else(|| error("expected 3 numbers after `vertex`".into()))?,
+            ),
+            Some("endfacet") => {
+                if corners.len() != 3 {
+                    return Err(error(format!(
+                        "facet with {} vertices, expected 3",
+                        corners.len()
+                    )));
+                }
+
+                triangles.push((normal, [corners[0], corners[1], corners[2]]));
+                corners.clear();
+                facet = None;
+            }
+            Some("solid") | Some("endsolid") | Some("outer") | Some("endloop") | None => (),
+            Some(_) => return Err(error(format!("unexpected `{}`", words[0]))),
+        }
+    }
+
+    if let Some(line) = facet {
+        return Err(invalid(format!(
+            "truncated ASCII STL, the facet of line {} never ends",
+            line
+        )));
+    }
+
+    Ok(from_triangles(triangles))
+}
+
+// Normals of the written facets follow their winding, as transforms leave the
+// stored ones behind
+fn facets(mesh: &IndexedMesh) -> impl Iterator<Item = (Vertex, [Vertex; 3])> + '_ {
+    mesh.faces.iter().map(move |face| {
+        let [a, b, c] = face.vertices.map(|index| mesh.vertices[index]);
+
+        (winding_normal(a, b, c), [a, b, c])
+    })
+}
+
+pub fn write_binary(mesh: &IndexedMesh) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(HEADER + mesh.faces.len() * TRIANGLE);
+    let mut header = b"Binary STL written by Pancake".to_vec();
+
+    header.resize(80, b' ');
+    bytes.extend(header);
+    bytes.extend((mesh.faces.len() as u32).to_le_bytes());
+
+    for (normal, corners) in facets(mesh) {
+        for vector in std::iter::once(normal).chain(corners) {
+            for axis in 0..3 {
+                bytes.extend((vector[axis] as f32).to_le_bytes());
+            }
+        }
+
+        // No attributes
+        bytes.extend([0; 2]);
+    }
+
+    bytes
+}
+
+pub fn write_ascii(mesh: &IndexedMesh, name: &str) -> Vec<u8> {
+    // The name ends the first line, it can't span several
+    let name = name.replace(char::is_whitespace, "_");
+    let mut text = format!("solid {}\n", name);
+
+    for (normal, corners) in facets(mesh) {
+        text += &format!(
+            "  facet normal {:e} {:e} {:e}\n    outer loop\n",
+            normal[0], normal[1], normal[2]
+        );
+
+        for corner in corners.iter() {
+            text += &format!(
+                "      vertex {:e} {:e} {:e}\n",
+                corner[0], corner[1], corner[2]
+            );
+        }
+
+        text += "    endloop\n  endfacet\n";
+    }
+
+    text += &format!("endsolid {}\n", name);
+    text.into_bytes()
+}
@@ -0,0 +1,473 @@
+use std::collections::HashMap;
+use std::io;
+
+use stl_io::{IndexedMesh, Vector};
+
+use super::super::math::{Affine, Matrix};
+use super::super::xml::{self, escape, Element};
+use super::super::zip::{Archive, Writer};
+use super::{from_polygons, invalid, Part};
+
+// Components may nest, but never this deep unless they loop
+const MAX_DEPTH: usize = 32;
+const ROOT_MODEL: &str = "3D/3dmodel.model";
+const CORE: &str = "http://schemas.microsoft.com/3dmanufacturing/core/2015/02";
+const MODEL_RELATIONSHIP: &str = "http://schemas.microsoft.com/3dmanufacturing/2013/01/3dmodel";
+// Per object settings of PrusaSlicer and of Bambu Studio / OrcaSlicer
+const SETTINGS: [&str; 2] = [
+    "Metadata/Slic3r_PE_model.config",
+    "Metadata/model_settings.config",
+];
+
+// 3MF writes the 3x3 linear part row by row then the translation, for points
+// that are row vectors multiplied on the left
+fn parse_matrix(raw: Option<&str>) -> io::Result<Matrix> {
+    let raw = match raw {
+        Some(raw) => raw,
+        None => return Ok(Matrix::IDENTITY),
+    };
+    let v: Vec<f64> = raw
+        .split_whitespace()
+        .map(|value| value.parse().ok().filter(|value: &f64| value.is_finite()))
+        .collect::<Option<_>>()
+        .filter(|values: &Vec<f64>| values.len() == 12)
+        .ok_or_else(|| invalid(format!("invalid transform `{}`", raw)))?;
+
+    Ok(Matrix([
+        [v[0], v[3], v[6], v[9]],
+        [v[1], v[4], v[7], v[10]],
+        [v[2], v[5], v[8], v[11]],
+        [0.0, 0.0, 0.0, 1.0],
+    ]))
+}
+
+fn millimeters(unit: &str) -> io::Result<f64> {
+    match unit {
+        "micron" => Ok(0.001),
+        "millimeter" => Ok(1.0),
+        "centimeter" => Ok(10.0),
+        "inch" => Ok(25.4),
+        "foot" => Ok(304.8),
+        "meter" => Ok(1000.0),
+        _ => Err(invalid(format!("unknown unit `{}`", unit))),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Object {
+    // Ids are only unique within the document of the package holding them
+    pub path: String,
+    pub id: usize,
+    pub name: Option<String>,
+    // In millimeters, with its components in place
+    pub mesh: IndexedMesh,
+    // Metadata of the object and slicer settings overriding the print ones
+    pub settings: Vec<(String, String)>,
+}
+
+// Copy of an object on the build plate
+#[derive(Debug, Clone)]
+pub struct Item {
+    // Index in `Model::objects`
+    pub object: usize,
+    // In millimeters
+    pub transform: Matrix,
+}
+
+#[derive(Debug, Clone)]
+pub struct Model {
+    pub objects: Vec<Object>,
+    pub items: Vec<Item>,
+}
+
+impl Model {
+    // Every item in place, named after its object
+    pub fn parts(&self) -> Vec<Part> {
+        self.items
+            .iter()
+            .map(|item| {
+                let object = &self.objects[item.object];
+
+                Part {
+                    name: object
+                        .name
+                        .clone()
+                        .unwrap_or_else(|| format!("object {}", object.id)),
+                    mesh: object.mesh.clone().affine(&item.transform),
+                    settings: object.settings.clone(),
+                }
+            })
+            .collect()
+    }
+}
+
+// Model documents of the package, parsed once
+struct Documents<'a> {
+    archive: &'a Archive<'a>,
+    parsed: HashMap<String, Element>,
+}
+
+impl<'a> Documents<'a> {
+    fn get(&mut self, path: &str) -> io::Result<&Element> {
+        let path = path.trim_start_matches('/').to_string();
+
+        if !self.parsed.contains_key(&path) {
+            let text = String::from_utf8(self.archive.read(&path)?)
+                .map_err(|_| invalid(format!("{} isn't UTF-8 text", path)))?;
+            let document =
+                xml::parse(&text).map_err(|error| invalid(format!("{}: {}", path, error)))?;
+
+            self.parsed.insert(path.clone(), document);
+        }
+
+        Ok(&self.parsed[&path])
+    }
+
+    fn object(&mut self, path: &str, id: usize) -> io::Result<Element> {
+        self.get(path)?
+            .child("resources")
+            .into_iter()
+            .flat_map(|resources| resources.children("object"))
+            .find(|object| object.attribute("id").and_then(|raw| raw.parse().ok()) == Some(id))
+            .cloned()
+            .ok_or_else(|| invalid(format!("{}: no object {}", path, id)))
+    }
+
+    // Triangles of an object and of its components, placed by `transform`
+    fn flatten(
+        &mut self,
+        path: &str,
+        id: usize,
+        transform: &Matrix,
+        depth: usize,
+        vertices: &mut Vec<Vector<f64>>,
+        polygons: &mut Vec<Vec<usize>>,
+    ) -> io::Result<()> {
+        if depth > MAX_DEPTH {
+            return Err(invalid(format!("object {} contains itself", id)));
+        }
+
+        let object = self.object(path, id)?;
+
+        if let Some(mesh) = object.child("mesh") {
+            let start = vertices.len();
+            let count = mesh
+                .child("vertices")
+                .map_or(0, |list| list.children("vertex").count());
+
+            for vertex in mesh
+                .child("vertices")
+                .into_iter()
+                .flat_map(|list| list.children("vertex"))
+            {
+                let coordinate = |axis: &str| {
+                    vertex
+                        .attribute(axis)
+                        .and_then(|raw| raw.parse().ok())
+                        .filter(|value: &f64| value.is_finite())
+                        .ok_or_else(|| invalid(format!("object {} has an invalid vertex", id)))
+                };
+
+                vertices.push(Vector::new(transform.apply([
+                    coordinate("x")?,
+                    coordinate("y")?,
+                    coordinate("z")?,
+                ])));
+            }
+
+            let mirrored = transform.determinant() < 0.0;
+
+            for triangle in mesh
+                .child("triangles")
+                .into_iter()
+                .flat_map(|list| list.children("triangle"))
+            {
+                let index = |name: &str| {
+                    triangle
+                        .attribute(name)
+                        .and_then(|raw| raw.parse::<usize>().ok())
+                        .filter(|index| *index < count)
+                        .map(|index| start + index)
+                        .ok_or_else(|| invalid(format!("object {} has an invalid triangle", id)))
+                };
+                let (a, b, c) = (index("v1")?, index("v2")?, index("v3")?);
+
+                polygons.push(if mirrored {
+                    vec![a, c, b]
+                } else {
+                    vec![a, b, c]
+                });
+            }
+        }
+
+        for component in object
+            .child("components")
+            .into_iter()
+            .flat_map(|list| list.children("component"))
+        {
+            let child: usize = component
+                .attribute("objectid")
+                .and_then(|raw| raw.parse().ok())
+                .ok_or_else(|| invalid(format!("object {} has an invalid component", id)))?;
+            // The production extension keeps objects in other documents
+            let child_path = component.attribute("path").unwrap_or(path).to_string();
+            let placed = parse_matrix(component.attribute("transform"))?.then(transform);
+
+            self.flatten(&child_path, child, &placed, depth + 1, vertices, polygons)?;
+        }
+
+        Ok(())
+    }
+}
+
+// Object settings of the slicer configuration files, by object id
+fn slicer_settings(archive: &Archive) -> HashMap<usize, Vec<(String, String)>> {
+    let mut settings: HashMap<usize, Vec<(String, String)>> = HashMap::new();
+
+    for path in SETTINGS.iter() {
+        let config = match archive
+            .read(path)
+            .ok()
+            .and_then(|bytes| String::from_utf8(bytes).ok())
+            .and_then(|text| xml::parse(&text).ok())
+        {
+            Some(config) => config,
+            None => continue,
+        };
+
+        for object in config.children("object") {
+            let id = match object.attribute("id").and_then(|raw| raw.parse().ok()) {
+                Some(id) => id,
+                None => continue,
+            };
+
+            settings.entry(id).or_default().extend(
+                object
+                    .children("metadata")
+                    .filter(|metadata| metadata.attribute("type").unwrap_or("object") == "object")
+                    .filter_map(|metadata| {
+                        Some((
+                            metadata.attribute("key")?.to_string(),
+                            metadata.attribute("value")?.to_string(),
+                        ))
+                    }),
+            );
+        }
+    }
+
+    settings
+}
+
+pub fn read(bytes: &[u8]) -> io::Result<Model> {
+    let archive = Archive::new(bytes)?;
+
+    // The package relationships point to the root model
+    let root = archive
+        .read("_rels/.rels")
+        .ok()
+        .and_then(|bytes| String::from_utf8(bytes).ok())
+        .and_then(|text| xml::parse(&text).ok())
+        .and_then(|relationships| {
+            relationships
+                .children("Relationship")
+                .find(|relationship| {
+                    relationship
+                        .attribute("Type")
+                        .map_or(false, |kind| kind.ends_with("/3dmodel"))
+                })
+                .and_then(|relationship| relationship.attribute("Target"))
+                .map(|target| target.trim_start_matches('/').to_string())
+        })
+        .unwrap_or_else(|| ROOT_MODEL.to_string());
+
+    let mut documents = Documents {
+        archive: &archive,
+        parsed: HashMap::new(),
+    };
+    let model = documents.get(&root)?.clone();
+    let scale = millimeters(model.attribute("unit").unwrap_or("millimeter"))?;
+    let scaling = Matrix::scaling(scale, scale, scale);
+    let mut settings = slicer_settings(&archive);
+
+    let mut objects: Vec<Object> = vec![];
+    let mut items = vec![];
+
+    for item in model
+        .child("build")
+        .into_iter()
+        .flat_map(|build| build.children("item"))
+    {
+        let id: usize = item
+            .attribute("objectid")
+            .and_then(|raw| raw.parse().ok())
+            .ok_or_else(|| invalid("build item without a valid objectid".to_string()))?;
+        let path = item
+            .attribute("path")
+            .unwrap_or(&root)
+            .trim_start_matches('/')
+            .to_string();
+        // Translations are in the model unit too
+        let mut transform = parse_matrix(item.attribute("transform"))?;
+
+        for row in transform.0.iter_mut().take(3) {
+            row[3] *= scale;
+        }
+
+        let index = match objects
+            .iter()
+            .position(|object| object.path == path && object.id == id)
+        {
+            Some(index) => index,
+            None => {
+                let element = documents.object(&path, id)?;
+                let mut vertices = vec![];
+                let mut polygons = vec![];
+
+                documents.flatten(&path, id, &scaling, 0, &mut vertices, &mut polygons)?;
+
+                let mut object_settings: Vec<(String, String)> = element
+                    .children("metadatagroup")
+                    .flat_map(|group| group.children("metadata"))
+                    .filter_map(|metadata| {
+                        Some((
+                            metadata.attribute("name")?.to_string(),
+                            metadata.text.trim().to_string(),
+                        ))
+                    })
+                    .collect();
+
+                // The slicer configuration only knows the objects of the root model
+                if path == root {
+                    object_settings.extend(settings.remove(&id).unwrap_or_default());
+                }
+
+                objects.push(Object {
+                    path: path.clone(),
+                    id,
+                    name: element.attribute("name").map(String::from),
+                    mesh: from_polygons(vertices, polygons),
+                    settings: object_settings,
+                });
+                objects.len() - 1
+            }
+        };
+
+        items.push(Item {
+            object: index,
+            transform,
+        });
+    }
+
+    Ok(Model { objects, items })
+}
+
+// Package holding `mesh` as its only object, placed as is
+pub fn write(mesh: &IndexedMesh, name: &str) -> Vec<u8> {
+    let mut model = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <model unit=\"millimeter\" xml:lang=\"en-US\" xmlns=\"{}\">\n\
+         <metadata name=\"Application\">Pancake</metadata>\n\
+         <resources>\n\
+         <object id=\"1\" type=\"model\" name=\"{}\">\n\
+         <mesh>\n\
+         <vertices>\n",
+        CORE,
+        escape(name)
+    );
+
+    for vertex in mesh.vertices.iter() {
+        model += &format!(
+            "<vertex x=\"{}\" y=\"{}\" z=\"{}\"/>\n",
+            vertex[0], vertex[1], vertex[2]
+        );
+    }
+
+    model += "</vertices>\n<triangles>\n";
+
+    for face in mesh.faces.iter() {
+        let [a, b, c] = face.vertices;
+
+        model += &format!("<triangle v1=\"{}\" v2=\"{}\" v3=\"{}\"/>\n", a, b, c);
+    }
+
+    model += "</triangles>\n\
+              </mesh>\n\
+              </object>\n\
+              </resources>\n\
+              <build>\n\
+              <item objectid=\"1\"/>\n\
+              </build>\n\
+              </model>\n";
+
+    let content_types = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+        <Types xmlns=\"http://schemas.openxmlformats.org/package/2006/content-types\">\n\
+        <Default Extension=\"rels\" ContentType=\"application/vnd.openxmlformats-package.relationships+xml\"/>\n\
+        <Default Extension=\"model\" ContentType=\"application/vnd.ms-package.3dmanufacturing-3dmodel+xml\"/>\n\
+        </Types>\n";
+    let relationships = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <Relationships xmlns=\"http://schemas.openxmlformats.org/package/2006/relationships\">\n\
+         <Relationship Target=\"/{}\" Id=\"rel0\" Type=\"{}\"/>\n\
+         </Relationships>\n",
+        ROOT_MODEL, MODEL_RELATIONSHIP
+    );
+
+    let mut package = Writer::new();
+
+    package.add("[Content_Types].xml", content_types.as_bytes());
+    package.add("_rels/.rels", relationships.as_bytes());
+    package.add_deflated(ROOT_MODEL, model.as_bytes());
+    package.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::zip::Writer;
+    use super::{read, CORE, ROOT_MODEL};
+
+    // Model of a single triangle as object 1, at `x`, with build `items`
+    fn document(x: u32, items: &str) -> String {
+        format!(
+            "<model unit=\"millimeter\" xmlns=\"{}\"><resources>\
+             <object id=\"1\" type=\"model\"><mesh><vertices>\
+             <vertex x=\"{}\" y=\"0\" z=\"0\"/><vertex x=\"{}\" y=\"1\" z=\"0\"/>\
+             <vertex x=\"{}\" y=\"0\" z=\"1\"/></vertices><triangles>\
+             <triangle v1=\"0\" v2=\"1\" v3=\"2\"/></triangles></mesh></object>\
+             </resources><build>{}</build></model>",
+            CORE, x, x, x, items
+        )
+    }
+
+    #[test]
+    fn same_id_in_other_documents() {
+        let mut package = Writer::new();
+
+        package.add(
+            ROOT_MODEL,
+            document(
+                0,
+                "<item objectid=\"1\"/><item objectid=\"1\" path=\"/3D/other.model\"/>\
+                 <item objectid=\"1\" path=\"/3D/3dmodel.model\"/>",
+            )
+            .as_bytes(),
+        );
+        package.add("3D/other.model", document(5, "").as_bytes());
+
+        let model = read(&package.finish()).unwrap();
+        let starts: Vec<f64> = model
+            .objects
+            .iter()
+            .map(|object| object.mesh.vertices[0][0])
+            .collect();
+
+        assert_eq!(starts, vec![0.0, 5.0]);
+        assert_eq!(
+            model
+                .items
+                .iter()
+                .map(|item| item.object)
+                .collect::<Vec<_>>(),
+            vec![0, 1, 0]
+        );
+    }
+}
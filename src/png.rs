@@ -0,0 +1,62 @@
+use super::checksum::crc32;
+use super::deflate::deflate;
+
+// Minimal PNG encoder for RGBA and grayscale images, compressed with fixed Huffman deflate
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+
+    for byte in data.iter() {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+
+    b << 16 | a
+}
+
+fn chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend((data.len() as u32).to_be_bytes());
+
+    let start = out.len();
+
+    out.extend(kind);
+    out.extend(data);
+
+    let crc = crc32(&out[start..]);
+    out.extend(crc.to_be_bytes());
+}
+
+fn image(width: u32, height: u32, color: u8, channels: usize, pixels: &[u8]) -> Vec<u8> {
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+
+    let mut header = vec![];
+    header.extend(width.to_be_bytes());
+    header.extend(height.to_be_bytes());
+    // 8 bits per channel, deflate, adaptive filtering, no interlace
+    header.extend([8, color, 0, 0, 0]);
+    chunk(&mut png, b"IHDR", &header);
+
+    // Every row starts with its filter type, none here
+    let raw: Vec<u8> = pixels
+        .chunks(width as usize * channels)
+        .flat_map(|row| std::iter::once(0).chain(row.iter().copied()))
+        .collect();
+
+    let mut zlib = vec![0x78, 0x01];
+    zlib.extend(deflate(&raw));
+    zlib.extend(adler32(&raw).to_be_bytes());
+    chunk(&mut png, b"IDAT", &zlib);
+
+    chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+// `pixels` holds `width` × `height` RGBA values, rows from top to bottom
+pub fn encode(width: u32, height: u32, pixels: &[u8]) -> Vec<u8> {
+    image(width, height, 6, 4, pixels)
+}
+
+// Same with one gray level per pixel
+pub fn encode_gray(width: u32, height: u32, pixels: &[u8]) -> Vec<u8> {
+    image(width, height, 0, 1, pixels)
+}
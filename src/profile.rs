@@ -0,0 +1,137 @@
+use super::flavor::Flavor;
+
+// Settings driving G-code generation. Defaults match the Dagoma DiscoUltimate
+// the start and end G-code blocks were written for.
+#[derive(Debug, Clone)]
+pub struct Profile {
+    // Route travels inside the current layer instead of crossing holes and outer air
+    pub avoid_crossing_perimeters: bool,
+    // Distance kept between combed travels and the slice contours, in millimeters
+    pub comb_inset: f64,
+    // Filament pulled back when a travel has to leave the part, in millimeters
+    pub retract_length: f64,
+    // Feedrate of retractions, in mm/min
+    pub retract_speed: f64,
+    // Retract with G10/G11, leaving length and speed to the firmware settings
+    pub firmware_retraction: bool,
+    // Print the outer wall as one continuous spiral once past the bottom layers
+    pub spiral_vase: bool,
+    // Solid layers printed before the spiral starts
+    pub bottom_layers: usize,
+    // Width of an extruded line, used as spacing of solid fill lines, in millimeters
+    pub extrusion_width: f64,
+    // Replace runs of segments following a circle by G2/G3 arcs, the firmware must support them
+    pub arc_fitting: bool,
+    // Maximum distance between the original segments and a fitted arc, in millimeters
+    pub arc_tolerance: f64,
+    // Write E values relative to the previous move (M83) instead of absolute (M82)
+    pub relative_extrusion: bool,
+    // Nozzle and bed temperatures in °C, a bed temperature of 0 leaves the bed off
+    pub first_layer_temperature: f64,
+    pub temperature: f64,
+    pub first_layer_bed_temperature: f64,
+    pub bed_temperature: f64,
+    // Fan speed (0-255) reached at `fan_full_layer`, ramping up from `fan_start_layer`
+    pub fan_speed: u8,
+    pub fan_start_layer: usize,
+    pub fan_full_layer: usize,
+    // Fan speed overriding the layer one while printing bridges and overhangs
+    pub bridge_fan_speed: Option<u8>,
+    pub overhang_fan_speed: Option<u8>,
+    // Layers estimated faster than this, in seconds, are slowed down with the fan
+    // at `max_fan_speed`. 0 disables the slowdown.
+    pub min_layer_time: f64,
+    // Slowed down layers never go slower than this, in mm/s
+    pub min_print_speed: f64,
+    pub max_fan_speed: u8,
+    // Printable width, depth and height from the origin, in millimeters
+    pub build_volume: [f64; 3],
+    // Machine limits for X, Y, Z and E, in mm/s and mm/s²
+    pub max_feedrate: [f64; 4],
+    pub max_acceleration: [f64; 4],
+    // Acceleration of moves and of retractions, in mm/s²
+    pub acceleration: f64,
+    pub retract_acceleration: f64,
+    // How far the nozzle may stray from a corner to carry speed through it, in millimeters
+    pub junction_deviation: f64,
+    // Filament diameter in millimeters, density in g/cm³ and cost per kilogram
+    pub filament_diameter: f64,
+    pub filament_density: f64,
+    pub filament_cost: f64,
+    // Write M73 progress and remaining time at each layer, and every
+    // `progress_interval` seconds within layers when non zero
+    pub progress: bool,
+    pub progress_interval: f64,
+    // Firmware the G-code is written for
+    pub flavor: Flavor,
+    // Write the binary G-code container instead of text
+    pub binary_gcode: bool,
+    // Width and height in pixels of the preview images embedded in the output
+    pub thumbnails: Vec<(u16, u16)>,
+    // Directory to draw every layer to as SVG, with an HTML page to browse them
+    pub svg_dir: Option<String>,
+    // Layers, numbered from 1, the print pauses before
+    pub pause_layers: Vec<usize>,
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Profile {
+            avoid_crossing_perimeters: false,
+            comb_inset: 0.2,
+            retract_length: 3.0,
+            retract_speed: 5000.0,
+            firmware_retraction: false,
+            spiral_vase: false,
+            bottom_layers: 3,
+            extrusion_width: 0.4,
+            arc_fitting: false,
+            arc_tolerance: 0.05,
+            relative_extrusion: false,
+            first_layer_temperature: 215.0,
+            temperature: 215.0,
+            first_layer_bed_temperature: 0.0,
+            bed_temperature: 0.0,
+            fan_speed: 255,
+            fan_start_layer: 5,
+            fan_full_layer: 5,
+            bridge_fan_speed: None,
+            overhang_fan_speed: None,
+            min_layer_time: 0.0,
+            min_print_speed: 10.0,
+            max_fan_speed: 255,
+            build_volume: [205.0, 205.0, 205.0],
+            max_feedrate: [300.0, 300.0, 12.0, 120.0],
+            max_acceleration: [3000.0, 3000.0, 100.0, 10000.0],
+            acceleration: 1000.0,
+            retract_acceleration: 1500.0,
+            junction_deviation: 0.05,
+            filament_diameter: 1.75,
+            filament_density: 1.24,
+            filament_cost: 20.0,
+            progress: true,
+            progress_interval: 0.0,
+            flavor: Flavor::Marlin,
+            binary_gcode: false,
+            thumbnails: vec![],
+            svg_dir: None,
+            pause_layers: vec![],
+        }
+    }
+}
+
+impl Profile {
+    // Fan speed of a layer, ramping linearly between the start and full layers
+    pub fn layer_fan_speed(&self, layer: usize) -> u8 {
+        if layer < self.fan_start_layer {
+            0
+        } else if layer >= self.fan_full_layer {
+            self.fan_speed
+        } else {
+            let steps = (self.fan_full_layer - self.fan_start_layer + 1) as f64;
+            let step = (layer - self.fan_start_layer + 1) as f64;
+
+            (self.fan_speed as f64 * step / steps).round() as u8
+        }
+    }
+}
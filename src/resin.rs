@@ -0,0 +1,337 @@
+use std::fmt::Write;
+use std::io;
+use std::path::Path;
+
+use super::math::region::Point;
+use super::math::Region;
+use super::png;
+use super::slice::Slice;
+use super::thumbnail::Thumbnail;
+use super::zip;
+
+// Exposure and machine settings of MSLA printers, where an LCD masks the UV
+// light curing each layer at once. Defaults match an Elegoo Mars.
+#[derive(Debug, Clone)]
+pub struct Options {
+    // Pixels of the LCD, and the size of the area they light in millimeters
+    pub resolution: (u32, u32),
+    pub display: (f64, f64),
+    // Seconds of light per layer, longer on the first layers so they stick to the plate
+    pub exposure: f64,
+    pub bottom_exposure: f64,
+    pub bottom_layers: usize,
+    // How far the plate rises to peel each layer off the vat, in millimeters,
+    // and how fast it goes up and back down, in mm/min
+    pub lift_height: f64,
+    pub lift_speed: f64,
+    pub retract_speed: f64,
+    // Seconds left for the resin to settle before exposing
+    pub light_off_delay: f64,
+    // Samples along each side of a pixel, 1 for sharp edges
+    pub antialiasing: u8,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            resolution: (1440, 2560),
+            display: (68.04, 120.96),
+            exposure: 8.0,
+            bottom_exposure: 60.0,
+            bottom_layers: 5,
+            lift_height: 5.0,
+            lift_speed: 60.0,
+            retract_speed: 150.0,
+            light_off_delay: 1.0,
+            antialiasing: 4,
+        }
+    }
+}
+
+// Coverage of row pixels by the span between `x0` and `x1`, in pixels
+fn fill(coverage: &mut [f64], x0: f64, x1: f64, sharp: bool) {
+    let width = coverage.len() as f64;
+
+    if sharp {
+        // Pixels whose center is inside
+        let first = (x0 - 0.5).ceil().clamp(0.0, width) as usize;
+        let last = (x1 - 0.5).ceil().clamp(0.0, width) as usize;
+
+        for c in coverage[first..last.max(first)].iter_mut() {
+            *c += 1.0;
+        }
+
+        return;
+    }
+
+    let (x0, x1) = (x0.clamp(0.0, width), x1.clamp(0.0, width));
+
+    if x1 <= x0 {
+        return;
+    }
+
+    let (first, last) = (x0.floor() as usize, x1.floor() as usize);
+
+    if first == last {
+        coverage[first] += x1 - x0;
+        return;
+    }
+
+    coverage[first] += (first + 1) as f64 - x0;
+
+    for c in coverage[first + 1..last].iter_mut() {
+        *c += 1.0;
+    }
+
+    if last < coverage.len() {
+        coverage[last] += x1 - last as f64;
+    }
+}
+
+// Gray level of every pixel, lit as much as it is inside the even-odd fill of
+// `region`, with `center` of the model in the middle of the display
+fn rasterize(region: &Region, options: &Options, center: Point) -> Vec<u8> {
+    let (width, height) = (options.resolution.0 as usize, options.resolution.1 as usize);
+    let pixel = (
+        options.display.0 / width as f64,
+        options.display.1 / height as f64,
+    );
+    let samples = options.antialiasing.max(1) as usize;
+
+    // Image rows go down while model Y goes up
+    let to_pixel = |p: Point| {
+        (
+            width as f64 / 2.0 + (p.0 - center.0) / pixel.0,
+            height as f64 / 2.0 - (p.1 - center.1) / pixel.1,
+        )
+    };
+    let edges: Vec<(Point, Point)> = region
+        .edges()
+        .map(|(a, b)| (to_pixel(a), to_pixel(b)))
+        .collect();
+
+    let mut pixels = vec![0; width * height];
+    let mut coverage = vec![0.0; width];
+    let mut crossings = vec![];
+
+    for (row, line) in pixels.chunks_mut(width).enumerate() {
+        coverage.iter_mut().for_each(|c| *c = 0.0);
+
+        for sample in 0..samples {
+            let y = row as f64 + (sample as f64 + 0.5) / samples as f64;
+
+            crossings.clear();
+            crossings.extend(
+                edges
+                    .iter()
+                    .filter(|(a, b)| (a.1 > y) != (b.1 > y))
+                    .map(|(a, b)| a.0 + (y - a.1) / (b.1 - a.1) * (b.0 - a.0)),
+            );
+            crossings.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+            for span in crossings.chunks_exact(2) {
+                fill(&mut coverage, span[0], span[1], samples == 1);
+            }
+        }
+
+        for (gray, c) in line.iter_mut().zip(coverage.iter()) {
+            *gray = (c / samples as f64 * 255.0).round().min(255.0) as u8;
+        }
+    }
+
+    pixels
+}
+
+// Writes one PNG per slice and the exposure settings, either in a `.zip`
+// package or in a directory, and returns the layer count
+pub fn export(
+    slices: &[Slice],
+    layer_height: f64,
+    options: &Options,
+    thumbnails: &[Thumbnail],
+    out: &str,
+) -> io::Result<usize> {
+    let regions: Vec<Region> = slices
+        .iter()
+        .map(|slice| Region::new(&slice.polygons))
+        .collect();
+
+    let (mut min, mut max) = (
+        (f64::INFINITY, f64::INFINITY),
+        (f64::NEG_INFINITY, f64::NEG_INFINITY),
+    );
+
+    for p in regions
+        .iter()
+        .flat_map(|region| region.boundaries.iter().flatten())
+    {
+        min = (min.0.min(p.0), min.1.min(p.1));
+        max = (max.0.max(p.0), max.1.max(p.1));
+    }
+
+    if max.0 - min.0 > options.display.0 || max.1 - min.1 > options.display.1 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "The model is {:.1}x{:.1} mm, larger than the {}x{} mm display",
+                max.0 - min.0,
+                max.1 - min.1,
+                options.display.0,
+                options.display.1
+            ),
+        ));
+    }
+
+    let center = ((min.0 + max.0) / 2.0, (min.1 + max.1) / 2.0);
+    let path = Path::new(out);
+    let packed = path
+        .extension()
+        .map_or(false, |extension| extension.eq_ignore_ascii_case("zip"));
+    let name = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or("print");
+    let pixel_area = options.display.0 / options.resolution.0 as f64 * options.display.1
+        / options.resolution.1 as f64;
+
+    let mut files: Vec<(String, Vec<u8>)> = vec![];
+    let mut volume = 0.0;
+
+    for (i, region) in regions.iter().enumerate() {
+        let pixels = rasterize(region, options, center);
+
+        volume +=
+            pixels.iter().map(|gray| *gray as f64 / 255.0).sum::<f64>() * pixel_area * layer_height;
+        files.push((
+            format!("{}{:05}.png", name, i),
+            png::encode_gray(options.resolution.0, options.resolution.1, &pixels),
+        ));
+    }
+
+    for thumbnail in thumbnails.iter() {
+        files.push((
+            format!(
+                "thumbnail/thumbnail{}x{}.png",
+                thumbnail.width, thumbnail.height
+            ),
+            thumbnail.png.clone(),
+        ));
+    }
+
+    // Every layer waits, is exposed, then peeled off
+    let bottom = options.bottom_layers.min(slices.len());
+    let peel = options.light_off_delay
+        + options.lift_height / options.lift_speed * 60.0
+        + options.lift_height / options.retract_speed * 60.0;
+    let time = bottom as f64 * options.bottom_exposure
+        + (slices.len() - bottom) as f64 * options.exposure
+        + slices.len() as f64 * peel;
+
+    let mut config = String::new();
+    writeln!(config, "jobDir = {}", name).unwrap();
+    writeln!(config, "layerHeight = {}", layer_height).unwrap();
+    writeln!(config, "numLayers = {}", slices.len()).unwrap();
+    writeln!(config, "expTime = {}", options.exposure).unwrap();
+    writeln!(config, "expTimeFirst = {}", options.bottom_exposure).unwrap();
+    writeln!(config, "numBottomLayers = {}", options.bottom_layers).unwrap();
+    writeln!(config, "liftHeight = {}", options.lift_height).unwrap();
+    writeln!(config, "liftSpeed = {}", options.lift_speed).unwrap();
+    writeln!(config, "retractSpeed = {}", options.retract_speed).unwrap();
+    writeln!(config, "lightOffDelay = {}", options.light_off_delay).unwrap();
+    writeln!(config, "resolutionX = {}", options.resolution.0).unwrap();
+    writeln!(config, "resolutionY = {}", options.resolution.1).unwrap();
+    writeln!(config, "displayWidth = {}", options.display.0).unwrap();
+    writeln!(config, "displayHeight = {}", options.display.1).unwrap();
+    writeln!(config, "antialiasing = {}", options.antialiasing).unwrap();
+    // Seconds, and milliliters of resin
+    writeln!(config, "printTime = {:.0}", time).unwrap();
+    writeln!(config, "usedMaterial = {:.2}", volume / 1000.0).unwrap();
+    files.push(("config.ini".to_string(), config.into_bytes()));
+
+    if packed {
+        let mut package = zip::Writer::new();
+
+        for (name, data) in files.iter() {
+            package.add(name, data);
+        }
+
+        std::fs::write(path, package.finish())?;
+    } else {
+        for (name, data) in files.iter() {
+            let file = path.join(name);
+
+            if let Some(parent) = file.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+
+            std::fs::write(file, data)?;
+        }
+    }
+
+    Ok(slices.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::math::region::Point;
+    use super::super::math::Region;
+    use super::{rasterize, Options};
+
+    fn rectangle(min: Point, max: Point) -> Vec<Point> {
+        vec![min, (max.0, min.1), max, (min.0, max.1)]
+    }
+
+    // A 10x10 display one millimeter per pixel, the model centered on it
+    fn raster(boundaries: Vec<Vec<Point>>, antialiasing: u8) -> Vec<u8> {
+        let options = Options {
+            resolution: (10, 10),
+            display: (10.0, 10.0),
+            antialiasing,
+            ..Options::default()
+        };
+
+        rasterize(&Region { boundaries }, &options, (5.0, 5.0))
+    }
+
+    #[test]
+    fn hole() {
+        let outer = rectangle((0.0, 0.0), (10.0, 10.0));
+        let hole = rectangle((4.0, 4.0), (6.0, 6.0));
+        let mut reversed = hole.clone();
+
+        reversed.reverse();
+
+        // Even-odd, the winding of the hole does not matter
+        for hole in [hole, reversed] {
+            let pixels = raster(vec![outer.clone(), hole], 1);
+
+            for (i, gray) in pixels.iter().enumerate() {
+                let (row, column) = (i / 10, i % 10);
+                let inside_hole = (4..6).contains(&row) && (4..6).contains(&column);
+
+                assert_eq!(
+                    *gray,
+                    if inside_hole { 0 } else { 255 },
+                    "{} {}",
+                    row,
+                    column
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn antialiased_edges() {
+        // Three quarters of columns 2 and 7 are covered, half of row 7
+        let pixels = raster(vec![rectangle((2.25, 2.5), (7.75, 8.0))], 4);
+        let at = |row: usize, column: usize| pixels[row * 10 + column];
+
+        assert_eq!(at(5, 1), 0);
+        assert_eq!(at(5, 2), 191);
+        assert_eq!(at(5, 5), 255);
+        assert_eq!(at(5, 7), 191);
+        assert_eq!(at(7, 5), 128);
+        assert_eq!(at(7, 2), 96);
+        assert_eq!(at(8, 5), 0);
+    }
+}
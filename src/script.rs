@@ -0,0 +1,367 @@
+use std::error::Error;
+use std::fmt;
+
+use lalrpop_util::ParseError;
+
+use super::ast::{Axis, Centering, Transform};
+use super::transform::TransformsParser;
+
+// Words of the transform language, and how they are written
+const WORDS: [(&str, &str); 20] = [
+    ("rotate", "rotate("),
+    ("rotate_axis", "rotate_axis("),
+    ("center", "center("),
+    ("drop_to_bed", "drop_to_bed()"),
+    ("place_on_bed", "place_on_bed()"),
+    ("lay_flat", "lay_flat()"),
+    ("auto_orient", "auto_orient()"),
+    ("move", "move("),
+    ("scale", "scale("),
+    ("scale_to", "scale_to("),
+    ("homothety", "homothety("),
+    ("mirror", "mirror("),
+    ("bbox", "bbox"),
+    ("mass", "mass"),
+    ("bed", "bed"),
+    ("width", "width"),
+    ("depth", "depth"),
+    ("height", "height"),
+    ("deg", "deg"),
+    ("rad", "rad"),
+];
+
+// Mistake in a transform script, with the bytes of the source it is about
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    source: String,
+    start: usize,
+    end: usize,
+    message: String,
+    help: Option<String>,
+}
+
+impl Diagnostic {
+    fn new(source: &str, start: usize, end: usize, message: String) -> Self {
+        Diagnostic {
+            source: source.to_string(),
+            start,
+            end,
+            message,
+            help: None,
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Only the line the mistake starts on, should the script span several
+        let line_start = self.source[..self.start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = self.source[self.start..]
+            .find('\n')
+            .map_or(self.source.len(), |i| self.start + i);
+        let column = self.source[line_start..self.start].chars().count();
+        let width = self.source[self.start..self.end.min(line_end)]
+            .chars()
+            .count()
+            .max(1);
+
+        writeln!(f, "Invalid transform: {}", self.message)?;
+        writeln!(f, "  {}", &self.source[line_start..line_end])?;
+        write!(f, "  {}{}", " ".repeat(column), "^".repeat(width))?;
+
+        if let Some(help) = self.help.as_ref() {
+            write!(f, "\n  help: {}", help)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Error for Diagnostic {}
+
+// Transforms of `raw`, checked before they reach any mesh
+pub fn parse(raw: &str) -> Result<Vec<Transform>, Diagnostic> {
+    let transforms = TransformsParser::new()
+        .parse(raw)
+        .map_err(|error| syntax(raw, error))?;
+
+    for (start, transform, end) in transforms.iter() {
+        if let Some(message) = check(transform) {
+            return Err(Diagnostic::new(raw, *start, *end, message));
+        }
+    }
+
+    Ok(transforms
+        .into_iter()
+        .map(|(_, transform, _)| transform)
+        .collect())
+}
+
+fn syntax<T, E: fmt::Display>(raw: &str, error: ParseError<usize, T, E>) -> Diagnostic {
+    match error {
+        ParseError::InvalidToken { location } => {
+            let (start, end) = word(raw, location);
+            let mut diagnostic =
+                Diagnostic::new(raw, start, end, format!("unknown `{}`", &raw[start..end]));
+
+            diagnostic.help = suggestion(&raw[start..end]);
+            diagnostic
+        }
+        ParseError::UnrecognizedEOF { location, expected } => Diagnostic::new(
+            raw,
+            location,
+            location,
+            format!("expected {}, found the end", plain(&expected)),
+        ),
+        ParseError::UnrecognizedToken {
+            token: (start, _, end),
+            expected,
+        } => {
+            let found = &raw[start..end];
+            let comma = expected.iter().any(|token| token == "\",\"");
+            let mut diagnostic = Diagnostic::new(
+                raw,
+                start,
+                end,
+                format!("expected {}, found `{}`", plain(&expected), found),
+            );
+
+            diagnostic.help = if found == ")" && comma {
+                Some("this transform takes more values".to_string())
+            } else if found == "," && expected.iter().any(|token| token == "\")\"") {
+                Some("this transform takes fewer values".to_string())
+            } else if expected.len() == 1 && comma {
+                Some("transforms are separated by commas".to_string())
+            } else {
+                None
+            };
+            diagnostic
+        }
+        ParseError::ExtraToken {
+            token: (start, _, end),
+        } => Diagnostic::new(
+            raw,
+            start,
+            end,
+            format!("unexpected `{}`", &raw[start..end]),
+        ),
+        ParseError::User { error } => Diagnostic::new(raw, 0, raw.len(), error.to_string()),
+    }
+}
+
+// Values no mesh can be transformed by
+fn check(transform: &Transform) -> Option<String> {
+    let values = match *transform {
+        Transform::Rotate(_, angle) => vec![angle],
+        Transform::RotateAxis(x, y, z, angle) => vec![x, y, z, angle],
+        Transform::Move(x, y, z) | Transform::Scale(x, y, z) => vec![x, y, z],
+        Transform::ScaleTo(_, size) => vec![size],
+        Transform::Homothety(factor) => vec![factor],
+        _ => vec![],
+    };
+
+    if values.iter().any(|value| value.is_nan()) {
+        return Some("a value is not a number, as from 0/0".to_string());
+    }
+
+    if values.iter().any(|value| value.is_infinite()) {
+        return Some("a value is infinite, as from a division by 0".to_string());
+    }
+
+    match *transform {
+        Transform::Scale(x, y, z) => {
+            let flat: Vec<&str> = [(x, "X"), (y, "Y"), (z, "Z")]
+                .iter()
+                .filter(|(value, _)| *value == 0.0)
+                .map(|(_, name)| *name)
+                .collect();
+
+            if flat.is_empty() {
+                None
+            } else {
+                Some(format!(
+                    "scaling by 0 along {} flattens the model",
+                    flat.join(" and ")
+                ))
+            }
+        }
+        Transform::Homothety(0.0) => Some("scaling by 0 shrinks the model to a point".to_string()),
+        Transform::ScaleTo(_, size) if size <= 0.0 => {
+            Some(format!("the size must be above 0mm, not {}", size))
+        }
+        Transform::RotateAxis(x, y, z, _) if x == 0.0 && y == 0.0 && z == 0.0 => {
+            Some("the axis of rotation has no direction".to_string())
+        }
+        Transform::Center(Centering::Axis(Axis::Z)) => {
+            Some("centering along Z sinks the model half into the bed".to_string())
+        }
+        _ => None,
+    }
+}
+
+// Expected tokens as the grammar names them, in words
+fn plain(expected: &[String]) -> String {
+    let mut words: Vec<String> = vec![];
+    let mut push = |word: String| {
+        if !words.contains(&word) {
+            words.push(word);
+        }
+    };
+    let literals: Vec<&str> = expected
+        .iter()
+        .filter(|token| !token.starts_with("r#"))
+        .map(|token| token.trim_matches('"'))
+        .collect();
+    let number = expected.iter().any(|token| token.contains("0-9"));
+    let operator = ["+", "-", "*", "/"]
+        .iter()
+        .all(|sign| literals.contains(sign));
+
+    if literals.iter().any(|literal| literal.ends_with("()")) {
+        push("a transform".to_string());
+    }
+
+    if number {
+        push("a number".to_string());
+    }
+
+    if expected
+        .iter()
+        .any(|token| token.starts_with("r#") && !token.contains("0-9"))
+    {
+        push("an axis".to_string());
+    }
+
+    if operator {
+        push("an operator".to_string());
+    }
+
+    for literal in literals {
+        let transform = literal.len() > 1 && (literal.ends_with('(') || literal.ends_with(')'));
+        // Signs and parentheses start numbers
+        let part_of_number = number && ["(", "+", "-"].contains(&literal);
+        let part_of_operator = operator && ["+", "-", "*", "/"].contains(&literal);
+
+        if !transform && !part_of_number && !part_of_operator {
+            push(format!("`{}`", literal));
+        }
+    }
+
+    match words.split_last() {
+        None => "nothing".to_string(),
+        Some((last, [])) => last.clone(),
+        Some((last, rest)) => format!("{} or {}", rest.join(", "), last),
+    }
+}
+
+// Bytes of the word around `location`, or of the character there
+fn word(raw: &str, location: usize) -> (usize, usize) {
+    let letter = |c: char| c.is_alphabetic() || c == '_';
+    let start = raw[..location]
+        .char_indices()
+        .rev()
+        .take_while(|(_, c)| letter(*c))
+        .last()
+        .map_or(location, |(i, _)| i);
+    let end = raw[location..]
+        .char_indices()
+        .find(|(_, c)| !letter(*c))
+        .map_or(raw.len(), |(i, _)| location + i);
+
+    if start < end {
+        (start, end)
+    } else {
+        let width = raw[location..].chars().next().map_or(0, char::len_utf8);
+
+        (location, location + width)
+    }
+}
+
+fn suggestion(typed: &str) -> Option<String> {
+    if let Some((_, written)) = WORDS.iter().find(|(word, _)| *word == typed) {
+        return Some(format!(
+            "write `{}`, with its parentheses right after the name",
+            written
+        ));
+    }
+
+    let limit = (typed.chars().count() / 3).max(1);
+
+    WORDS
+        .iter()
+        .map(|(word, written)| (distance(typed, word), written))
+        .filter(|(edits, _)| *edits <= limit)
+        .min_by_key(|(edits, _)| *edits)
+        .map(|(_, written)| format!("did you mean `{}`?", written))
+}
+
+// Edits turning `a` into `b`, swapping two neighbours counting as one
+fn distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+
+    for (j, value) in d[0].iter_mut().enumerate() {
+        *value = j;
+    }
+
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+
+            d[i][j] = (d[i - 1][j] + 1)
+                .min(d[i][j - 1] + 1)
+                .min(d[i - 1][j - 1] + cost);
+
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+
+    d[a.len()][b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::ast::{Axis, Centering, Transform};
+    use super::parse;
+
+    #[test]
+    fn center_axis() {
+        assert!(matches!(
+            parse("center(x)").unwrap()[0],
+            Transform::Center(Centering::Axis(Axis::X))
+        ));
+        assert!(parse("center(z)").is_err());
+    }
+
+    #[test]
+    fn caret() {
+        let diagnostic = parse("move(1, 2, 3), homothety(0)").unwrap_err();
+
+        assert_eq!(
+            diagnostic.to_string(),
+            "Invalid transform: scaling by 0 shrinks the model to a point\n\
+             \x20 move(1, 2, 3), homothety(0)\n\
+             \x20                ^^^^^^^^^^^^"
+        );
+    }
+
+    #[test]
+    fn misspelled() {
+        let diagnostic = parse("move(1, 2, 3), rotat(z, 90deg)").unwrap_err();
+
+        assert_eq!((diagnostic.start, diagnostic.end), (15, 20));
+        assert_eq!(diagnostic.help.as_deref(), Some("did you mean `rotate(`?"));
+        assert!(diagnostic.to_string().ends_with(
+            "  move(1, 2, 3), rotat(z, 90deg)\n\
+             \x20                ^^^^^\n\
+             \x20 help: did you mean `rotate(`?"
+        ));
+    }
+}
@@ -11,6 +11,16 @@ pub struct Slice {
     pub polygons: Vec<Polygon>,
 }
 
+impl Slice {
+    // Closed polygon enclosing the largest area, the outer wall of a single part
+    pub fn outer_contour(&self) -> Option<&Polygon> {
+        self.polygons
+            .iter()
+            .filter(|polygon| polygon.is_closed())
+            .max_by(|a, b| a.area().abs().partial_cmp(&b.area().abs()).unwrap())
+    }
+}
+
 pub trait GetSlice {
     fn get_slice(&self, height: f64) -> Option<Slice>;
 }
@@ -0,0 +1,308 @@
+use std::fmt::Write;
+
+use super::gcode::Vec4;
+use super::math::{Polygon, Region, X, Y};
+use super::toolpath::{Command, Role};
+
+// Line widths in millimeters
+const CONTOUR_WIDTH: f64 = 0.05;
+const CHAIN_WIDTH: f64 = 0.15;
+const TRAVEL_WIDTH: f64 = 0.05;
+// Blank border around the drawing, in millimeters
+const MARGIN: f64 = 2.0;
+
+const OUTER: &str = "#1f77b4";
+const HOLE: &str = "#d62728";
+const CHAIN: &str = "#e377c2";
+const PERIMETER: &str = "#ff7f0e";
+const INFILL: &str = "#2ca02c";
+const TRAVEL: &str = "#7f7f7f";
+
+struct Bounds {
+    min: (f64, f64),
+    max: (f64, f64),
+}
+
+impl Bounds {
+    fn add(&mut self, (x, y): (f64, f64)) {
+        self.min = (self.min.0.min(x), self.min.1.min(y));
+        self.max = (self.max.0.max(x), self.max.1.max(y));
+    }
+}
+
+fn points(polygon: &Polygon) -> Vec<(f64, f64)> {
+    let mut points: Vec<(f64, f64)> = polygon
+        .iter()
+        .map(|segment| (segment.vertices[0][X], segment.vertices[0][Y]))
+        .collect();
+
+    if let Some(last) = polygon.last() {
+        points.push((last.vertices[1][X], last.vertices[1][Y]));
+    }
+
+    points
+}
+
+// Holes are the contours lying inside an odd number of other contours
+fn is_hole(polygon: &Polygon, closed: &[&Polygon]) -> bool {
+    let p = match polygon.first() {
+        Some(segment) => (segment.vertices[0][X], segment.vertices[0][Y]),
+        None => return false,
+    };
+
+    closed
+        .iter()
+        .filter(|other| !std::ptr::eq(**other, polygon))
+        .filter(|other| Region::new(&[(**other).clone()]).contains(p))
+        .count()
+        % 2
+        == 1
+}
+
+// Drawing of a slice and of the toolpath printing it. `from` is the print
+// head position before the first command, and `shift` what the toolpath
+// adds to model coordinates.
+pub fn layer(
+    layer: usize,
+    polygons: &[Polygon],
+    commands: &[Command],
+    from: &Vec4,
+    shift: (f64, f64),
+    extrusion_width: f64,
+) -> String {
+    let mut bounds = Bounds {
+        min: (f64::INFINITY, f64::INFINITY),
+        max: (f64::NEG_INFINITY, f64::NEG_INFINITY),
+    };
+    let mut body = String::new();
+    let closed: Vec<&Polygon> = polygons.iter().filter(|p| p.is_closed()).collect();
+
+    // Toolpath first so contours stay visible on top of it
+    let mut pos = from.clone();
+    let mut role = Role::Perimeter;
+
+    for command in commands.iter() {
+        let mut next = pos.clone();
+        command.apply(&mut next);
+
+        let (a, b) = (
+            (pos.x - shift.0, pos.y - shift.1),
+            (next.x - shift.0, next.y - shift.1),
+        );
+        let extruding = next.e > pos.e;
+        let (color, width, dashed) = if extruding {
+            let color = match role {
+                Role::Perimeter => PERIMETER,
+                Role::Infill => INFILL,
+            };
+
+            (color, extrusion_width, false)
+        } else {
+            (TRAVEL, TRAVEL_WIDTH, true)
+        };
+        let style = format!(
+            "stroke=\"{}\" stroke-width=\"{}\"{}",
+            color,
+            width,
+            if dashed {
+                " stroke-dasharray=\"0.3 0.3\""
+            } else {
+                " stroke-opacity=\"0.6\" stroke-linecap=\"round\""
+            }
+        );
+
+        match command {
+            Command::Role(new) => role = *new,
+            Command::Move(_) if a != b => {
+                bounds.add(a);
+                bounds.add(b);
+                writeln!(
+                    body,
+                    "<line x1=\"{:.3}\" y1=\"{:.3}\" x2=\"{:.3}\" y2=\"{:.3}\" {}/>",
+                    a.0, a.1, b.0, b.1, style
+                )
+                .unwrap();
+            }
+            Command::Arc {
+                center, clockwise, ..
+            } => {
+                let radius = center.0.hypot(center.1);
+                let (cx, cy) = (a.0 + center.0, a.1 + center.1);
+                let start = (a.1 - cy).atan2(a.0 - cx);
+                let end = (b.1 - cy).atan2(b.0 - cx);
+                let mut sweep = if *clockwise { start - end } else { end - start };
+
+                if sweep <= 0.0 {
+                    sweep += 2.0 * std::f64::consts::PI;
+                }
+
+                bounds.add(a);
+                bounds.add(b);
+                writeln!(
+                    body,
+                    "<path d=\"M {:.3} {:.3} A {:.3} {:.3} 0 {} {} {:.3} {:.3}\" fill=\"none\" {}/>",
+                    a.0,
+                    a.1,
+                    radius,
+                    radius,
+                    (sweep > std::f64::consts::PI) as u8,
+                    (!clockwise) as u8,
+                    b.0,
+                    b.1,
+                    style
+                )
+                .unwrap();
+            }
+            _ => (),
+        }
+
+        pos = next;
+    }
+
+    for polygon in polygons.iter() {
+        let points = points(polygon);
+
+        for p in points.iter() {
+            bounds.add(*p);
+        }
+
+        let list: Vec<String> = points
+            .iter()
+            .map(|(x, y)| format!("{:.3},{:.3}", x, y))
+            .collect();
+
+        if polygon.is_closed() {
+            let color = if is_hole(polygon, &closed) {
+                HOLE
+            } else {
+                OUTER
+            };
+
+            writeln!(
+                body,
+                "<polygon points=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"{}\"/>",
+                list.join(" "),
+                color,
+                CONTOUR_WIDTH
+            )
+            .unwrap();
+        } else if let (Some(first), Some(last)) = (points.first(), points.last()) {
+            // Open chains are what `Polygon::build` failed to close, make them stand out
+            writeln!(
+                body,
+                "<polyline points=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"{}\"/>",
+                list.join(" "),
+                CHAIN,
+                CHAIN_WIDTH
+            )
+            .unwrap();
+
+            for (x, y) in [first, last].iter() {
+                writeln!(
+                    body,
+                    "<circle cx=\"{:.3}\" cy=\"{:.3}\" r=\"{}\" fill=\"{}\"/>",
+                    x,
+                    y,
+                    CHAIN_WIDTH * 2.0,
+                    CHAIN
+                )
+                .unwrap();
+            }
+        }
+    }
+
+    if bounds.min.0 > bounds.max.0 {
+        bounds.min = (0.0, 0.0);
+        bounds.max = (0.0, 0.0);
+    }
+
+    let (width, height) = (
+        bounds.max.0 - bounds.min.0 + 2.0 * MARGIN,
+        bounds.max.1 - bounds.min.1 + 2.0 * MARGIN,
+    );
+
+    // Model Y goes up, SVG Y goes down
+    format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"{:.3} {:.3} {:.3} {:.3}\" width=\"{:.0}mm\" height=\"{:.0}mm\">\n\
+         <title>Layer {}</title>\n\
+         <g transform=\"scale(1,-1)\">\n{}</g>\n</svg>\n",
+        bounds.min.0 - MARGIN,
+        -bounds.max.1 - MARGIN,
+        width,
+        height,
+        width,
+        height,
+        layer,
+        body
+    )
+}
+
+// Page showing the layers one at a time, with a slider and arrow keys to move through them
+pub fn index(layers: usize) -> String {
+    let legend = [
+        (OUTER, "outer contour"),
+        (HOLE, "hole"),
+        (CHAIN, "open chain"),
+        (PERIMETER, "perimeter"),
+        (INFILL, "infill"),
+        (TRAVEL, "travel"),
+    ]
+    .iter()
+    .map(|(color, name)| format!("<span style=\"color:{}\">&#9632; {}</span>", color, name))
+    .collect::<Vec<String>>()
+    .join("\n");
+
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Pancake layers</title>
+<style>
+body {{ font-family: sans-serif; margin: 1em; }}
+#controls {{ display: flex; gap: 1em; align-items: center; }}
+#slider {{ flex: 1; }}
+#legend span {{ margin-right: 1em; }}
+img {{ max-width: 100%; max-height: 85vh; border: 1px solid #ccc; margin-top: 1em; }}
+</style>
+</head>
+<body>
+<div id="controls">
+<button id="previous">&larr;</button>
+<input id="slider" type="range" min="1" max="{layers}" value="1">
+<button id="next">&rarr;</button>
+<span id="label"></span>
+</div>
+<div id="legend">
+{legend}
+</div>
+<img id="layer" alt="">
+<script>
+const count = {layers};
+const slider = document.getElementById("slider");
+function show(layer) {{
+    layer = Math.min(Math.max(layer, 1), count);
+    slider.value = layer;
+    document.getElementById("layer").src = "layer_" + String(layer).padStart(4, "0") + ".svg";
+    document.getElementById("label").textContent = "Layer " + layer + " / " + count;
+}}
+slider.oninput = () => show(Number(slider.value));
+document.getElementById("previous").onclick = () => show(Number(slider.value) - 1);
+document.getElementById("next").onclick = () => show(Number(slider.value) + 1);
+document.onkeydown = (event) => {{
+    if (event.key === "ArrowLeft") show(Number(slider.value) - 1);
+    if (event.key === "ArrowRight") show(Number(slider.value) + 1);
+}};
+show(1);
+</script>
+</body>
+</html>
+"#,
+        layers = layers,
+        legend = legend
+    )
+}
+
+pub fn path(dir: &str, layer: usize) -> String {
+    format!("{}/layer_{:04}.svg", dir, layer)
+}
@@ -0,0 +1,226 @@
+use stl_io::IndexedMesh;
+
+use super::png;
+
+// Camera turned this far around Z from the front, and looking down this much, in radians
+const AZIMUTH: f64 = std::f64::consts::FRAC_PI_4;
+const ELEVATION: f64 = std::f64::consts::FRAC_PI_6;
+// Rendered this many times larger then downsampled, to smooth edges
+const SUPERSAMPLING: usize = 3;
+// Blank border, as a fraction of the image size
+const MARGIN: f64 = 0.05;
+const COLOR: [f64; 3] = [237.0, 125.0, 49.0];
+const AMBIENT: f64 = 0.3;
+// Base64 characters per G-code line
+const LINE_WIDTH: usize = 78;
+
+#[derive(Debug, Clone)]
+pub struct Thumbnail {
+    pub width: u16,
+    pub height: u16,
+    pub png: Vec<u8>,
+}
+
+// Screen coordinates and depth, away from the camera, of a point of the model
+fn project(p: [f64; 3]) -> [f64; 3] {
+    let (sin_a, cos_a) = AZIMUTH.sin_cos();
+    let (sin_e, cos_e) = ELEVATION.sin_cos();
+    let x = p[0] * cos_a - p[1] * sin_a;
+    let y = p[0] * sin_a + p[1] * cos_a;
+
+    [x, y * sin_e + p[2] * cos_e, y * cos_e - p[2] * sin_e]
+}
+
+fn edge(a: [f64; 3], b: [f64; 3], x: f64, y: f64) -> f64 {
+    (b[0] - a[0]) * (y - a[1]) - (b[1] - a[1]) * (x - a[0])
+}
+
+// Flat shaded view of the mesh from the front right, on a transparent background
+pub fn render(mesh: &IndexedMesh, width: u16, height: u16) -> Thumbnail {
+    let (w, h) = (
+        width as usize * SUPERSAMPLING,
+        height as usize * SUPERSAMPLING,
+    );
+    let points: Vec<[f64; 3]> = mesh
+        .vertices
+        .iter()
+        .map(|v| project([v[0], v[1], v[2]]))
+        .collect();
+
+    let (mut min, mut max) = ([f64::INFINITY; 2], [f64::NEG_INFINITY; 2]);
+
+    for p in points.iter() {
+        for axis in 0..2 {
+            min[axis] = min[axis].min(p[axis]);
+            max[axis] = max[axis].max(p[axis]);
+        }
+    }
+
+    let usable = (1.0 - 2.0 * MARGIN) * w.min(h) as f64;
+    let scale = usable / (max[0] - min[0]).max(max[1] - min[1]).max(f64::EPSILON);
+    let center = [(min[0] + max[0]) / 2.0, (min[1] + max[1]) / 2.0];
+
+    // Image rows go down while screen Y goes up
+    let screen: Vec<[f64; 3]> = points
+        .iter()
+        .map(|p| {
+            [
+                w as f64 / 2.0 + (p[0] - center[0]) * scale,
+                h as f64 / 2.0 - (p[1] - center[1]) * scale,
+                p[2],
+            ]
+        })
+        .collect();
+
+    let mut depth = vec![f64::INFINITY; w * h];
+    let mut shade = vec![None; w * h];
+
+    for face in mesh.faces.iter() {
+        let [a, b, c] = [
+            screen[face.vertices[0]],
+            screen[face.vertices[1]],
+            screen[face.vertices[2]],
+        ];
+        let area = edge(a, b, c[0], c[1]);
+
+        if area.abs() < f64::EPSILON {
+            continue;
+        }
+
+        // Lit from the camera, whichever way the face is wound
+        let [pa, pb, pc] = [
+            points[face.vertices[0]],
+            points[face.vertices[1]],
+            points[face.vertices[2]],
+        ];
+        let (u, v) = (
+            [pb[0] - pa[0], pb[1] - pa[1], pb[2] - pa[2]],
+            [pc[0] - pa[0], pc[1] - pa[1], pc[2] - pa[2]],
+        );
+        let normal = [
+            u[1] * v[2] - u[2] * v[1],
+            u[2] * v[0] - u[0] * v[2],
+            u[0] * v[1] - u[1] * v[0],
+        ];
+        let length = (normal[0].powi(2) + normal[1].powi(2) + normal[2].powi(2)).sqrt();
+        let light = AMBIENT + (1.0 - AMBIENT) * (normal[2] / length).abs();
+
+        let x0 = a[0].min(b[0]).min(c[0]).floor().max(0.0) as usize;
+        let x1 = (a[0].max(b[0]).max(c[0]).ceil() as usize).min(w - 1);
+        let y0 = a[1].min(b[1]).min(c[1]).floor().max(0.0) as usize;
+        let y1 = (a[1].max(b[1]).max(c[1]).ceil() as usize).min(h - 1);
+
+        for y in y0..=y1 {
+            for x in x0..=x1 {
+                let (px, py) = (x as f64 + 0.5, y as f64 + 0.5);
+                let weights = [
+                    edge(b, c, px, py) / area,
+                    edge(c, a, px, py) / area,
+                    edge(a, b, px, py) / area,
+                ];
+
+                if weights.iter().any(|weight| *weight < 0.0) {
+                    continue;
+                }
+
+                let z = weights[0] * a[2] + weights[1] * b[2] + weights[2] * c[2];
+                let i = y * w + x;
+
+                if z < depth[i] {
+                    depth[i] = z;
+                    shade[i] = Some(light);
+                }
+            }
+        }
+    }
+
+    // Average every block of samples into a pixel, alpha being the covered part
+    let mut pixels = Vec::with_capacity(width as usize * height as usize * 4);
+    let samples = (SUPERSAMPLING * SUPERSAMPLING) as f64;
+
+    for y in 0..height as usize {
+        for x in 0..width as usize {
+            let lights: Vec<f64> = (0..SUPERSAMPLING * SUPERSAMPLING)
+                .filter_map(|s| {
+                    let (sx, sy) = (
+                        x * SUPERSAMPLING + s % SUPERSAMPLING,
+                        y * SUPERSAMPLING + s / SUPERSAMPLING,
+                    );
+
+                    shade[sy * w + sx]
+                })
+                .collect();
+
+            if lights.is_empty() {
+                pixels.extend([0, 0, 0, 0]);
+                continue;
+            }
+
+            let light = lights.iter().sum::<f64>() / lights.len() as f64;
+
+            for channel in COLOR.iter() {
+                pixels.push((channel * light).round().min(255.0) as u8);
+            }
+
+            pixels.push((lights.len() as f64 / samples * 255.0).round() as u8);
+        }
+    }
+
+    Thumbnail {
+        width,
+        height,
+        png: png::encode(width as u32, height as u32, &pixels),
+    }
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut text = String::with_capacity((data.len() + 2) / 3 * 4);
+
+    for group in data.chunks(3) {
+        let bytes = [
+            group[0],
+            *group.get(1).unwrap_or(&0),
+            *group.get(2).unwrap_or(&0),
+        ];
+        let value = (bytes[0] as usize) << 16 | (bytes[1] as usize) << 8 | bytes[2] as usize;
+
+        for i in 0..4 {
+            if i <= group.len() {
+                text.push(ALPHABET[(value >> (18 - 6 * i)) & 0x3F] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+
+    text
+}
+
+impl Thumbnail {
+    // Text of the comment lines embedding the PNG, as read by printer
+    // firmwares and OctoPrint plugins
+    pub fn comments(&self) -> Vec<String> {
+        let encoded = base64(&self.png);
+        let mut lines = vec![
+            String::new(),
+            format!(
+                " thumbnail begin {}x{} {}",
+                self.width,
+                self.height,
+                encoded.len()
+            ),
+        ];
+
+        lines.extend(
+            encoded
+                .as_bytes()
+                .chunks(LINE_WIDTH)
+                .map(|line| format!(" {}", String::from_utf8_lossy(line))),
+        );
+        lines.push(" thumbnail end".to_string());
+        lines.push(String::new());
+        lines
+    }
+}
@@ -0,0 +1,251 @@
+use std::io;
+
+use super::flavor::GcodeFlavor;
+use super::gcode::Vec4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Heater {
+    Nozzle,
+    Bed,
+}
+
+// What the extrusions that follow are part of
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Role {
+    Perimeter,
+    Infill,
+}
+
+// One instruction of the G-code stream produced by `Printer`, positions are
+// absolute printer coordinates.
+#[derive(Debug, Clone)]
+pub enum Command {
+    // Linear move
+    Move(Vec4),
+    // Circular move in the XY plane, `center` is relative to the start point
+    Arc {
+        to: Vec4,
+        center: (f64, f64),
+        clockwise: bool,
+    },
+    // Filament only move, used by retractions
+    Extrude {
+        e: f64,
+        feedrate: f64,
+    },
+    // Firmware retraction and its undoing
+    Retract,
+    Unretract,
+    // Set the current E position as zero
+    ResetE,
+    // Whether E values of the commands that follow are relative
+    ExtrusionMode {
+        relative: bool,
+    },
+    Feedrate(f64),
+    // Heater target in °C, optionally waiting for it to be reached
+    Temperature {
+        heater: Heater,
+        celsius: f64,
+        wait: bool,
+    },
+    // Set a heater and wait for it to get down to the temperature
+    CoolDown {
+        heater: Heater,
+        celsius: f64,
+    },
+    // Part cooling fan speed, from 0 (off) to 255
+    Fan(u8),
+    // Start of a layer, numbered from 1
+    Layer(usize),
+    Role(Role),
+    // Print progress shown by the printer display, with the minutes left
+    Progress {
+        percent: u8,
+        minutes: u32,
+    },
+    // Stop until the user resumes the print
+    Pause,
+    // Bed levelling probe
+    Level,
+    Comment(String),
+    // Verbatim G-code: start and end blocks
+    Raw(String),
+}
+
+impl Command {
+    // Position of the print head once the command is executed
+    pub fn apply(&self, pos: &mut Vec4) {
+        match self {
+            Command::Move(to) | Command::Arc { to, .. } => *pos = to.clone(),
+            Command::Extrude { e, .. } => pos.e = *e,
+            Command::ResetE => pos.e = 0.0,
+            _ => (),
+        }
+    }
+}
+
+// Turns the command stream into G-code text for a firmware flavor, keeping
+// track of the print head to write E either absolute or relative to the
+// previous command
+#[derive(Debug, Clone)]
+pub struct Writer {
+    pos: Vec4,
+    relative_e: bool,
+    flavor: &'static dyn GcodeFlavor,
+}
+
+impl Writer {
+    pub fn new(relative_e: bool, flavor: &'static dyn GcodeFlavor) -> Self {
+        Writer {
+            pos: Vec4 {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+                e: 0.0,
+            },
+            relative_e,
+            flavor,
+        }
+    }
+
+    fn number(&self, value: f64) -> String {
+        self.flavor.number(value, self.flavor.decimals().0)
+    }
+
+    // Fixed decimals, E values line up from one move to the next
+    fn e(&self, e: f64) -> String {
+        let e = if self.relative_e { e - self.pos.e } else { e };
+
+        format!("{:.*}", self.flavor.decimals().1, e)
+    }
+
+    fn xyz(&self, to: &Vec4) -> String {
+        format!(
+            "X{} Y{} Z{}",
+            self.number(to.x),
+            self.number(to.y),
+            self.number(to.z)
+        )
+    }
+
+    // None for commands the flavor has no equivalent of
+    pub fn format(&mut self, command: &Command) -> Option<String> {
+        let flavor = self.flavor;
+        let line = match command {
+            Command::Move(to) => format!("G1 {} E{}", self.xyz(to), self.e(to.e)),
+            Command::Arc {
+                to,
+                center,
+                clockwise,
+            } => format!(
+                "{} {} I{} J{} E{}",
+                if *clockwise { "G2" } else { "G3" },
+                self.xyz(to),
+                self.number(center.0),
+                self.number(center.1),
+                self.e(to.e)
+            ),
+            Command::Extrude { e, feedrate } => {
+                format!("G1 F{} E{}", self.number(*feedrate), self.e(*e))
+            }
+            Command::Retract => flavor.retract(),
+            Command::Unretract => flavor.unretract(),
+            Command::ResetE => "G92 E0".to_string(),
+            Command::ExtrusionMode { relative } => {
+                self.relative_e = *relative;
+                flavor.extrusion_mode(*relative)
+            }
+            Command::Feedrate(feedrate) => format!("G1 F{}", self.number(*feedrate)),
+            Command::Temperature {
+                heater,
+                celsius,
+                wait,
+            } => flavor.temperature(*heater, *celsius, *wait),
+            Command::CoolDown { heater, celsius } => flavor.cool_down(*heater, *celsius),
+            Command::Fan(speed) => flavor.fan(*speed),
+            Command::Layer(layer) => flavor.comment(&format!("LAYER:{}", layer)),
+            Command::Role(Role::Perimeter) => flavor.comment("TYPE:WALL-OUTER"),
+            Command::Role(Role::Infill) => flavor.comment("TYPE:SKIN"),
+            Command::Progress { percent, minutes } => flavor.progress(*percent, *minutes)?,
+            Command::Pause => flavor.pause(),
+            Command::Level => flavor.level(),
+            Command::Comment(text) => flavor.comment(text),
+            Command::Raw(raw) => raw.clone(),
+        };
+
+        command.apply(&mut self.pos);
+        Some(line)
+    }
+}
+
+pub fn write<W: io::Write>(
+    out: &mut W,
+    commands: &[Command],
+    relative_e: bool,
+    flavor: &'static dyn GcodeFlavor,
+) -> io::Result<()> {
+    let mut writer = Writer::new(relative_e, flavor);
+
+    for line in commands.iter().filter_map(|command| writer.format(command)) {
+        writeln!(out, "{}", line)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::flavor::Marlin;
+    use super::super::gcode::Vec4;
+    use super::{write, Command};
+
+    fn to(x: f64, e: f64) -> Command {
+        Command::Move(Vec4 {
+            x,
+            y: 0.0,
+            z: 0.2,
+            e,
+        })
+    }
+
+    // E values of the moves, resets left out
+    fn e_values(commands: &[Command], relative: bool) -> Vec<f64> {
+        let mut text = vec![];
+
+        write(&mut text, commands, relative, &Marlin).unwrap();
+        String::from_utf8(text)
+            .unwrap()
+            .lines()
+            .filter(|line| line.starts_with("G1 "))
+            .filter_map(|line| line.split_whitespace().find(|word| word.starts_with('E')))
+            .map(|word| word[1..].parse().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn relative_e_adds_up() {
+        let commands = vec![
+            to(10.0, 0.5),
+            to(20.0, 1.25),
+            Command::Extrude {
+                e: 0.25,
+                feedrate: 2400.0,
+            },
+            Command::Extrude {
+                e: 1.25,
+                feedrate: 2400.0,
+            },
+            to(30.0, 2.0),
+            Command::ResetE,
+            to(40.0, 0.5),
+        ];
+        let absolute = e_values(&commands, false);
+        let relative = e_values(&commands, true);
+
+        assert_eq!(absolute, vec![0.5, 1.25, 0.25, 1.25, 2.0, 0.5]);
+        // Up to the reset, then from it
+        assert!((relative[..5].iter().sum::<f64>() - 2.0).abs() < 1e-9);
+        assert!((relative[5] - 0.5).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,274 @@
+use std::io;
+
+// Just enough XML for the documents of 3MF packages: elements, attributes and
+// text. Namespace prefixes are dropped, declarations and comments skipped.
+// Writers format their documents themselves, escaping text with `escape`.
+
+#[derive(Debug, Clone, Default)]
+pub struct Element {
+    pub name: String,
+    pub attributes: Vec<(String, String)>,
+    pub children: Vec<Element>,
+    pub text: String,
+}
+
+impl Element {
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn children<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Element> {
+        self.children.iter().filter(move |child| child.name == name)
+    }
+
+    pub fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|child| child.name == name)
+    }
+}
+
+fn local(name: &str) -> String {
+    name.rsplit(':').next().unwrap_or(name).to_string()
+}
+
+fn unescape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find('&') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let end = match rest.find(';') {
+            Some(end) => end,
+            None => break,
+        };
+        let entity = &rest[1..end];
+        let decoded = match entity {
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "amp" => Some('&'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => entity
+                .strip_prefix("#x")
+                .map(|hex| u32::from_str_radix(hex, 16))
+                .or_else(|| entity.strip_prefix('#').map(|decimal| decimal.parse()))
+                .and_then(|code| code.ok())
+                .and_then(char::from_u32),
+        };
+
+        match decoded {
+            Some(c) => {
+                out.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    out.push_str(rest);
+    out
+}
+
+// Text safe in attribute values and element content
+pub fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '&' => out.push_str("&amp;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            _ => out.push(c),
+        }
+    }
+
+    out
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, message: &str) -> io::Error {
+        let line = self.text[..self.position].matches('\n').count() + 1;
+
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("XML line {}: {}", line, message),
+        )
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.text[self.position..]
+    }
+
+    // Moves past `end`, which must come later
+    fn skip_past(&mut self, end: &str) -> io::Result<()> {
+        match self.rest().find(end) {
+            Some(i) => {
+                self.position += i + end.len();
+                Ok(())
+            }
+            None => Err(self.error(&format!("missing `{}`", end))),
+        }
+    }
+
+    fn skip_spaces(&mut self) {
+        let rest = self.rest();
+
+        self.position += rest.len() - rest.trim_start().len();
+    }
+
+    fn name(&mut self) -> io::Result<&'a str> {
+        let rest = self.rest();
+        let end = rest
+            .find(|c: char| c.is_whitespace() || c == '>' || c == '/' || c == '=')
+            .unwrap_or(rest.len());
+
+        if end == 0 {
+            return Err(self.error("expected a name"));
+        }
+
+        self.position += end;
+        Ok(&rest[..end])
+    }
+
+    // Start tag after its `<`, with its attributes, and whether it closes itself
+    fn start_tag(&mut self) -> io::Result<(Element, bool)> {
+        let mut element = Element {
+            name: local(self.name()?),
+            ..Element::default()
+        };
+
+        loop {
+            self.skip_spaces();
+
+            if self.rest().starts_with("/>") {
+                self.position += 2;
+                return Ok((element, true));
+            }
+
+            if self.rest().starts_with('>') {
+                self.position += 1;
+                return Ok((element, false));
+            }
+
+            let name = self.name()?;
+
+            self.skip_spaces();
+
+            if !self.rest().starts_with('=') {
+                return Err(self.error(&format!("expected `=` after attribute `{}`", name)));
+            }
+
+            self.position += 1;
+            self.skip_spaces();
+
+            let quote = match self.rest().chars().next() {
+                Some(quote @ ('"' | '\'')) => quote,
+                _ => return Err(self.error("expected a quoted attribute value")),
+            };
+            let value = self.rest()[1..]
+                .find(quote)
+                .map(|end| &self.rest()[1..end + 1])
+                .ok_or_else(|| self.error("unterminated attribute value"))?;
+
+            self.position += value.len() + 2;
+            element.attributes.push((local(name), unescape(value)));
+        }
+    }
+
+    fn document(&mut self) -> io::Result<Element> {
+        let mut stack: Vec<Element> = vec![];
+        let mut root = None;
+
+        while self.position < self.text.len() {
+            let rest = self.rest();
+
+            if !rest.starts_with('<') {
+                let end = rest.find('<').unwrap_or(rest.len());
+
+                // Indentation between elements isn't worth keeping
+                match stack.last_mut() {
+                    Some(current) if !rest[..end].trim().is_empty() => {
+                        current.text.push_str(&unescape(&rest[..end]))
+                    }
+                    _ => (),
+                }
+
+                self.position += end;
+            } else if rest.starts_with("<?") {
+                self.skip_past("?>")?;
+            } else if rest.starts_with("<!--") {
+                self.skip_past("-->")?;
+            } else if rest.starts_with("<![CDATA[") {
+                let end = rest
+                    .find("]]>")
+                    .ok_or_else(|| self.error("missing `]]>`"))?;
+
+                if let Some(current) = stack.last_mut() {
+                    current.text.push_str(&rest[9..end]);
+                }
+
+                self.position += end + 3;
+            } else if rest.starts_with("<!") {
+                self.skip_past(">")?;
+            } else if rest.starts_with("</") {
+                self.position += 2;
+
+                let name = local(self.name()?);
+
+                self.skip_past(">")?;
+
+                let element = stack
+                    .pop()
+                    .ok_or_else(|| self.error(&format!("unexpected `</{}>`", name)))?;
+
+                if element.name != name {
+                    return Err(
+                        self.error(&format!("`<{}>` closed by `</{}>`", element.name, name))
+                    );
+                }
+
+                match stack.last_mut() {
+                    Some(parent) => parent.children.push(element),
+                    None => root = Some(element),
+                }
+            } else {
+                self.position += 1;
+
+                let (element, closed) = self.start_tag()?;
+
+                if !closed {
+                    stack.push(element);
+                } else {
+                    match stack.last_mut() {
+                        Some(parent) => parent.children.push(element),
+                        None => root = Some(element),
+                    }
+                }
+            }
+        }
+
+        if let Some(element) = stack.last() {
+            return Err(self.error(&format!("`<{}>` never closed", element.name)));
+        }
+
+        root.ok_or_else(|| self.error("no root element"))
+    }
+}
+
+pub fn parse(text: &str) -> io::Result<Element> {
+    Parser { text, position: 0 }.document()
+}
@@ -0,0 +1,200 @@
+use std::io;
+
+use super::checksum::crc32;
+use super::deflate::{deflate, inflate};
+
+// Zip archives. Written with stored entries for what is compressed already or
+// too small to gain much, deflated ones for large text. Read with stored or
+// deflated entries, as other tools write them.
+
+const LOCAL_HEADER: u32 = 0x0403_4B50;
+const CENTRAL_HEADER: u32 = 0x0201_4B50;
+const END_OF_DIRECTORY: u32 = 0x0605_4B50;
+// Version 2.0 of the format, the first with folders
+const VERSION: u16 = 20;
+// 1980-01-01 00:00, the earliest MS-DOS date
+const DATE: u16 = 0x21;
+const STORED: u16 = 0;
+const DEFLATED: u16 = 8;
+
+#[derive(Debug, Default)]
+pub struct Writer {
+    bytes: Vec<u8>,
+    directory: Vec<u8>,
+    entries: u16,
+}
+
+impl Writer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, name: &str, data: &[u8]) {
+        self.entry(name, data, STORED, data);
+    }
+
+    pub fn add_deflated(&mut self, name: &str, data: &[u8]) {
+        self.entry(name, data, DEFLATED, &deflate(data));
+    }
+
+    // `content` is `data` as `method` packs it
+    fn entry(&mut self, name: &str, data: &[u8], method: u16, content: &[u8]) {
+        let offset = self.bytes.len() as u32;
+        let crc = crc32(data);
+
+        // Fields shared by the local and central headers, from the version needed on
+        let mut common = vec![];
+        common.extend(VERSION.to_le_bytes());
+        // No flags
+        common.extend(0u16.to_le_bytes());
+        common.extend(method.to_le_bytes());
+        common.extend(0u16.to_le_bytes());
+        common.extend(DATE.to_le_bytes());
+        common.extend(crc.to_le_bytes());
+        common.extend((content.len() as u32).to_le_bytes());
+        common.extend((data.len() as u32).to_le_bytes());
+        common.extend((name.len() as u16).to_le_bytes());
+        // No extra field
+        common.extend(0u16.to_le_bytes());
+
+        self.bytes.extend(LOCAL_HEADER.to_le_bytes());
+        self.bytes.extend(&common);
+        self.bytes.extend(name.as_bytes());
+        self.bytes.extend(content);
+
+        self.directory.extend(CENTRAL_HEADER.to_le_bytes());
+        self.directory.extend(VERSION.to_le_bytes());
+        self.directory.extend(&common);
+        // No comment, first disk, no attributes
+        self.directory.extend([0; 10]);
+        self.directory.extend(offset.to_le_bytes());
+        self.directory.extend(name.as_bytes());
+        self.entries += 1;
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        let offset = self.bytes.len() as u32;
+
+        self.bytes.extend(&self.directory);
+        self.bytes.extend(END_OF_DIRECTORY.to_le_bytes());
+        // Single disk
+        self.bytes.extend([0; 4]);
+        self.bytes.extend(self.entries.to_le_bytes());
+        self.bytes.extend(self.entries.to_le_bytes());
+        self.bytes
+            .extend((self.directory.len() as u32).to_le_bytes());
+        self.bytes.extend(offset.to_le_bytes());
+        // No comment
+        self.bytes.extend([0; 2]);
+        self.bytes
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> io::Result<u16> {
+    bytes
+        .get(offset..offset + 2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .ok_or_else(|| invalid("truncated zip archive"))
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> io::Result<u32> {
+    bytes
+        .get(offset..offset + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| invalid("truncated zip archive"))
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    name: String,
+    method: u16,
+    size: usize,
+    // Where its local header is
+    offset: usize,
+}
+
+// Files of an archive, found through its central directory
+#[derive(Debug)]
+pub struct Archive<'a> {
+    bytes: &'a [u8],
+    entries: Vec<Entry>,
+}
+
+impl<'a> Archive<'a> {
+    pub fn new(bytes: &'a [u8]) -> io::Result<Self> {
+        // The end record is last, possibly followed by a comment
+        let end = (0..bytes.len().saturating_sub(21))
+            .rev()
+            .find(|i| bytes[*i..].starts_with(&END_OF_DIRECTORY.to_le_bytes()))
+            .ok_or_else(|| invalid("not a zip archive"))?;
+        let count = u16_at(bytes, end + 10)? as usize;
+        let mut position = u32_at(bytes, end + 16)? as usize;
+        let mut entries = vec![];
+
+        for _ in 0..count {
+            if u32_at(bytes, position)? != CENTRAL_HEADER {
+                return Err(invalid("corrupt zip central directory"));
+            }
+
+            let name_length = u16_at(bytes, position + 28)? as usize;
+            let name = bytes
+                .get(position + 46..position + 46 + name_length)
+                .ok_or_else(|| invalid("truncated zip archive"))?;
+
+            entries.push(Entry {
+                name: String::from_utf8_lossy(name).to_string(),
+                method: u16_at(bytes, position + 10)?,
+                size: u32_at(bytes, position + 20)? as usize,
+                offset: u32_at(bytes, position + 42)? as usize,
+            });
+
+            position += 46
+                + name_length
+                + u16_at(bytes, position + 30)? as usize
+                + u16_at(bytes, position + 32)? as usize;
+        }
+
+        Ok(Archive { bytes, entries })
+    }
+
+    // Content of the file at `name`, a leading slash being optional
+    pub fn read(&self, name: &str) -> io::Result<Vec<u8>> {
+        let name = name.trim_start_matches('/');
+        let entry = self
+            .entries
+            .iter()
+            .find(|entry| entry.name == name)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("no {} in the archive", name),
+                )
+            })?;
+
+        if u32_at(self.bytes, entry.offset)? != LOCAL_HEADER {
+            return Err(invalid("corrupt zip local header"));
+        }
+
+        let start = entry.offset
+            + 30
+            + u16_at(self.bytes, entry.offset + 26)? as usize
+            + u16_at(self.bytes, entry.offset + 28)? as usize;
+        let data = self
+            .bytes
+            .get(start..start + entry.size)
+            .ok_or_else(|| invalid("truncated zip archive"))?;
+
+        match entry.method {
+            STORED => Ok(data.to_vec()),
+            DEFLATED => inflate(data),
+            method => Err(invalid(&format!(
+                "unsupported compression method {} for {}",
+                method, name
+            ))),
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use std::process::Command;
+
+// G-code of `pancake` run with `args`
+fn slice(args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_pancake"))
+        .args(args)
+        .output()
+        .expect("pancake runs");
+
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).expect("G-code is text")
+}
+
+#[test]
+fn layer_count() {
+    let gcode = slice(&["stl_files/cube.stl"]);
+    let markers = gcode.lines().filter(|l| l.starts_with(";LAYER:")).count();
+    let count: usize = gcode
+        .lines()
+        .find_map(|l| l.strip_prefix(";LAYER_COUNT:"))
+        .expect("layer count")
+        .parse()
+        .unwrap();
+
+    assert!(markers > 0);
+    assert_eq!(markers, count);
+}
+
+// X and Y of every move of `gcode`, with whether it extrudes. E values are
+// absolute, as pancake writes them by default.
+fn positions(gcode: &str) -> Vec<(f64, f64, bool)> {
+    let mut positions = vec![];
+    let mut last_e = 0.0;
+
+    for line in gcode.lines() {
+        let words: Vec<&str> = line.split(';').next().unwrap().split_whitespace().collect();
+
+        let value = |letter: char| {
+            words
+                .iter()
+                .skip(1)
+                .find(|word| word.starts_with(letter))
+                .map(|word| word[1..].parse::<f64>().unwrap())
+        };
+
+        match words.first() {
+            Some(&"G0") | Some(&"G1") => (),
+            Some(&"G92") => {
+                last_e = value('E').unwrap_or(last_e);
+                continue;
+            }
+            _ => continue,
+        }
+
+        let e = value('E').unwrap_or(last_e);
+
+        if let (Some(x), Some(y)) = (value('X'), value('Y')) {
+            positions.push((x, y, e > last_e));
+        }
+
+        last_e = e;
+    }
+
+    positions
+}
+
+#[test]
+fn transforms_stay_on_the_bed() {
+    // Around the middle of the default 205x205 bed
+    for transform in ["rotate(z,90deg)", "center()", "homothety(20), rotate(z,30deg), mirror(x)"].iter() {
+        let gcode = slice(&["stl_files/cube.stl", "-t", transform]);
+        let positions = positions(&gcode);
+
+        for (x, y, _) in positions.iter() {
+            assert!(
+                (0.0..=205.0).contains(x) && (0.0..=205.0).contains(y),
+                "{}: move to {} {}",
+                transform,
+                x,
+                y
+            );
+        }
+
+        let (min, max) = positions.iter().filter(|p| p.2).fold(
+            ((f64::INFINITY, f64::INFINITY), (f64::NEG_INFINITY, f64::NEG_INFINITY)),
+            |(min, max), (x, y, _)| ((min.0.min(*x), min.1.min(*y)), (max.0.max(*x), max.1.max(*y))),
+        );
+
+        assert!(((min.0 + max.0) / 2.0 - 102.5).abs() < 1.0, "{}", transform);
+        assert!(((min.1 + max.1) / 2.0 - 102.5).abs() < 1.0, "{}", transform);
+    }
+}